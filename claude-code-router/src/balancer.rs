@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{PoolMember, RouterConfig};
+
+/// Weight given to the newest observation in the latency and error moving averages
+const EWMA_ALPHA: f64 = 0.2;
+/// Lowest health factor, so a failing member still gets the odd request and can recover
+const MIN_HEALTH: f64 = 0.05;
+/// How long a conversation stays pinned to a pool member after its last request
+const STICKY_TTL: Duration = Duration::from_secs(3600);
+/// Upper bound on remembered conversations; expired pins are dropped first
+const MAX_STICKY: usize = 10_000;
+
+/// Observed behaviour of one pool member
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RouteStats {
+    /// Moving average of the time until the provider answered, in milliseconds
+    pub latency_ms: Option<f64>,
+    /// Moving average of failures, from 0 (healthy) to 1 (failing)
+    pub error_rate: f64,
+}
+
+#[derive(Debug)]
+struct Pin {
+    route: String,
+    last_used: Instant,
+}
+
+/// Spreads requests over the members of weighted model pools. Weights are scaled by
+/// each member's observed latency and error rate, and a conversation keeps using the
+/// member it started on while that member keeps answering.
+#[derive(Debug, Default)]
+pub struct Balancer {
+    pools: HashMap<String, Vec<PoolMember>>,
+    stats: Mutex<HashMap<String, RouteStats>>,
+    /// (pool name, conversation) -> pinned member route
+    pins: Mutex<HashMap<(String, String), Pin>>,
+}
+
+impl Balancer {
+    pub fn new(router: &RouterConfig) -> Self {
+        let pools = router
+            .pools
+            .iter()
+            .map(|(name, members)| {
+                let members = members
+                    .iter()
+                    .map(|m| PoolMember { route: m.route.trim().to_string(), weight: m.weight.max(0.0) })
+                    .filter(|m| !m.route.is_empty())
+                    .collect();
+                (name.clone(), members)
+            })
+            .collect();
+        Self { pools, ..Default::default() }
+    }
+
+    /// Replace pool names in `routes` by their members: the member chosen for this request
+    /// first, then the others by effective weight as fallbacks. Duplicates are dropped.
+    pub fn expand(&self, routes: Vec<String>, conversation: Option<&str>) -> Vec<String> {
+        let mut expanded: Vec<String> = Vec::new();
+        for route in routes {
+            let candidates = match self.pools.get(&route) {
+                Some(members) => self.order_pool(&route, members, conversation),
+                None => vec![route],
+            };
+            for candidate in candidates {
+                if !expanded.contains(&candidate) {
+                    expanded.push(candidate);
+                }
+            }
+        }
+        expanded
+    }
+
+    fn order_pool(&self, pool: &str, members: &[PoolMember], conversation: Option<&str>) -> Vec<String> {
+        if members.is_empty() {
+            return Vec::new();
+        }
+        let weights = self.effective_weights(members);
+
+        let pinned = conversation.and_then(|conversation| self.pinned(pool, conversation, members));
+        let first = match pinned {
+            Some(index) => index,
+            None => {
+                let index = weighted_pick(&weights).unwrap_or(0);
+                if let Some(conversation) = conversation {
+                    self.pin(pool, conversation, &members[index].route);
+                }
+                index
+            }
+        };
+
+        let mut rest: Vec<usize> = (0..members.len()).filter(|&i| i != first).collect();
+        rest.sort_by(|&a, &b| weights[b].total_cmp(&weights[a]));
+
+        log::debug!(
+            "Pool {} picked {} (effective weights {:?})",
+            pool, members[first].route, weights
+        );
+        std::iter::once(first).chain(rest).map(|i| members[i].route.clone()).collect()
+    }
+
+    /// Configured weights scaled by health (1 - error rate) and by speed relative to the
+    /// fastest measured member. Members without measurements keep their configured weight.
+    pub fn effective_weights(&self, members: &[PoolMember]) -> Vec<f64> {
+        let stats = self.stats.lock().unwrap();
+        let member_stats: Vec<RouteStats> = members
+            .iter()
+            .map(|m| stats.get(&m.route).copied().unwrap_or_default())
+            .collect();
+        let fastest = member_stats
+            .iter()
+            .filter_map(|s| s.latency_ms)
+            .fold(f64::INFINITY, f64::min);
+
+        members
+            .iter()
+            .zip(&member_stats)
+            .map(|(member, stats)| {
+                let health = (1.0 - stats.error_rate).max(MIN_HEALTH);
+                let speed = match stats.latency_ms {
+                    Some(latency) if latency > 0.0 && fastest.is_finite() => (fastest / latency).max(MIN_HEALTH),
+                    _ => 1.0,
+                };
+                member.weight * health * speed
+            })
+            .collect()
+    }
+
+    /// Record how a request to `route` went. Only pool members are tracked. On success
+    /// the conversation is pinned to `route`, so after a failover it stays on the member
+    /// that answered.
+    pub fn record(&self, route: &str, conversation: Option<&str>, latency: Duration, success: bool) {
+        let pools: Vec<&String> = self
+            .pools
+            .iter()
+            .filter(|(_, members)| members.iter().any(|m| m.route == route))
+            .map(|(name, _)| name)
+            .collect();
+        if pools.is_empty() {
+            return;
+        }
+
+        {
+            let mut stats = self.stats.lock().unwrap();
+            let entry = stats.entry(route.to_string()).or_default();
+            let failure = if success { 0.0 } else { 1.0 };
+            entry.error_rate += EWMA_ALPHA * (failure - entry.error_rate);
+            if success {
+                let latency = latency.as_secs_f64() * 1000.0;
+                entry.latency_ms = Some(match entry.latency_ms {
+                    Some(average) => average + EWMA_ALPHA * (latency - average),
+                    None => latency,
+                });
+            }
+        }
+
+        if let (true, Some(conversation)) = (success, conversation) {
+            for pool in pools {
+                self.pin(pool, conversation, route);
+            }
+        }
+    }
+
+    /// Current statistics of a route, if it was ever recorded
+    pub fn stats(&self, route: &str) -> Option<RouteStats> {
+        self.stats.lock().unwrap().get(route).copied()
+    }
+
+    fn pinned(&self, pool: &str, conversation: &str, members: &[PoolMember]) -> Option<usize> {
+        let mut pins = self.pins.lock().unwrap();
+        let pin = pins.get_mut(&(pool.to_string(), conversation.to_string()))?;
+        if pin.last_used.elapsed() > STICKY_TTL {
+            return None;
+        }
+        let index = members.iter().position(|m| m.route == pin.route)?;
+        pin.last_used = Instant::now();
+        Some(index)
+    }
+
+    fn pin(&self, pool: &str, conversation: &str, route: &str) {
+        let mut pins = self.pins.lock().unwrap();
+        if pins.len() >= MAX_STICKY {
+            pins.retain(|_, pin| pin.last_used.elapsed() <= STICKY_TTL);
+            if pins.len() >= MAX_STICKY {
+                // Still full of live conversations: forget the least recently used one
+                if let Some(oldest) = pins.iter().min_by_key(|(_, pin)| pin.last_used).map(|(key, _)| key.clone()) {
+                    pins.remove(&oldest);
+                }
+            }
+        }
+        pins.insert(
+            (pool.to_string(), conversation.to_string()),
+            Pin { route: route.to_string(), last_used: Instant::now() },
+        );
+    }
+}
+
+/// Index picked at random with probability proportional to its weight;
+/// None when no weight is positive
+fn weighted_pick(weights: &[f64]) -> Option<usize> {
+    let total: f64 = weights.iter().filter(|w| **w > 0.0).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let mut target = fastrand::f64() * total;
+    for (index, weight) in weights.iter().enumerate() {
+        if *weight <= 0.0 {
+            continue;
+        }
+        if target < *weight {
+            return Some(index);
+        }
+        target -= weight;
+    }
+    weights.iter().rposition(|w| *w > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn balancer() -> Balancer {
+        let router: RouterConfig = serde_json::from_value(json!({
+            "default": "kimi",
+            "pools": {
+                "kimi": [
+                    {"route": "groq,kimi-k2", "weight": 3},
+                    {"route": "openrouter,moonshotai/kimi-k2"},
+                    {"route": "backup,kimi-k2", "weight": 0}
+                ]
+            }
+        }))
+        .unwrap();
+        Balancer::new(&router)
+    }
+
+    #[test]
+    fn test_expand_follows_weights() {
+        let balancer = balancer();
+        let mut groq_first = 0;
+        for _ in 0..2000 {
+            let routes = balancer.expand(vec!["kimi".to_string(), "ollama,qwen".to_string()], None);
+            assert_eq!(routes.len(), 4);
+            // Zero-weight members are only ever fallbacks
+            assert_eq!(routes[2], "backup,kimi-k2");
+            assert_eq!(routes[3], "ollama,qwen");
+            if routes[0] == "groq,kimi-k2" {
+                groq_first += 1;
+            }
+        }
+        assert!((1300..1700).contains(&groq_first), "groq picked {} times", groq_first);
+    }
+
+    #[test]
+    fn test_weights_adapt_to_latency_and_errors() {
+        let balancer = balancer();
+        let members = &balancer.pools["kimi"];
+        assert_eq!(balancer.effective_weights(members), vec![3.0, 1.0, 0.0]);
+
+        balancer.record("groq,kimi-k2", None, Duration::from_millis(400), true);
+        balancer.record("openrouter,moonshotai/kimi-k2", None, Duration::from_millis(100), true);
+        let weights = balancer.effective_weights(members);
+        assert!((weights[0] - 0.75).abs() < 1e-9, "{:?}", weights);
+        assert!((weights[1] - 1.0).abs() < 1e-9, "{:?}", weights);
+
+        for _ in 0..30 {
+            balancer.record("openrouter,moonshotai/kimi-k2", None, Duration::ZERO, false);
+        }
+        let weights = balancer.effective_weights(members);
+        assert!(weights[1] < 0.1 && weights[1] > 0.0, "{:?}", weights);
+        assert!(balancer.stats("openrouter,moonshotai/kimi-k2").unwrap().error_rate > 0.9);
+
+        // Routes outside pools are not tracked
+        balancer.record("ollama,qwen", None, Duration::ZERO, false);
+        assert_eq!(balancer.stats("ollama,qwen"), None);
+    }
+
+    #[test]
+    fn test_conversation_sticks_to_member() {
+        let balancer = balancer();
+        let first = balancer.expand(vec!["kimi".to_string()], Some("session-1"))[0].clone();
+        for _ in 0..50 {
+            assert_eq!(balancer.expand(vec!["kimi".to_string()], Some("session-1"))[0], first);
+        }
+
+        // After a failover the conversation stays on the member that answered
+        let other = if first == "groq,kimi-k2" { "openrouter,moonshotai/kimi-k2" } else { "groq,kimi-k2" };
+        balancer.record(&first, Some("session-1"), Duration::ZERO, false);
+        balancer.record(other, Some("session-1"), Duration::from_millis(50), true);
+        assert_eq!(balancer.expand(vec!["kimi".to_string()], Some("session-1"))[0], other);
+    }
+
+    #[test]
+    fn test_weighted_pick() {
+        assert_eq!(weighted_pick(&[0.0, 0.0]), None);
+        assert_eq!(weighted_pick(&[0.0, 2.0]), Some(1));
+        assert_eq!(weighted_pick(&[]), None);
+    }
+}
@@ -4,7 +4,6 @@ use claude_code_router::server::Server;
 use std::process::{Command, Stdio};
 use std::fs;
 use std::env;
-use reqwest;
 use tokio::time::{sleep, Duration};
 
 #[derive(Parser)]
@@ -162,7 +161,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let log_file = "/tmp/ccr.log";
                 env::set_var("RUST_LOG", "debug");
                 
+                // The background server is meant to outlive this process, so it is never waited on
                 let mut cmd = Command::new("nohup");
+                #[allow(clippy::zombie_processes)]
                 cmd.arg(std::env::current_exe().unwrap())
                     .arg("start")
                     .stdout(Stdio::from(std::fs::File::create(log_file).expect("Failed to create log file")))
@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::proxy;
+use crate::secrets;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -14,15 +19,208 @@ pub struct Config {
     pub host: Option<String>,
     #[serde(rename = "LOG", default)]
     pub log: Option<bool>,
+    #[serde(rename = "RETRY", default)]
+    pub retry: Option<RetryConfig>,
+    #[serde(rename = "API_TIMEOUT_MS", default)]
+    pub api_timeout_ms: Option<u64>,
+    #[serde(rename = "CONNECT_TIMEOUT_MS", default)]
+    pub connect_timeout_ms: Option<u64>,
+    #[serde(rename = "STREAM_IDLE_TIMEOUT_MS", default)]
+    pub stream_idle_timeout_ms: Option<u64>,
+    #[serde(rename = "HEALTH", default)]
+    pub health: Option<HealthConfig>,
+    /// Outbound proxy for all providers: http, https, socks5 or socks5h, credentials in the URL
+    #[serde(rename = "PROXY_URL", default)]
+    pub proxy_url: Option<String>,
+    /// Comma-separated hosts, domains and CIDR ranges that bypass the proxy
+    #[serde(rename = "NO_PROXY", default)]
+    pub no_proxy: Option<String>,
+    /// Model name reported in responses; the requested model is echoed when unset
+    #[serde(rename = "DISPLAY_MODEL", default)]
+    pub display_model: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Provider {
     pub name: String,
+    /// Wire protocol spoken by the provider; `protocol` is accepted as an alias
+    #[serde(rename = "type", alias = "protocol", default)]
+    pub provider_type: ProviderType,
     pub api_base_url: String,
+    #[serde(default)]
     pub api_key: String,
+    /// Several keys with separate rate limits; used instead of `api_key` when non-empty
+    #[serde(default)]
+    pub api_keys: Option<Vec<String>>,
+    /// How `api_keys` are rotated (round-robin by default)
+    #[serde(default)]
+    pub key_rotation: Option<KeyRotation>,
     pub models: Vec<String>,
     pub transformer: Option<TransformerConfig>,
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+    /// Circuit breaker overrides for this provider
+    #[serde(default)]
+    pub health: Option<HealthConfig>,
+    /// Proxy for this provider only; an empty string connects directly
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    /// What to do with image inputs for OpenAI-compatible providers (native by default)
+    #[serde(default)]
+    pub images: Option<ImageSupport>,
+    /// Where the system prompt goes for OpenAI-compatible providers (a `system` message by default)
+    #[serde(default)]
+    pub system_prompt: Option<SystemPrompt>,
+    /// Rewriting of tool `input_schema`s for providers that reject some JSON Schema keywords
+    #[serde(default)]
+    pub schema: Option<SchemaProfile>,
+    /// How extended thinking is forwarded to OpenAI-compatible providers (not at all by default)
+    #[serde(default)]
+    pub reasoning: Option<ReasoningParam>,
+    /// Whether tool calls the model writes into its text are turned into tool_use blocks
+    #[serde(default)]
+    pub text_tool_calls: Option<TextToolCalls>,
+    #[serde(default)]
+    pub api_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub stream_idle_timeout_ms: Option<u64>,
+    /// Extra request settings per model name, e.g. Ollama's `num_ctx`, `keep_alive` or `format`
+    #[serde(default)]
+    pub model_options: Option<HashMap<String, serde_json::Value>>,
+    /// Azure `api-version` query parameter
+    #[serde(default)]
+    pub api_version: Option<String>,
+    /// Azure deployment name per model name; unmapped models use their own name
+    #[serde(default)]
+    pub deployments: Option<HashMap<String, String>>,
+    /// Why the provider cannot be used, e.g. an unset `$VAR` in its settings; set by
+    /// load_config() and reported when a request is routed to the provider
+    #[serde(skip)]
+    pub config_error: Option<String>,
+}
+
+/// Image input handling for OpenAI-compatible providers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageSupport {
+    /// Send images as `image_url` parts
+    #[default]
+    Native,
+    /// Text-only model: replace each image with a short text placeholder
+    Placeholder,
+    /// Text-only model: fail requests that contain images
+    Reject,
+}
+
+/// Handling of tool calls that models without native function calling write as text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextToolCalls {
+    /// Text stays text; only native `tool_calls` become tool_use blocks
+    #[default]
+    Ignore,
+    /// Parse `<tool_call>` tags, DeepSeek tool call tokens and fenced JSON calls of request tools
+    Extract,
+}
+
+/// How much a provider's tool `input_schema`s are rewritten before sending
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaProfile {
+    /// Send schemas exactly as Claude Code wrote them
+    #[default]
+    Passthrough,
+    /// Drop `$schema`-style meta keywords and inline `$ref`s
+    Openai,
+    /// Openai, plus no `additionalProperties`, `format` and similar keywords, `null`
+    /// unions flattened, `allOf` merged and `oneOf` turned into `anyOf` (Groq, OpenRouter)
+    Strict,
+    /// Strict, restricted to the keywords Gemini accepts, with `nullable` for null unions
+    Gemini,
+}
+
+/// Placement of the system prompt in OpenAI-compatible requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemPrompt {
+    /// Leading `role: system` message
+    #[default]
+    Message,
+    /// Prepended to the first user message, for models that reject the system role
+    MergeIntoUser,
+}
+
+/// Request field carrying Claude's `thinking` setting to an OpenAI-compatible provider
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningParam {
+    /// Drop the setting; the provider does not accept reasoning parameters
+    #[default]
+    None,
+    /// `reasoning_effort: "low" | "medium" | "high"` derived from `budget_tokens` (OpenAI, Groq, DeepSeek)
+    ReasoningEffort,
+    /// `reasoning: {"max_tokens": budget_tokens}` (OpenRouter)
+    Reasoning,
+}
+
+/// Order in which a provider's API keys are used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRotation {
+    /// Each request takes the next usable key
+    #[default]
+    RoundRobin,
+    /// Prefer the usable key whose last 429 is furthest in the past (never limited first)
+    LeastRecentlyLimited,
+}
+
+/// API flavour of a provider's endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderType {
+    /// OpenAI-compatible `/chat/completions` (the default)
+    #[default]
+    Openai,
+    /// Anthropic Messages API (`/v1/messages`), requests forwarded almost untouched
+    Anthropic,
+    /// Google Gemini `generateContent` / `streamGenerateContent`
+    Gemini,
+    /// Ollama native `/api/chat` with NDJSON streaming
+    Ollama,
+    /// Azure OpenAI: deployment URLs, `api-version` and `api-key` header auth
+    Azure,
+}
+
+/// Retry settings for transient upstream failures; unset fields fall back to
+/// the global `RETRY` block and then to the built-in defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetryConfig {
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+    #[serde(default)]
+    pub base_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub max_delay_ms: Option<u64>,
+}
+
+/// Circuit breaker and probe settings; unset fields fall back to the global
+/// `HEALTH` block and then to the built-in defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Consecutive failures (or slow responses) that open the circuit
+    #[serde(default)]
+    pub failure_threshold: Option<u32>,
+    /// How long an open circuit rejects requests before a trial request is let through
+    #[serde(default)]
+    pub open_ms: Option<u64>,
+    /// Responses slower than this count as failures
+    #[serde(default)]
+    pub slow_ms: Option<u64>,
+    /// Interval of the background probe of unhealthy providers; 0 disables probing
+    #[serde(default)]
+    pub probe_interval_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,17 +236,121 @@ pub enum TransformerUse {
     WithOptions(Vec<serde_json::Value>),
 }
 
+impl TransformerUse {
+    /// The transformer name, `None` for a malformed options array
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            TransformerUse::Simple(name) => Some(name),
+            TransformerUse::WithOptions(options) => options.first().and_then(|name| name.as_str()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RouterConfig {
-    pub default: String,
+    pub default: RouteTarget,
     #[serde(default)]
-    pub background: Option<String>,
+    pub background: Option<RouteTarget>,
     #[serde(default)]
-    pub think: Option<String>,
+    pub think: Option<RouteTarget>,
     #[serde(rename = "longContext", default)]
-    pub long_context: Option<String>,
+    pub long_context: Option<RouteTarget>,
     #[serde(rename = "webSearch", default)]
-    pub web_search: Option<String>,
+    pub web_search: Option<RouteTarget>,
+    /// Named model pools; a route entry equal to a pool name expands to its members
+    #[serde(default)]
+    pub pools: HashMap<String, Vec<PoolMember>>,
+}
+
+/// One backend of a model pool, e.g. the same open-weights model on another provider
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolMember {
+    /// "provider,model"
+    pub route: String,
+    /// Share of traffic relative to the other members, before latency and error adjustments
+    #[serde(default = "default_pool_weight")]
+    pub weight: f64,
+}
+
+fn default_pool_weight() -> f64 {
+    1.0
+}
+
+/// Effective timeouts for one provider: provider fields win over the global
+/// `API_TIMEOUT_MS` / `CONNECT_TIMEOUT_MS` / `STREAM_IDLE_TIMEOUT_MS` settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// Time allowed to establish the TCP/TLS connection
+    pub connect: Duration,
+    /// Time allowed for a non-streaming request including its body, or for a streamed
+    /// response to start; a stream that keeps sending chunks is not cut off
+    pub total: Duration,
+    /// Longest silence tolerated between two chunks of a streamed body
+    pub stream_idle: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(10),
+            // Same default Claude Code is launched with by `ccr code`
+            total: Duration::from_secs(600),
+            stream_idle: Duration::from_secs(300),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn for_provider(config: &Config, provider: &Provider) -> Self {
+        let defaults = Self::default();
+        let pick = |local: Option<u64>, global: Option<u64>, default: Duration| {
+            local.or(global).map(Duration::from_millis).unwrap_or(default)
+        };
+        Self {
+            connect: pick(provider.connect_timeout_ms, config.connect_timeout_ms, defaults.connect),
+            total: pick(provider.api_timeout_ms, config.api_timeout_ms, defaults.total),
+            stream_idle: pick(provider.stream_idle_timeout_ms, config.stream_idle_timeout_ms, defaults.stream_idle),
+        }
+    }
+}
+
+/// A scenario route: either a single "provider,model" string or an ordered
+/// list of them, tried in turn when a provider fails
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RouteTarget {
+    Single(String),
+    Chain(Vec<String>),
+}
+
+impl RouteTarget {
+    /// The configured routes in order, skipping empty entries
+    pub fn routes(&self) -> Vec<String> {
+        match self {
+            RouteTarget::Single(route) => vec![route.clone()],
+            RouteTarget::Chain(routes) => routes.clone(),
+        }
+        .into_iter()
+        .map(|route| route.trim().to_string())
+        .filter(|route| !route.is_empty())
+        .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes().is_empty()
+    }
+}
+
+impl Default for RouteTarget {
+    fn default() -> Self {
+        RouteTarget::Single(String::new())
+    }
+}
+
+impl From<&str> for RouteTarget {
+    fn from(route: &str) -> Self {
+        RouteTarget::Single(route.to_string())
+    }
 }
 
 pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
@@ -58,15 +360,24 @@ pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
         let default_config = Config {
             providers: vec![],
             router: RouterConfig {
-                default: "".to_string(),
+                default: RouteTarget::default(),
                 background: None,
                 think: None,
                 long_context: None,
                 web_search: None,
+                pools: HashMap::new(),
             },
             apikey: None,
             host: None,
             log: Some(false),
+            retry: None,
+            api_timeout_ms: None,
+            connect_timeout_ms: None,
+            stream_idle_timeout_ms: None,
+            health: None,
+            proxy_url: None,
+            no_proxy: None,
+            display_model: None,
         };
         
         save_config(&default_config)?;
@@ -74,7 +385,17 @@ pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
     }
     
     let config_content = fs::read_to_string(&config_path)?;
-    let config: Config = serde_json::from_str(&config_content)?;
+    let mut raw: serde_json::Value = serde_json::from_str(&config_content)?;
+    // Environment variables and file:/cmd: secrets are resolved in memory only;
+    // the file on disk keeps the references
+    let provider_errors = secrets::resolve_config(&mut raw)?;
+    let mut config: Config = serde_json::from_value(raw)?;
+    for (index, error) in provider_errors {
+        if let Some(provider) = config.providers.get_mut(index) {
+            provider.config_error = Some(error);
+        }
+    }
+    proxy::validate(&config)?;
     
     Ok(config)
 }
@@ -97,4 +418,31 @@ fn get_config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
     path.push(".claude-code-router");
     path.push("config.json");
     Ok(path)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeouts_resolution() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "slow", "api_base_url": "http://a", "api_key": "k", "models": [], "api_timeout_ms": 900000},
+                {"name": "plain", "api_base_url": "http://b", "api_key": "k", "models": []}
+            ],
+            "Router": {"default": "slow,m"},
+            "API_TIMEOUT_MS": 120000,
+            "STREAM_IDLE_TIMEOUT_MS": 30000
+        }))
+        .unwrap();
+
+        let slow = Timeouts::for_provider(&config, &config.providers[0]);
+        assert_eq!(slow.total, Duration::from_secs(900));
+        assert_eq!(slow.stream_idle, Duration::from_secs(30));
+        assert_eq!(slow.connect, Timeouts::default().connect);
+
+        let plain = Timeouts::for_provider(&config, &config.providers[1]);
+        assert_eq!(plain.total, Duration::from_secs(120));
+    }
+}
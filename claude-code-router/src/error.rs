@@ -0,0 +1,231 @@
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Typed failure of an upstream provider call.
+/// Carried inside `Box<dyn Error>` and recovered with `downcast_ref` where the
+/// caller needs to pick a status code or decide whether to retry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderError {
+    /// The provider answered with a non-success HTTP status
+    Http {
+        status: u16,
+        message: String,
+        retry_after: Option<String>,
+    },
+    /// The provider did not answer in time
+    Timeout(String),
+    /// The connection could not be established or was reset
+    Connection(String),
+    /// The route or provider configuration is unusable (a server-side fault)
+    Config(String),
+    /// The provider cannot serve this kind of request, e.g. images for a text-only model
+    Unsupported(String),
+    /// The provider answered with something we could not understand
+    InvalidResponse(String),
+    /// The provider's circuit is open after repeated failures; no request was sent
+    Unavailable(String),
+}
+
+impl ProviderError {
+    /// Build an HTTP error from an upstream status and body, extracting the
+    /// provider's own error message when the body is JSON
+    pub fn from_response(status: u16, body: &str, retry_after: Option<String>) -> Self {
+        let message = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|json| {
+                json.pointer("/error/message")
+                    .or_else(|| json.get("message"))
+                    .or_else(|| json.get("error").filter(|e| e.is_string()))
+                    .and_then(|m| m.as_str())
+                    .map(|m| m.to_string())
+            })
+            .unwrap_or_else(|| body.trim().to_string());
+
+        ProviderError::Http { status, message, retry_after }
+    }
+
+    /// HTTP status to report to our own client
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ProviderError::Http { status, .. } => match status {
+                400 | 401 | 403 | 404 | 413 | 429 => *status,
+                // Rejected payloads (e.g. unprocessable entity) are still invalid requests
+                402..=499 => 400,
+                503 | 529 => 529,
+                500..=599 => *status,
+                _ => 502,
+            },
+            ProviderError::Timeout(_) => 504,
+            ProviderError::Connection(_) => 502,
+            ProviderError::Config(_) => 500,
+            ProviderError::Unsupported(_) => 400,
+            ProviderError::InvalidResponse(_) => 502,
+            ProviderError::Unavailable(_) => 529,
+        }
+    }
+
+    /// HTTP status for the OpenAI dialect, which has no 529: overload is reported as 503
+    pub fn openai_status_code(&self) -> u16 {
+        match self.status_code() {
+            529 => 503,
+            status => status,
+        }
+    }
+
+    /// Anthropic error `type` matching `status_code()`
+    pub fn error_type(&self) -> &'static str {
+        match self.status_code() {
+            400 => "invalid_request_error",
+            401 => "authentication_error",
+            403 => "permission_error",
+            404 => "not_found_error",
+            413 => "request_too_large",
+            429 => "rate_limit_error",
+            504 => "timeout_error",
+            529 => "overloaded_error",
+            _ => "api_error",
+        }
+    }
+
+    /// Whether another attempt (on the same or a different provider) may succeed:
+    /// connection failures, timeouts, rate limits and server-side errors
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ProviderError::Http { status, .. } => *status == 429 || *status >= 500,
+            ProviderError::Timeout(_) | ProviderError::Connection(_) | ProviderError::Unavailable(_) => true,
+            ProviderError::Config(_) | ProviderError::Unsupported(_) | ProviderError::InvalidResponse(_) => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<&str> {
+        match self {
+            ProviderError::Http { retry_after, .. } => retry_after.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Http { status, message, .. } => write!(f, "HTTP {}: {}", status, message),
+            ProviderError::Timeout(message) => write!(f, "Request timed out: {}", message),
+            ProviderError::Connection(message) => write!(f, "Connection failed: {}", message),
+            ProviderError::Config(message) => write!(f, "{}", message),
+            ProviderError::Unsupported(message) => write!(f, "{}", message),
+            ProviderError::InvalidResponse(message) => write!(f, "Invalid provider response: {}", message),
+            ProviderError::Unavailable(message) => write!(f, "Provider unavailable: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<reqwest::Error> for ProviderError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ProviderError::Timeout(e.to_string())
+        } else if e.is_connect() || e.is_request() {
+            ProviderError::Connection(e.to_string())
+        } else if e.is_decode() || e.is_body() {
+            ProviderError::InvalidResponse(e.to_string())
+        } else {
+            ProviderError::Connection(e.to_string())
+        }
+    }
+}
+
+/// Anthropic error envelope: `{"type":"error","error":{"type":..,"message":..}}`
+pub fn anthropic_error_body(error_type: &str, message: &str) -> Value {
+    json!({
+        "type": "error",
+        "error": {"type": error_type, "message": message}
+    })
+}
+
+/// OpenAI error envelope: `{"error":{"message":..,"type":..,"code":..}}`
+pub fn openai_error_body(error_type: &str, message: &str, status: u16) -> Value {
+    json!({
+        "error": {"message": message, "type": error_type, "code": status}
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_mapping() {
+        let cases = [
+            (400, 400, "invalid_request_error"),
+            (401, 401, "authentication_error"),
+            (403, 403, "permission_error"),
+            (404, 404, "not_found_error"),
+            (413, 413, "request_too_large"),
+            (422, 400, "invalid_request_error"),
+            (429, 429, "rate_limit_error"),
+            (500, 500, "api_error"),
+            (502, 502, "api_error"),
+            (503, 529, "overloaded_error"),
+            (529, 529, "overloaded_error"),
+        ];
+        for (upstream, expected_status, expected_type) in cases {
+            let error = ProviderError::from_response(upstream, "oops", None);
+            assert_eq!(error.status_code(), expected_status, "upstream {}", upstream);
+            assert_eq!(error.error_type(), expected_type, "upstream {}", upstream);
+        }
+    }
+
+    #[test]
+    fn test_openai_status_mapping() {
+        assert_eq!(ProviderError::from_response(503, "", None).openai_status_code(), 503);
+        assert_eq!(ProviderError::from_response(529, "", None).openai_status_code(), 503);
+        assert_eq!(ProviderError::Unavailable("circuit open".into()).openai_status_code(), 503);
+        assert_eq!(ProviderError::from_response(429, "", None).openai_status_code(), 429);
+        assert_eq!(ProviderError::Timeout("slow".into()).openai_status_code(), 504);
+    }
+
+    #[test]
+    fn test_non_http_errors() {
+        assert_eq!(ProviderError::Timeout("slow".into()).status_code(), 504);
+        assert_eq!(ProviderError::Timeout("slow".into()).error_type(), "timeout_error");
+        assert_eq!(ProviderError::Connection("reset".into()).error_type(), "api_error");
+        assert_eq!(ProviderError::Config("no provider".into()).status_code(), 500);
+        assert_eq!(ProviderError::Config("no provider".into()).error_type(), "api_error");
+        assert_eq!(ProviderError::Unsupported("no images".into()).error_type(), "invalid_request_error");
+        assert_eq!(ProviderError::Unavailable("circuit open".into()).error_type(), "overloaded_error");
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        assert!(ProviderError::from_response(429, "", None).is_retryable());
+        assert!(ProviderError::from_response(503, "", None).is_retryable());
+        assert!(ProviderError::from_response(529, "", None).is_retryable());
+        assert!(ProviderError::Timeout("slow".into()).is_retryable());
+        assert!(ProviderError::Connection("reset".into()).is_retryable());
+        assert!(!ProviderError::from_response(400, "", None).is_retryable());
+        assert!(!ProviderError::from_response(401, "", None).is_retryable());
+        assert!(!ProviderError::Config("no provider".into()).is_retryable());
+    }
+
+    #[test]
+    fn test_upstream_message_extraction() {
+        let openai = ProviderError::from_response(429, r#"{"error":{"message":"Rate limit reached","type":"requests"}}"#, Some("7".into()));
+        assert_eq!(openai.to_string(), "HTTP 429: Rate limit reached");
+        assert_eq!(openai.retry_after(), Some("7"));
+
+        let plain = ProviderError::from_response(502, "Bad Gateway\n", None);
+        assert_eq!(plain.to_string(), "HTTP 502: Bad Gateway");
+    }
+
+    #[test]
+    fn test_envelopes() {
+        let body = anthropic_error_body("rate_limit_error", "slow down");
+        assert_eq!(body["type"], "error");
+        assert_eq!(body["error"]["type"], "rate_limit_error");
+        assert_eq!(body["error"]["message"], "slow down");
+
+        let body = openai_error_body("rate_limit_error", "slow down", 429);
+        assert_eq!(body["error"]["code"], 429);
+    }
+}
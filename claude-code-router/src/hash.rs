@@ -0,0 +1,10 @@
+/// 64-bit FNV-1a parameters
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a hash of `text`. Unlike the std hasher it is the same across restarts
+/// and builds, for values that have to be recognised later.
+pub fn fnv1a(text: &str) -> u64 {
+    text.bytes()
+        .fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME))
+}
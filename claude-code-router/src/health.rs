@@ -0,0 +1,397 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, HealthConfig, Provider};
+use crate::error::ProviderError;
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_OPEN_MS: u64 = 30_000;
+const DEFAULT_PROBE_INTERVAL_MS: u64 = 30_000;
+
+/// Effective circuit breaker settings for one provider: provider overrides win over the
+/// global `HEALTH` block, which wins over the built-in defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthPolicy {
+    pub failure_threshold: u32,
+    pub open_duration: Duration,
+    /// Responses slower than this count as failures; None disables the latency check
+    pub slow_threshold: Option<Duration>,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            open_duration: Duration::from_millis(DEFAULT_OPEN_MS),
+            slow_threshold: None,
+        }
+    }
+}
+
+impl HealthPolicy {
+    pub fn for_provider(config: &Config, provider: &Provider) -> Self {
+        let global = config.health.as_ref();
+        let local = provider.health.as_ref();
+        let pick = |field: fn(&HealthConfig) -> Option<u64>| {
+            local.and_then(field).or_else(|| global.and_then(field))
+        };
+
+        let defaults = Self::default();
+        Self {
+            failure_threshold: pick(|h| h.failure_threshold.map(u64::from))
+                .map(|v| v.max(1) as u32)
+                .unwrap_or(defaults.failure_threshold),
+            open_duration: pick(|h| h.open_ms).map(Duration::from_millis).unwrap_or(defaults.open_duration),
+            slow_threshold: pick(|h| h.slow_ms).map(Duration::from_millis),
+        }
+    }
+}
+
+/// Interval of the background probe, None when `HEALTH.probe_interval_ms` is 0
+pub fn probe_interval(config: &Config) -> Option<Duration> {
+    let ms = config
+        .health
+        .as_ref()
+        .and_then(|h| h.probe_interval_ms)
+        .unwrap_or(DEFAULT_PROBE_INTERVAL_MS);
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Requests flow normally
+    Closed,
+    /// Requests are rejected without contacting the provider
+    Open,
+    /// The cool-off has passed; a single trial request decides whether to close again
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct ProviderHealth {
+    policy: HealthPolicy,
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// When the half-open trial request was let through
+    trial_started: Option<Instant>,
+    latency_ms: Option<f64>,
+    requests: u64,
+    failures: u64,
+    last_error: Option<String>,
+}
+
+impl ProviderHealth {
+    fn new(policy: HealthPolicy) -> Self {
+        Self {
+            policy,
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            trial_started: None,
+            latency_ms: None,
+            requests: 0,
+            failures: 0,
+            last_error: None,
+        }
+    }
+
+    fn cooled_off(&self, now: Instant) -> bool {
+        self.opened_at.is_none_or(|opened| now.duration_since(opened) >= self.policy.open_duration)
+    }
+
+    /// A half-open trial that never reported back (e.g. the client went away) expires
+    /// after the open duration, so the circuit cannot stay stuck
+    fn trial_in_flight(&self, now: Instant) -> bool {
+        self.trial_started
+            .is_some_and(|started| now.duration_since(started) < self.policy.open_duration)
+    }
+
+    /// Whether a request would be let through right now
+    fn accepting(&self, now: Instant) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => self.cooled_off(now),
+            CircuitState::HalfOpen => !self.trial_in_flight(now),
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open;
+        self.opened_at = Some(now);
+        self.trial_started = None;
+    }
+
+    fn record_failure(&mut self, reason: String, now: Instant) -> bool {
+        self.failures += 1;
+        self.consecutive_failures += 1;
+        self.last_error = Some(reason);
+        let trip = match self.state {
+            CircuitState::HalfOpen => true,
+            CircuitState::Closed => self.consecutive_failures >= self.policy.failure_threshold,
+            CircuitState::Open => false,
+        };
+        if trip {
+            self.open(now);
+        }
+        trip
+    }
+}
+
+/// Circuit breaker state of every provider, shared by all clones of a ProviderClient
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+    providers: HashMap<String, Mutex<ProviderHealth>>,
+}
+
+impl HealthTracker {
+    pub fn new(config: &Config) -> Self {
+        let providers = config
+            .providers
+            .iter()
+            .map(|provider| {
+                let health = ProviderHealth::new(HealthPolicy::for_provider(config, provider));
+                (provider.name.clone(), Mutex::new(health))
+            })
+            .collect();
+        Self { providers }
+    }
+
+    /// Admit a request to `provider`, or fail fast with `ProviderError::Unavailable` while
+    /// its circuit is open. Once the cool-off has passed one trial request is admitted.
+    pub fn try_acquire(&self, provider: &Provider) -> Result<(), ProviderError> {
+        let Some(health) = self.providers.get(&provider.name) else {
+            return Ok(());
+        };
+        let mut health = health.lock().unwrap();
+        let now = Instant::now();
+        if !health.accepting(now) {
+            return Err(ProviderError::Unavailable(format!(
+                "circuit for provider {} is open after {} consecutive failures",
+                provider.name, health.consecutive_failures
+            )));
+        }
+        if health.state != CircuitState::Closed {
+            health.state = CircuitState::HalfOpen;
+            health.trial_started = Some(now);
+            log::info!("🩺 Circuit for provider {} is half-open, sending a trial request", provider.name);
+        }
+        health.requests += 1;
+        Ok(())
+    }
+
+    /// Whether routing should avoid `provider_name` because its circuit rejects requests
+    pub fn is_open(&self, provider_name: &str) -> bool {
+        self.providers
+            .get(provider_name)
+            .is_some_and(|health| !health.lock().unwrap().accepting(Instant::now()))
+    }
+
+    /// A response arrived; slow responses still count as failures
+    pub fn record_success(&self, provider: &Provider, latency: Duration) {
+        let Some(health) = self.providers.get(&provider.name) else {
+            return;
+        };
+        let mut health = health.lock().unwrap();
+        let now = Instant::now();
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        health.latency_ms = Some(match health.latency_ms {
+            Some(average) => average + 0.2 * (latency_ms - average),
+            None => latency_ms,
+        });
+
+        if let Some(slow) = health.policy.slow_threshold.filter(|slow| latency > *slow) {
+            let reason = format!("response took {}ms (slow threshold {}ms)", latency.as_millis(), slow.as_millis());
+            if health.record_failure(reason, now) {
+                log::warn!("🚧 Circuit for provider {} opened: responses are too slow", provider.name);
+            }
+            return;
+        }
+
+        if health.state != CircuitState::Closed {
+            log::info!("✅ Circuit for provider {} closed again", provider.name);
+        }
+        health.state = CircuitState::Closed;
+        health.consecutive_failures = 0;
+        health.opened_at = None;
+        health.trial_started = None;
+    }
+
+    /// A request failed. Only failures that point at the provider being down (connection
+    /// errors, timeouts, 5xx) count; others just end a half-open trial.
+    pub fn record_error(&self, provider: &Provider, error: &ProviderError) {
+        let Some(health) = self.providers.get(&provider.name) else {
+            return;
+        };
+        let mut health = health.lock().unwrap();
+        if !counts_as_failure(error) {
+            health.trial_started = None;
+            return;
+        }
+        if health.record_failure(error.to_string(), Instant::now()) {
+            log::warn!(
+                "🚧 Circuit for provider {} opened after {} consecutive failures: {}",
+                provider.name, health.consecutive_failures, error
+            );
+        }
+    }
+
+    /// Providers whose circuit is open and due for a trial request
+    pub fn due_for_probe(&self) -> Vec<String> {
+        let now = Instant::now();
+        let mut names: Vec<String> = self
+            .providers
+            .iter()
+            .filter(|(_, health)| {
+                let health = health.lock().unwrap();
+                health.state != CircuitState::Closed && health.accepting(now)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn state(&self, provider_name: &str) -> Option<CircuitState> {
+        self.providers.get(provider_name).map(|health| health.lock().unwrap().state)
+    }
+
+    /// Health of every provider for the admin endpoint
+    pub fn snapshot(&self) -> Value {
+        let now = Instant::now();
+        let mut providers: Vec<(&String, Value)> = self
+            .providers
+            .iter()
+            .map(|(name, health)| {
+                let health = health.lock().unwrap();
+                let reopens_in_ms = match health.state {
+                    CircuitState::Open => health
+                        .opened_at
+                        .map(|opened| health.policy.open_duration.saturating_sub(now.duration_since(opened)))
+                        .map(|remaining| remaining.as_millis() as u64),
+                    _ => None,
+                };
+                let status = json!({
+                    "state": health.state,
+                    "consecutive_failures": health.consecutive_failures,
+                    "requests": health.requests,
+                    "failures": health.failures,
+                    "latency_ms": health.latency_ms.map(|ms| ms.round() as u64),
+                    "last_error": health.last_error,
+                    "reopens_in_ms": reopens_in_ms
+                });
+                (name, status)
+            })
+            .collect();
+        providers.sort_by(|a, b| a.0.cmp(b.0));
+        Value::Object(providers.into_iter().map(|(name, status)| (name.clone(), status)).collect())
+    }
+}
+
+/// Failures that suggest the provider itself is unhealthy
+fn counts_as_failure(error: &ProviderError) -> bool {
+    match error {
+        ProviderError::Http { status, .. } => *status >= 500,
+        ProviderError::Timeout(_) | ProviderError::Connection(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(health: Value) -> Config {
+        serde_json::from_value(json!({
+            "Providers": [
+                {"name": "groq", "api_base_url": "http://groq", "api_key": "k", "models": ["m"], "health": {"failure_threshold": 2}},
+                {"name": "openrouter", "api_base_url": "http://openrouter", "api_key": "k", "models": ["m"]}
+            ],
+            "Router": {"default": "groq,m"},
+            "HEALTH": health
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_policy_resolution() {
+        let config = config(json!({"failure_threshold": 4, "slow_ms": 2000, "probe_interval_ms": 0}));
+        let groq = HealthPolicy::for_provider(&config, &config.providers[0]);
+        assert_eq!(groq.failure_threshold, 2);
+        assert_eq!(groq.slow_threshold, Some(Duration::from_secs(2)));
+        let openrouter = HealthPolicy::for_provider(&config, &config.providers[1]);
+        assert_eq!(openrouter.failure_threshold, 4);
+        assert_eq!(openrouter.open_duration, Duration::from_millis(DEFAULT_OPEN_MS));
+        assert_eq!(probe_interval(&config), None);
+        assert_eq!(probe_interval(&self::config(json!({}))), Some(Duration::from_millis(DEFAULT_PROBE_INTERVAL_MS)));
+    }
+
+    #[test]
+    fn test_circuit_opens_half_opens_and_closes() {
+        let config = config(json!({"open_ms": 30}));
+        let groq = &config.providers[0];
+        let tracker = HealthTracker::new(&config);
+        let down = ProviderError::Connection("refused".into());
+
+        tracker.record_error(groq, &ProviderError::from_response(400, "bad", None));
+        tracker.record_error(groq, &down);
+        assert_eq!(tracker.state("groq"), Some(CircuitState::Closed));
+        tracker.record_error(groq, &down);
+        assert_eq!(tracker.state("groq"), Some(CircuitState::Open));
+        assert!(tracker.due_for_probe().is_empty());
+
+        // After the cool-off one trial is admitted and a second caller is turned away
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(tracker.due_for_probe(), vec!["groq"]);
+        tracker.try_acquire(groq).unwrap();
+        assert_eq!(tracker.state("groq"), Some(CircuitState::HalfOpen));
+        assert!(matches!(tracker.try_acquire(groq), Err(ProviderError::Unavailable(_))));
+
+        // A failed trial reopens, a successful one closes
+        tracker.record_error(groq, &down);
+        assert_eq!(tracker.state("groq"), Some(CircuitState::Open));
+        std::thread::sleep(Duration::from_millis(40));
+        tracker.try_acquire(groq).unwrap();
+        tracker.record_success(groq, Duration::from_millis(20));
+        assert_eq!(tracker.state("groq"), Some(CircuitState::Closed));
+        assert!(tracker.due_for_probe().is_empty());
+    }
+
+    #[test]
+    fn test_open_circuit_rejects_until_cooled_off() {
+        let config = config(json!({"open_ms": 60_000}));
+        let groq = &config.providers[0];
+        let tracker = HealthTracker::new(&config);
+        for _ in 0..2 {
+            tracker.record_error(groq, &ProviderError::from_response(502, "bad gateway", None));
+        }
+        assert!(tracker.is_open("groq"));
+        assert!(!tracker.is_open("openrouter"));
+        let error = tracker.try_acquire(groq).unwrap_err();
+        assert!(error.to_string().contains("circuit for provider groq is open"));
+        assert!(tracker.due_for_probe().is_empty());
+
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot["groq"]["state"], "open");
+        assert_eq!(snapshot["groq"]["consecutive_failures"], 2);
+        assert!(snapshot["groq"]["reopens_in_ms"].as_u64().unwrap() > 0);
+        assert_eq!(snapshot["openrouter"]["state"], "closed");
+    }
+
+    #[test]
+    fn test_slow_responses_count_as_failures() {
+        let config = config(json!({"slow_ms": 100}));
+        let groq = &config.providers[0];
+        let tracker = HealthTracker::new(&config);
+        tracker.record_success(groq, Duration::from_millis(500));
+        tracker.record_success(groq, Duration::from_millis(50));
+        tracker.record_success(groq, Duration::from_millis(500));
+        assert_eq!(tracker.state("groq"), Some(CircuitState::Closed));
+        tracker.record_success(groq, Duration::from_millis(500));
+        assert_eq!(tracker.state("groq"), Some(CircuitState::Open));
+    }
+}
@@ -0,0 +1,315 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, KeyRotation, Provider};
+
+/// How long a key rests after a 429 when the provider does not say
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// A key handed out for one upstream attempt
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyLease {
+    pub index: usize,
+    pub key: String,
+}
+
+/// Result of an attempt made with a leased key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyOutcome {
+    Success,
+    /// 429, with the delay the provider asked for, if any
+    RateLimited(Option<Duration>),
+    /// 401: the key is skipped from now on
+    Unauthorized,
+    /// Any other failure; does not affect key selection
+    Failed,
+}
+
+#[derive(Debug, Default)]
+struct KeyState {
+    key: String,
+    cooldown_until: Option<Instant>,
+    last_limited: Option<Instant>,
+    disabled: bool,
+    requests: u64,
+    successes: u64,
+    rate_limited: u64,
+    unauthorized: u64,
+    errors: u64,
+}
+
+impl KeyState {
+    fn cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until.is_some_and(|until| until > now)
+    }
+}
+
+#[derive(Debug)]
+struct ProviderKeys {
+    rotation: KeyRotation,
+    next: usize,
+    keys: Vec<KeyState>,
+}
+
+impl ProviderKeys {
+    fn pick(&mut self, now: Instant) -> usize {
+        let count = self.keys.len();
+        // Candidates in round-robin order starting at `next`
+        let order: Vec<usize> = (0..count).map(|offset| (self.next + offset) % count).collect();
+
+        let usable: Vec<usize> = order
+            .iter()
+            .copied()
+            .filter(|&i| !self.keys[i].disabled && !self.keys[i].cooling_down(now))
+            .collect();
+
+        let chosen = match self.rotation {
+            KeyRotation::RoundRobin => usable.first().copied(),
+            // min_by_key keeps the first of equal keys, so ties fall back to round-robin order
+            KeyRotation::LeastRecentlyLimited => usable.iter().copied().min_by_key(|&i| self.keys[i].last_limited),
+        };
+
+        let chosen = chosen
+            // Everything enabled is cooling down: use the key that recovers first
+            .or_else(|| {
+                order
+                    .iter()
+                    .copied()
+                    .filter(|&i| !self.keys[i].disabled)
+                    .min_by_key(|&i| self.keys[i].cooldown_until)
+            })
+            // Every key was rejected; keep trying them rather than failing without a request
+            .unwrap_or(order[0]);
+
+        self.next = (chosen + 1) % count;
+        chosen
+    }
+}
+
+/// API keys of every provider with their rotation state and usage counters.
+/// Shared by all clones of a ProviderClient.
+#[derive(Debug, Default)]
+pub struct KeyPool {
+    providers: HashMap<String, Mutex<ProviderKeys>>,
+}
+
+impl KeyPool {
+    pub fn new(config: &Config) -> Self {
+        let providers = config
+            .providers
+            .iter()
+            .map(|provider| {
+                let keys = provider_keys(provider)
+                    .into_iter()
+                    .map(|key| KeyState { key, ..Default::default() })
+                    .collect();
+                let state = ProviderKeys {
+                    rotation: provider.key_rotation.unwrap_or_default(),
+                    next: 0,
+                    keys,
+                };
+                (provider.name.clone(), Mutex::new(state))
+            })
+            .collect();
+        Self { providers }
+    }
+
+    /// Pick the key for the next attempt against `provider`
+    pub fn acquire(&self, provider: &Provider) -> KeyLease {
+        let Some(state) = self.providers.get(&provider.name) else {
+            return KeyLease { index: 0, key: provider.api_key.clone() };
+        };
+        let mut state = state.lock().unwrap();
+        let index = state.pick(Instant::now());
+        let key_state = &mut state.keys[index];
+        key_state.requests += 1;
+        KeyLease { index, key: key_state.key.clone() }
+    }
+
+    /// Record how an attempt with a leased key went
+    pub fn report(&self, provider: &Provider, lease: &KeyLease, outcome: KeyOutcome) {
+        let Some(state) = self.providers.get(&provider.name) else {
+            return;
+        };
+        let mut state = state.lock().unwrap();
+        let Some(key_state) = state.keys.get_mut(lease.index) else {
+            return;
+        };
+        let now = Instant::now();
+        match outcome {
+            KeyOutcome::Success => key_state.successes += 1,
+            KeyOutcome::RateLimited(delay) => {
+                key_state.rate_limited += 1;
+                key_state.last_limited = Some(now);
+                key_state.cooldown_until = Some(now + delay.unwrap_or(DEFAULT_COOLDOWN));
+            }
+            KeyOutcome::Unauthorized => {
+                key_state.unauthorized += 1;
+                key_state.disabled = true;
+                log::warn!("🔑 Key {} of provider {} was rejected (401), skipping it", lease.index, provider.name);
+            }
+            KeyOutcome::Failed => key_state.errors += 1,
+        }
+    }
+
+    /// Number of keys configured for `provider`
+    pub fn key_count(&self, provider: &Provider) -> usize {
+        self.providers.get(&provider.name).map_or(1, |state| state.lock().unwrap().keys.len())
+    }
+
+    /// Whether `provider` has another key that is neither rejected nor cooling down
+    pub fn has_usable_key(&self, provider: &Provider) -> bool {
+        let Some(state) = self.providers.get(&provider.name) else {
+            return false;
+        };
+        let state = state.lock().unwrap();
+        let now = Instant::now();
+        state.keys.iter().any(|k| !k.disabled && !k.cooling_down(now))
+    }
+
+    /// Per-key counters for debugging, with the keys masked
+    pub fn usage(&self) -> Value {
+        let now = Instant::now();
+        let mut providers: Vec<(&String, Value)> = self
+            .providers
+            .iter()
+            .map(|(name, state)| {
+                let state = state.lock().unwrap();
+                let keys: Vec<Value> = state
+                    .keys
+                    .iter()
+                    .map(|k| {
+                        json!({
+                            "key": mask_key(&k.key),
+                            "requests": k.requests,
+                            "successes": k.successes,
+                            "rate_limited": k.rate_limited,
+                            "unauthorized": k.unauthorized,
+                            "errors": k.errors,
+                            "disabled": k.disabled,
+                            "cooldown_remaining_ms": k
+                                .cooldown_until
+                                .map(|until| until.saturating_duration_since(now).as_millis() as u64)
+                                .unwrap_or(0)
+                        })
+                    })
+                    .collect();
+                (name, json!(keys))
+            })
+            .collect();
+        providers.sort_by(|a, b| a.0.cmp(b.0));
+        Value::Object(providers.into_iter().map(|(name, keys)| (name.clone(), keys)).collect())
+    }
+}
+
+/// `api_keys` when given, otherwise the single `api_key` (possibly empty, e.g. for Ollama)
+fn provider_keys(provider: &Provider) -> Vec<String> {
+    match &provider.api_keys {
+        Some(keys) if !keys.is_empty() => keys.clone(),
+        _ => vec![provider.api_key.clone()],
+    }
+}
+
+/// Enough of a key to tell keys apart in logs and debug output
+pub fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}...{}", head, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rotation: &str) -> Config {
+        serde_json::from_value(json!({
+            "Providers": [{
+                "name": "groq",
+                "api_base_url": "http://groq",
+                "api_key": "",
+                "api_keys": ["gsk_key_one_1111", "gsk_key_two_2222", "gsk_key_three_3333"],
+                "key_rotation": rotation,
+                "models": []
+            }],
+            "Router": {"default": "groq,m"}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_round_robin_skips_limited_and_rejected_keys() {
+        let config = config("round_robin");
+        let provider = &config.providers[0];
+        let pool = KeyPool::new(&config);
+
+        let picks: Vec<usize> = (0..4).map(|_| pool.acquire(provider).index).collect();
+        assert_eq!(picks, vec![0, 1, 2, 0]);
+
+        let second = pool.acquire(provider);
+        assert_eq!(second.index, 1);
+        pool.report(provider, &second, KeyOutcome::RateLimited(None));
+        let third = pool.acquire(provider);
+        pool.report(provider, &third, KeyOutcome::Unauthorized);
+
+        assert_eq!(pool.acquire(provider).index, 0);
+        assert_eq!(pool.acquire(provider).index, 0);
+        assert!(pool.has_usable_key(provider));
+    }
+
+    #[test]
+    fn test_all_keys_cooling_down_uses_first_to_recover() {
+        let config = config("round_robin");
+        let provider = &config.providers[0];
+        let pool = KeyPool::new(&config);
+
+        for delay in [30, 5, 60] {
+            let lease = pool.acquire(provider);
+            pool.report(provider, &lease, KeyOutcome::RateLimited(Some(Duration::from_secs(delay))));
+        }
+        assert!(!pool.has_usable_key(provider));
+        assert_eq!(pool.acquire(provider).index, 1);
+    }
+
+    #[test]
+    fn test_least_recently_limited() {
+        let config = config("least_recently_limited");
+        let provider = &config.providers[0];
+        let pool = KeyPool::new(&config);
+
+        // Limit key 0 and key 1 briefly, key 0 first
+        for index in [0, 1] {
+            let lease = KeyLease { index, key: String::new() };
+            pool.report(provider, &lease, KeyOutcome::RateLimited(Some(Duration::ZERO)));
+        }
+        // Key 2 was never limited, then key 0 was limited longest ago
+        assert_eq!(pool.acquire(provider).index, 2);
+        assert_eq!(pool.acquire(provider).index, 2);
+        let lease = KeyLease { index: 2, key: String::new() };
+        pool.report(provider, &lease, KeyOutcome::RateLimited(Some(Duration::ZERO)));
+        assert_eq!(pool.acquire(provider).index, 0);
+    }
+
+    #[test]
+    fn test_usage_counters_are_masked() {
+        let config = config("round_robin");
+        let provider = &config.providers[0];
+        let pool = KeyPool::new(&config);
+
+        let lease = pool.acquire(provider);
+        pool.report(provider, &lease, KeyOutcome::Success);
+
+        let usage = pool.usage();
+        assert_eq!(usage["groq"][0]["key"], "gsk_...1111");
+        assert_eq!(usage["groq"][0]["requests"], 1);
+        assert_eq!(usage["groq"][0]["successes"], 1);
+        assert_eq!(usage["groq"][1]["requests"], 0);
+        assert!(!usage.to_string().contains("gsk_key_one_1111"));
+        assert_eq!(mask_key("short"), "*****");
+    }
+}
@@ -1,6 +1,21 @@
 pub mod config;
+pub mod error;
 pub mod server;
 pub mod router;
+pub mod balancer;
 pub mod provider;
+pub mod protocols;
+pub mod retry;
+pub mod keys;
+pub mod health;
+pub mod hash;
+pub mod proxy;
+pub mod secrets;
+pub mod schema;
+pub mod text_tool_calls;
+pub mod thinking;
+pub mod tool_args;
+pub mod tool_names;
 pub mod message_transformer;
+pub mod stream_transformer;
 pub mod transformers;
\ No newline at end of file
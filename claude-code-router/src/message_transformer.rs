@@ -1,6 +1,9 @@
 use serde_json::{Value, json, Map};
 use crate::router::{Message, ClaudeTool};
 
+/// Stands in for an image sent to a provider that only accepts text
+pub const IMAGE_PLACEHOLDER: &str = "[image omitted: this model does not accept images]";
+
 pub struct MessageTransformer;
 
 impl MessageTransformer {
@@ -10,13 +13,13 @@ impl MessageTransformer {
         for message in messages {
             match message.role.as_str() {
                 "user" => {
-                    let (text_content, tool_results) = Self::process_user_content(&message.content);
+                    let (user_content, tool_results) = Self::process_user_content(&message.content);
                     
-                    // Add user text content if any
-                    if !text_content.is_empty() {
+                    // Add user text and image content if any
+                    if !Self::is_empty_content(&user_content) {
                         openai_messages.push(json!({
                             "role": "user",
-                            "content": text_content
+                            "content": user_content
                         }));
                     }
                     
@@ -29,6 +32,18 @@ impl MessageTransformer {
                             "name": tool_name
                         }));
                     }
+
+                    // Tool messages only carry text, so screenshots returned by tools
+                    // follow them in a user message
+                    let tool_images = Self::extract_tool_result_images(&message.content);
+                    if !tool_images.is_empty() {
+                        let mut parts = vec![json!({"type": "text", "text": "Images returned by the tool calls above:"})];
+                        parts.extend(tool_images);
+                        openai_messages.push(json!({
+                            "role": "user",
+                            "content": parts
+                        }));
+                    }
                 }
                 "assistant" => {
                     let (text_content, tool_calls) = Self::process_assistant_content(&message.content);
@@ -56,6 +71,56 @@ impl MessageTransformer {
         openai_messages
     }
     
+    /// Leading OpenAI `system` message for a Claude system prompt. The array form is
+    /// joined into one string, which also drops `cache_control` markers.
+    pub fn system_message(system: &Value) -> Option<Value> {
+        let text = match system {
+            Value::String(text) => text.clone(),
+            Value::Array(blocks) => blocks
+                .iter()
+                .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+                .filter(|text| !text.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            _ => return None,
+        };
+        (!text.is_empty()).then(|| json!({"role": "system", "content": text}))
+    }
+
+    /// Move `system` messages into the first user message, for models that reject the
+    /// system role. The system text is prepended; without a user message one is added.
+    pub fn merge_system_into_first_user(messages: &mut Vec<Value>) {
+        let mut system_texts = Vec::new();
+        messages.retain(|message| {
+            if message.get("role").and_then(|r| r.as_str()) != Some("system") {
+                return true;
+            }
+            match message.get("content") {
+                Some(Value::String(text)) => system_texts.push(text.clone()),
+                Some(content) => system_texts.push(Self::extract_text_content(content)),
+                None => {}
+            }
+            false
+        });
+        system_texts.retain(|text| !text.is_empty());
+        if system_texts.is_empty() {
+            return;
+        }
+        let system = system_texts.join("\n\n");
+
+        let first_user = messages
+            .iter_mut()
+            .find(|message| message.get("role").and_then(|r| r.as_str()) == Some("user"));
+        match first_user {
+            Some(message) => match message.get_mut("content") {
+                Some(Value::String(text)) => *text = format!("{}\n\n{}", system, text),
+                Some(Value::Array(parts)) => parts.insert(0, json!({"type": "text", "text": system})),
+                _ => message["content"] = Value::String(system),
+            },
+            None => messages.insert(0, json!({"role": "user", "content": system})),
+        }
+    }
+
     pub fn transform_tools_to_openai(tools: &[ClaudeTool]) -> Vec<Value> {
         tools.iter().map(|tool| {
             let description = if tool.description.is_empty() {
@@ -69,16 +134,207 @@ impl MessageTransformer {
                 "function": {
                     "name": tool.name,
                     "description": description,
-                    "parameters": tool.input_schema.clone()
+                    "parameters": if tool.input_schema.is_null() {
+                        json!({"type": "object"})
+                    } else {
+                        tool.input_schema.clone()
+                    }
                 }
             })
         }).collect()
     }
     
-    fn process_user_content(content: &Value) -> (String, Vec<(String, String, String)>) {
-        let text = Self::extract_text_content(content);
+    /// OpenAI `tool_choice` for a Claude one: auto -> "auto", any -> "required",
+    /// none -> "none", tool -> the named function
+    pub fn tool_choice_to_openai(tool_choice: &Value) -> Option<Value> {
+        match tool_choice.get("type").and_then(|t| t.as_str())? {
+            "auto" => Some(json!("auto")),
+            "any" => Some(json!("required")),
+            "none" => Some(json!("none")),
+            "tool" => {
+                let name = tool_choice.get("name").and_then(|n| n.as_str())?;
+                Some(json!({"type": "function", "function": {"name": name}}))
+            }
+            other => {
+                log::warn!("Unsupported tool_choice type: {}", other);
+                None
+            }
+        }
+    }
+
+    /// Convert OpenAI chat messages into Claude-style messages (used for routing decisions
+    /// on requests that arrive through the OpenAI-compatible endpoint)
+    pub fn transform_messages_from_openai(messages: &[Value]) -> Vec<Message> {
+        messages.iter().map(|message| {
+            let role = message.get("role").and_then(|r| r.as_str()).unwrap_or("user");
+            let mut blocks = Vec::new();
+
+            match message.get("content") {
+                Some(Value::String(text)) if !text.is_empty() => {
+                    blocks.push(json!({"type": "text", "text": text}));
+                }
+                Some(Value::Array(parts)) => {
+                    for part in parts {
+                        match part.get("type").and_then(|t| t.as_str()) {
+                            Some("text") => blocks.push(json!({"type": "text", "text": part.get("text").cloned().unwrap_or_default()})),
+                            Some("image_url") => blocks.push(json!({"type": "image", "source": {"type": "url", "url": part["image_url"]["url"].clone()}})),
+                            _ => log::warn!("Unsupported OpenAI content part: {:?}", part),
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(tool_calls) = message.get("tool_calls").and_then(|t| t.as_array()) {
+                for tool_call in tool_calls {
+                    let arguments = tool_call["function"]["arguments"].as_str().unwrap_or("{}");
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": tool_call.get("id").cloned().unwrap_or_default(),
+                        "name": tool_call["function"]["name"].clone(),
+                        "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| Value::String(arguments.to_string()))
+                    }));
+                }
+            }
+
+            if role == "tool" {
+                return Message {
+                    role: "user".to_string(),
+                    content: json!([{
+                        "type": "tool_result",
+                        "tool_use_id": message.get("tool_call_id").cloned().unwrap_or_default(),
+                        "content": blocks
+                    }]),
+                };
+            }
+
+            Message {
+                role: role.to_string(),
+                content: Value::Array(blocks),
+            }
+        }).collect()
+    }
+    
+    /// Convert OpenAI function tools back into ClaudeTool format
+    pub fn transform_tools_from_openai(tools: &[Value]) -> Vec<ClaudeTool> {
+        tools.iter().filter_map(|tool| {
+            let function = tool.get("function")?;
+            Some(ClaudeTool {
+                name: function.get("name")?.as_str()?.to_string(),
+                description: function.get("description").and_then(|d| d.as_str()).unwrap_or("").to_string(),
+                input_schema: function.get("parameters").cloned().unwrap_or_else(|| json!({"type": "object"})),
+            })
+        }).collect()
+    }
+    
+    fn process_user_content(content: &Value) -> (Value, Vec<(String, String, String)>) {
+        let user_content = Self::extract_user_content(content);
         let tool_results = Self::extract_tool_results(content);
-        (text, tool_results)
+        (user_content, tool_results)
+    }
+
+    /// Text of a user message as a string, or an OpenAI multi-part array with
+    /// `image_url` parts in block order when it contains images
+    fn extract_user_content(content: &Value) -> Value {
+        let Value::Array(blocks) = content else {
+            return Value::String(Self::extract_text_content(content));
+        };
+        if !blocks.iter().any(|block| block.get("type").and_then(|t| t.as_str()) == Some("image")) {
+            return Value::String(Self::extract_text_content(content));
+        }
+
+        let parts: Vec<Value> = blocks
+            .iter()
+            .filter_map(|block| match block.get("type").and_then(|t| t.as_str()) {
+                Some("text") => block
+                    .get("text")
+                    .and_then(|t| t.as_str())
+                    .map(|text| json!({"type": "text", "text": text})),
+                Some("image") => Self::image_part(block),
+                _ => None,
+            })
+            .collect();
+        Value::Array(parts)
+    }
+
+    fn is_empty_content(content: &Value) -> bool {
+        match content {
+            Value::String(text) => text.is_empty(),
+            Value::Array(parts) => parts.is_empty(),
+            _ => true,
+        }
+    }
+
+    /// OpenAI `image_url` part for a Claude image block: base64 sources become data URLs,
+    /// URL sources are passed through
+    fn image_part(block: &Value) -> Option<Value> {
+        let source = block.get("source")?;
+        let url = match source.get("type").and_then(|t| t.as_str()) {
+            Some("base64") => format!(
+                "data:{};base64,{}",
+                source.get("media_type").and_then(|m| m.as_str()).unwrap_or("image/png"),
+                source.get("data").and_then(|d| d.as_str())?
+            ),
+            Some("url") => source.get("url").and_then(|u| u.as_str())?.to_string(),
+            other => {
+                log::warn!("Unsupported image source type: {:?}", other);
+                return None;
+            }
+        };
+        Some(json!({"type": "image_url", "image_url": {"url": url}}))
+    }
+
+    /// `image_url` parts for images inside tool_result content
+    fn extract_tool_result_images(content: &Value) -> Vec<Value> {
+        let Value::Array(blocks) = content else {
+            return Vec::new();
+        };
+        blocks
+            .iter()
+            .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_result"))
+            .filter_map(|block| block.get("content").and_then(|c| c.as_array()))
+            .flatten()
+            .filter(|part| part.get("type").and_then(|t| t.as_str()) == Some("image"))
+            .filter_map(Self::image_part)
+            .collect()
+    }
+
+    /// Number of `image_url` parts in OpenAI messages
+    pub fn count_images(messages: &[Value]) -> usize {
+        messages
+            .iter()
+            .filter_map(|message| message.get("content").and_then(|c| c.as_array()))
+            .flatten()
+            .filter(|part| part.get("type").and_then(|t| t.as_str()) == Some("image_url"))
+            .count()
+    }
+
+    /// Replace `image_url` parts with IMAGE_PLACEHOLDER for text-only providers. Messages that
+    /// are left with text parts only are collapsed to a plain string.
+    pub fn replace_images_with_placeholder(messages: &mut [Value]) {
+        for message in messages.iter_mut() {
+            let Some(parts) = message.get_mut("content").and_then(|c| c.as_array_mut()) else {
+                continue;
+            };
+            if !parts.iter().any(|part| part.get("type").and_then(|t| t.as_str()) == Some("image_url")) {
+                continue;
+            }
+            for part in parts.iter_mut() {
+                if part.get("type").and_then(|t| t.as_str()) == Some("image_url") {
+                    *part = json!({"type": "text", "text": IMAGE_PLACEHOLDER});
+                }
+            }
+            let texts: Option<Vec<&str>> = parts
+                .iter()
+                .map(|part| match part.get("type").and_then(|t| t.as_str()) {
+                    Some("text") => part.get("text").and_then(|t| t.as_str()),
+                    _ => None,
+                })
+                .collect();
+            if let Some(texts) = texts {
+                message["content"] = Value::String(texts.join("\n"));
+            }
+        }
     }
     
     fn process_assistant_content(content: &Value) -> (String, Vec<Value>) {
@@ -119,7 +375,7 @@ impl MessageTransformer {
                     if map.get("type").and_then(|t| t.as_str()) == Some("tool_use") {
                         let id = map.get("id")
                             .and_then(|v| v.as_str())
-                            .unwrap_or_else(|| "call_000000000000000000000000");
+                            .unwrap_or("call_000000000000000000000000");
                         
                         if let Some(name) = map.get("name").and_then(|n| n.as_str()) {
                             let empty_object = Value::Object(Map::new());
@@ -155,7 +411,7 @@ impl MessageTransformer {
                             .to_string();
                             
                         let content = map.get("content")
-                            .map(|c| Self::extract_text_content(c))
+                            .map(Self::extract_text_content)
                             .unwrap_or_default();
                             
                         let name = map.get("name")
@@ -243,6 +499,109 @@ mod tests {
         assert_eq!(result[1]["tool_call_id"], "toolu_123");
     }
     
+    #[test]
+    fn test_image_blocks_become_image_url_parts() {
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: json!([
+                {"type": "text", "text": "What is in this screenshot?"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/jpeg", "data": "/9j/AAAA"}},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/chart.png"}}
+            ])
+        }];
+
+        let result = MessageTransformer::transform_messages_to_openai(&messages);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0]["content"], json!([
+            {"type": "text", "text": "What is in this screenshot?"},
+            {"type": "image_url", "image_url": {"url": "data:image/jpeg;base64,/9j/AAAA"}},
+            {"type": "image_url", "image_url": {"url": "https://example.com/chart.png"}}
+        ]));
+    }
+
+    #[test]
+    fn test_tool_result_images_follow_tool_messages() {
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: json!([{
+                "type": "tool_result",
+                "tool_use_id": "toolu_1",
+                "content": [
+                    {"type": "text", "text": "Captured the page"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0"}}
+                ]
+            }])
+        }];
+
+        let result = MessageTransformer::transform_messages_to_openai(&messages);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0]["role"], "tool");
+        assert_eq!(result[0]["content"], "Captured the page");
+        assert_eq!(result[1]["role"], "user");
+        assert_eq!(result[1]["content"][1]["image_url"]["url"], "data:image/png;base64,iVBORw0");
+        assert_eq!(MessageTransformer::count_images(&result), 1);
+    }
+
+    #[test]
+    fn test_images_replaced_for_text_only_providers() {
+        let mut messages = vec![
+            json!({"role": "user", "content": [
+                {"type": "text", "text": "Describe"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}}
+            ]}),
+            json!({"role": "assistant", "content": "Sure"}),
+        ];
+        MessageTransformer::replace_images_with_placeholder(&mut messages);
+        assert_eq!(messages[0]["content"], format!("Describe\n{}", IMAGE_PLACEHOLDER));
+        assert_eq!(messages[1]["content"], "Sure");
+        assert_eq!(MessageTransformer::count_images(&messages), 0);
+    }
+
+    #[test]
+    fn test_system_prompt_becomes_system_message() {
+        assert_eq!(
+            MessageTransformer::system_message(&json!("Be brief.")),
+            Some(json!({"role": "system", "content": "Be brief."}))
+        );
+        let blocks = json!([
+            {"type": "text", "text": "You are Claude Code."},
+            {"type": "text", "text": "Use tools.", "cache_control": {"type": "ephemeral"}}
+        ]);
+        assert_eq!(
+            MessageTransformer::system_message(&blocks),
+            Some(json!({"role": "system", "content": "You are Claude Code.\n\nUse tools."}))
+        );
+        assert_eq!(MessageTransformer::system_message(&json!("")), None);
+        assert_eq!(MessageTransformer::system_message(&json!([])), None);
+    }
+
+    #[test]
+    fn test_system_merged_into_first_user_message() {
+        let mut messages = vec![
+            json!({"role": "system", "content": "Be brief."}),
+            json!({"role": "user", "content": "Hi"}),
+            json!({"role": "assistant", "content": "Hello"}),
+            json!({"role": "user", "content": "Bye"}),
+        ];
+        MessageTransformer::merge_system_into_first_user(&mut messages);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0], json!({"role": "user", "content": "Be brief.\n\nHi"}));
+        assert_eq!(messages[2]["content"], "Bye");
+
+        let mut messages = vec![
+            json!({"role": "system", "content": [{"type": "text", "text": "Be brief."}]}),
+            json!({"role": "user", "content": [{"type": "image_url", "image_url": {"url": "https://x/y.png"}}]}),
+        ];
+        MessageTransformer::merge_system_into_first_user(&mut messages);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["content"][0], json!({"type": "text", "text": "Be brief."}));
+        assert_eq!(messages[0]["content"][1]["type"], "image_url");
+
+        let mut messages = vec![json!({"role": "system", "content": "Be brief."})];
+        MessageTransformer::merge_system_into_first_user(&mut messages);
+        assert_eq!(messages, vec![json!({"role": "user", "content": "Be brief."})]);
+    }
+
     #[test]
     fn test_tools_transformation() {
         let tools = vec![
@@ -265,6 +624,22 @@ mod tests {
         assert_eq!(result[0]["function"]["parameters"]["type"], "object");
     }
     
+    #[test]
+    fn test_tool_choice_conversion() {
+        assert_eq!(MessageTransformer::tool_choice_to_openai(&json!({"type": "auto"})), Some(json!("auto")));
+        assert_eq!(
+            MessageTransformer::tool_choice_to_openai(&json!({"type": "any", "disable_parallel_tool_use": true})),
+            Some(json!("required"))
+        );
+        assert_eq!(MessageTransformer::tool_choice_to_openai(&json!({"type": "none"})), Some(json!("none")));
+        assert_eq!(
+            MessageTransformer::tool_choice_to_openai(&json!({"type": "tool", "name": "get_weather"})),
+            Some(json!({"type": "function", "function": {"name": "get_weather"}}))
+        );
+        assert_eq!(MessageTransformer::tool_choice_to_openai(&json!({"type": "tool"})), None);
+        assert_eq!(MessageTransformer::tool_choice_to_openai(&json!("auto")), None);
+    }
+
     #[test]
     fn test_empty_description() {
         let tools = vec![
@@ -278,4 +653,39 @@ mod tests {
         let result = MessageTransformer::transform_tools_to_openai(&tools);
         assert_eq!(result[0]["function"]["description"], "");
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_openai_messages_to_claude() {
+        let messages = vec![
+            json!({"role": "system", "content": "Be brief"}),
+            json!({"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_1", "type": "function", "function": {"name": "search", "arguments": "{\"q\":\"rust\"}"}}
+            ]}),
+            json!({"role": "tool", "tool_call_id": "call_1", "content": "found it"}),
+        ];
+        
+        let result = MessageTransformer::transform_messages_from_openai(&messages);
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].role, "system");
+        assert_eq!(result[0].content[0]["text"], "Be brief");
+        assert_eq!(result[1].content[0]["type"], "tool_use");
+        assert_eq!(result[1].content[0]["input"]["q"], "rust");
+        assert_eq!(result[2].role, "user");
+        assert_eq!(result[2].content[0]["type"], "tool_result");
+        assert_eq!(result[2].content[0]["tool_use_id"], "call_1");
+    }
+    
+    #[test]
+    fn test_openai_tools_to_claude() {
+        let tools = vec![json!({
+            "type": "function",
+            "function": {"name": "search", "parameters": {"type": "object"}}
+        })];
+        
+        let result = MessageTransformer::transform_tools_from_openai(&tools);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].name, "search");
+        assert_eq!(result[0].description, "");
+        assert_eq!(result[0].input_schema["type"], "object");
+    }
+}
@@ -0,0 +1,174 @@
+use serde_json::{json, Value};
+
+use crate::config::Provider;
+use crate::server::ClaudeRequest;
+use crate::stream_transformer::SseEvent;
+use crate::thinking;
+
+/// Value sent in the `anthropic-version` header
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Messages endpoint of an Anthropic-compatible provider. The base URL may be the
+/// host root, end in `/v1`, or already be the full `/v1/messages` URL.
+pub fn messages_url(provider: &Provider) -> String {
+    let base = provider.api_base_url.trim_end_matches('/');
+    if base.ends_with("/messages") {
+        base.to_string()
+    } else if base.ends_with("/v1") {
+        format!("{}/messages", base)
+    } else {
+        format!("{}/v1/messages", base)
+    }
+}
+
+/// The Claude request as sent by the client, with only the model replaced
+/// by the routed one and `stream` set explicitly. Thinking blocks another provider
+/// produced carry a router signature Anthropic would reject, so they are dropped.
+pub fn build_body(claude_req: &ClaudeRequest, model: &str, stream: bool) -> Result<Value, serde_json::Error> {
+    let mut body = serde_json::to_value(claude_req)?;
+    body["model"] = json!(model);
+    body["stream"] = json!(stream);
+    if let Some(messages) = body["messages"].as_array_mut() {
+        messages.iter_mut().for_each(strip_router_thinking);
+    }
+    Ok(body)
+}
+
+/// Remove thinking blocks signed by `thinking::signature()`. A message that had nothing
+/// else keeps the reasoning as plain text so it does not become empty.
+fn strip_router_thinking(message: &mut Value) {
+    let Some(content) = message.get_mut("content").and_then(|c| c.as_array_mut()) else {
+        return;
+    };
+    let is_router_thinking = |block: &Value| {
+        block["type"] == "thinking"
+            && block["signature"].as_str().is_some_and(thinking::is_router_signature)
+    };
+    if !content.iter().any(is_router_thinking) {
+        return;
+    }
+    if content.iter().all(is_router_thinking) {
+        let text = content
+            .iter()
+            .filter_map(|block| block["thinking"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        *content = vec![json!({"type": "text", "text": text})];
+    } else {
+        content.retain(|block| !is_router_thinking(block));
+    }
+}
+
+/// Report `model` as the response's model, like converted responses do
+pub fn set_model(response: &mut Value, model: &str) {
+    if let Some(response) = response.as_object_mut() {
+        response.insert("model".to_string(), json!(model));
+    }
+}
+
+/// Re-frame an upstream SSE event for the client. The payload is untouched except for the
+/// model of `message_start`, which becomes `model`.
+pub fn format_passthrough_event(sse_event: &SseEvent, model: &str) -> String {
+    let mut data = sse_event.data.clone();
+    if data.contains("\"message_start\"") {
+        if let Ok(mut event) = serde_json::from_str::<Value>(&data) {
+            if event["type"] == "message_start" {
+                set_model(&mut event["message"], model);
+                data = event.to_string();
+            }
+        }
+    }
+    match &sse_event.event {
+        Some(event) => format!("event: {}\ndata: {}\n\n", event, data),
+        None => format!("data: {}\n\n", data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderType;
+
+    fn provider(base: &str) -> Provider {
+        serde_json::from_value(json!({
+            "name": "native",
+            "type": "anthropic",
+            "api_base_url": base,
+            "api_key": "k",
+            "models": []
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_messages_url() {
+        assert_eq!(provider("https://gw.example.com").provider_type, ProviderType::Anthropic);
+        assert_eq!(messages_url(&provider("https://gw.example.com/")), "https://gw.example.com/v1/messages");
+        assert_eq!(messages_url(&provider("https://gw.example.com/v1")), "https://gw.example.com/v1/messages");
+        assert_eq!(messages_url(&provider("https://gw.example.com/v1/messages")), "https://gw.example.com/v1/messages");
+    }
+
+    #[test]
+    fn test_body_keeps_unknown_fields() {
+        let claude_req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": [{"type": "text", "text": "Hi", "cache_control": {"type": "ephemeral"}}]}],
+            "max_tokens": 1024,
+            "top_k": 5,
+            "stop_sequences": ["END"]
+        }))
+        .unwrap();
+
+        let body = build_body(&claude_req, "upstream-model", true).unwrap();
+        assert_eq!(body["model"], "upstream-model");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["top_k"], 5);
+        assert_eq!(body["stop_sequences"][0], "END");
+        assert_eq!(body["messages"][0]["content"][0]["cache_control"]["type"], "ephemeral");
+        assert!(body.get("system").is_none());
+    }
+
+    #[test]
+    fn test_body_strips_router_signed_thinking() {
+        let claude_req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [
+                {"role": "user", "content": "What is 6 times 7?"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "6 * 7", "signature": thinking::signature("6 * 7")},
+                    {"type": "text", "text": "42"}
+                ]},
+                {"role": "user", "content": "And 6 times 8?"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "6 * 8", "signature": "EqQBCkYIARgC"},
+                    {"type": "text", "text": "48"}
+                ]},
+                {"role": "user", "content": "Think about 6 times 9"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "6 * 9 is 54", "signature": thinking::signature("6 * 9 is 54")}
+                ]},
+                {"role": "user", "content": "Go on"}
+            ],
+            "max_tokens": 1024
+        }))
+        .unwrap();
+
+        let body = build_body(&claude_req, "upstream-model", false).unwrap();
+        assert_eq!(body["messages"][1]["content"], json!([{"type": "text", "text": "42"}]));
+        assert_eq!(body["messages"][3]["content"][0]["signature"], "EqQBCkYIARgC");
+        assert_eq!(body["messages"][5]["content"], json!([{"type": "text", "text": "6 * 9 is 54"}]));
+    }
+
+    #[test]
+    fn test_passthrough_event_reports_model() {
+        let event = |name: &str, data: Value| SseEvent { event: Some(name.to_string()), data: data.to_string() };
+
+        let start = event("message_start", json!({"type": "message_start", "message": {"id": "msg_1", "model": "claude-upstream"}}));
+        let framed = format_passthrough_event(&start, "claude-3-5-sonnet");
+        let data: Value = serde_json::from_str(framed.strip_prefix("event: message_start\ndata: ").unwrap().trim_end()).unwrap();
+        assert_eq!(data["message"], json!({"id": "msg_1", "model": "claude-3-5-sonnet"}));
+
+        let delta = event("content_block_delta", json!({"type": "content_block_delta", "delta": {"type": "text_delta", "text": "message_start"}}));
+        assert_eq!(format_passthrough_event(&delta, "claude-3-5-sonnet"), format!("event: content_block_delta\ndata: {}\n\n", delta.data));
+    }
+}
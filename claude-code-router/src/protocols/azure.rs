@@ -0,0 +1,59 @@
+use crate::config::Provider;
+
+/// `api-version` used when the provider does not set one
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+/// Deployment serving `model`: the provider's `deployments` entry, or the model name itself
+pub fn deployment_for<'a>(provider: &'a Provider, model: &'a str) -> &'a str {
+    provider
+        .deployments
+        .as_ref()
+        .and_then(|deployments| deployments.get(model))
+        .map(|deployment| deployment.as_str())
+        .unwrap_or(model)
+}
+
+/// `{resource}/openai/deployments/{deployment}/chat/completions?api-version=...`.
+/// The base URL is the resource endpoint, with or without the trailing `/openai`.
+pub fn chat_completions_url(provider: &Provider, model: &str) -> String {
+    let base = provider.api_base_url.trim_end_matches('/');
+    let base = base.strip_suffix("/openai").unwrap_or(base);
+    format!(
+        "{}/openai/deployments/{}/chat/completions?api-version={}",
+        base,
+        deployment_for(provider, model),
+        provider.api_version.as_deref().unwrap_or(DEFAULT_API_VERSION)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_deployment_urls() {
+        let provider: Provider = serde_json::from_value(json!({
+            "name": "azure",
+            "type": "azure",
+            "api_base_url": "https://contoso.openai.azure.com/openai/",
+            "api_key": "k",
+            "models": ["gpt-4o", "gpt-4o-mini"],
+            "api_version": "2025-01-01-preview",
+            "deployments": {"gpt-4o": "prod-gpt4o"}
+        }))
+        .unwrap();
+
+        assert_eq!(
+            chat_completions_url(&provider, "gpt-4o"),
+            "https://contoso.openai.azure.com/openai/deployments/prod-gpt4o/chat/completions?api-version=2025-01-01-preview"
+        );
+        assert_eq!(deployment_for(&provider, "gpt-4o-mini"), "gpt-4o-mini");
+
+        let plain = Provider { api_version: None, deployments: None, ..provider };
+        assert_eq!(
+            chat_completions_url(&plain, "gpt-4o"),
+            "https://contoso.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21"
+        );
+    }
+}
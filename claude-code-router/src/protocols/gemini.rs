@@ -0,0 +1,549 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use crate::config::{Provider, SchemaProfile};
+use crate::router::Message;
+use crate::schema;
+use crate::server::ClaudeRequest;
+use crate::thinking::ThinkingConfig;
+use crate::tool_names::ToolNameMap;
+
+/// `generateContent` endpoint for a model
+pub fn generate_url(provider: &Provider, model: &str) -> String {
+    format!("{}/{}:generateContent", provider.api_base_url.trim_end_matches('/'), model_path(model))
+}
+
+/// `streamGenerateContent` endpoint for a model, asking for SSE framing
+pub fn stream_url(provider: &Provider, model: &str) -> String {
+    format!(
+        "{}/{}:streamGenerateContent?alt=sse",
+        provider.api_base_url.trim_end_matches('/'),
+        model_path(model)
+    )
+}
+
+fn model_path(model: &str) -> String {
+    if model.starts_with("models/") || model.starts_with("tunedModels/") {
+        model.to_string()
+    } else {
+        format!("models/{}", model)
+    }
+}
+
+/// Build a `GenerateContentRequest` from a Claude request. Tool names Gemini would
+/// reject are sanitized; the response mapping restores them.
+pub fn build_body(claude_req: &ClaudeRequest) -> Value {
+    let mut body = json!({"contents": build_contents(&claude_req.messages)});
+
+    if let Some(system) = claude_req.system.as_ref().and_then(system_text) {
+        body["systemInstruction"] = json!({"parts": [{"text": system}]});
+    }
+
+    if let Some(tools) = &claude_req.tools {
+        let declarations: Vec<Value> = tools
+            .iter()
+            .filter_map(|tool| {
+                let name = tool.get("name")?.as_str()?;
+                let mut declaration = json!({"name": name});
+                if let Some(description) = tool.get("description").and_then(|d| d.as_str()) {
+                    declaration["description"] = json!(description);
+                }
+                if let Some(schema) = tool.get("input_schema") {
+                    declaration["parameters"] = schema::sanitize(schema, SchemaProfile::Gemini);
+                }
+                Some(declaration)
+            })
+            .collect();
+        if !declarations.is_empty() {
+            body["tools"] = json!([{"functionDeclarations": declarations}]);
+            if let Some(tool_config) = claude_req.tool_choice.as_ref().and_then(tool_config) {
+                body["toolConfig"] = tool_config;
+            }
+        }
+    }
+
+    let mut generation_config = Map::new();
+    if let Some(max_tokens) = claude_req.max_tokens {
+        generation_config.insert("maxOutputTokens".to_string(), json!(max_tokens));
+    }
+    if let Some(temperature) = claude_req.temperature {
+        generation_config.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(top_p) = claude_req.top_p {
+        generation_config.insert("topP".to_string(), json!(top_p));
+    }
+    if let Some(top_k) = claude_req.top_k {
+        generation_config.insert("topK".to_string(), json!(top_k));
+    }
+    if let Some(stop_sequences) = claude_req.stop_sequences.as_ref().filter(|s| !s.is_empty()) {
+        generation_config.insert("stopSequences".to_string(), json!(stop_sequences));
+    }
+    if let Some(thinking) = claude_req.thinking.as_ref().and_then(ThinkingConfig::from_value) {
+        let mut thinking_config = json!({"includeThoughts": true});
+        if let Some(budget) = thinking.budget_tokens {
+            thinking_config["thinkingBudget"] = json!(budget);
+        }
+        generation_config.insert("thinkingConfig".to_string(), thinking_config);
+    }
+    if !generation_config.is_empty() {
+        body["generationConfig"] = Value::Object(generation_config);
+    }
+
+    ToolNameMap::from_request(claude_req).apply_to_gemini_body(&mut body);
+    body
+}
+
+/// Convert Claude messages into Gemini `contents`. Assistant turns use the `model` role,
+/// tool results become `functionResponse` parts, and consecutive turns of the same role
+/// are merged because Gemini expects the roles to alternate.
+fn build_contents(messages: &[Message]) -> Vec<Value> {
+    // functionResponse needs the function name, Claude tool_result only has the id
+    let mut tool_names: HashMap<String, String> = HashMap::new();
+    let mut contents: Vec<Value> = Vec::new();
+
+    for message in messages {
+        let role = if message.role == "assistant" { "model" } else { "user" };
+        let parts: Vec<Value> = match &message.content {
+            Value::String(text) => vec![json!({"text": text})],
+            Value::Array(blocks) => blocks
+                .iter()
+                .filter_map(|block| convert_block(block, &mut tool_names))
+                .collect(),
+            _ => vec![],
+        };
+        if parts.is_empty() {
+            continue;
+        }
+
+        match contents.last_mut() {
+            Some(last) if last["role"] == role => {
+                if let Some(existing) = last["parts"].as_array_mut() {
+                    existing.extend(parts);
+                }
+            }
+            _ => contents.push(json!({"role": role, "parts": parts})),
+        }
+    }
+
+    contents
+}
+
+fn convert_block(block: &Value, tool_names: &mut HashMap<String, String>) -> Option<Value> {
+    match block.get("type")?.as_str()? {
+        "text" => Some(json!({"text": block.get("text")?.as_str()?})),
+        "image" => image_part(block.get("source")?),
+        "tool_use" => {
+            let name = block.get("name")?.as_str()?;
+            if let Some(id) = block.get("id").and_then(|v| v.as_str()) {
+                tool_names.insert(id.to_string(), name.to_string());
+            }
+            Some(json!({
+                "functionCall": {
+                    "name": name,
+                    "args": block.get("input").cloned().unwrap_or_else(|| json!({}))
+                }
+            }))
+        }
+        "tool_result" => {
+            let id = block.get("tool_use_id").and_then(|v| v.as_str()).unwrap_or_default();
+            let name = tool_names.get(id).cloned().unwrap_or_else(|| id.to_string());
+            let output = tool_result_text(block.get("content"));
+            let key = if block.get("is_error").and_then(|v| v.as_bool()).unwrap_or(false) {
+                "error"
+            } else {
+                "content"
+            };
+            Some(json!({
+                "functionResponse": {
+                    "name": name,
+                    "response": {key: output}
+                }
+            }))
+        }
+        other => {
+            log::debug!("Dropping unsupported content block type for Gemini: {}", other);
+            None
+        }
+    }
+}
+
+fn image_part(source: &Value) -> Option<Value> {
+    match source.get("type")?.as_str()? {
+        "base64" => Some(json!({
+            "inlineData": {
+                "mimeType": source.get("media_type")?.as_str()?,
+                "data": source.get("data")?.as_str()?
+            }
+        })),
+        "url" => {
+            let url = source.get("url")?.as_str()?;
+            let mut file_data = json!({"fileUri": url});
+            if let Some(mime_type) = guess_image_mime_type(url) {
+                file_data["mimeType"] = json!(mime_type);
+            }
+            Some(json!({"fileData": file_data}))
+        }
+        _ => None,
+    }
+}
+
+fn guess_image_mime_type(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_ascii_lowercase();
+    let extension = path.rsplit('.').next()?;
+    match extension {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+fn tool_result_text(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(blocks)) => blocks
+            .iter()
+            .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(other) if !other.is_null() => other.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// System prompt as plain text, from either the string or the content-block form
+fn system_text(system: &Value) -> Option<String> {
+    let text = match system {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => return None,
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+/// `toolConfig` for a Claude `tool_choice`: auto -> AUTO, any -> ANY, none -> NONE,
+/// tool -> ANY restricted to the named function
+fn tool_config(tool_choice: &Value) -> Option<Value> {
+    let config = match tool_choice.get("type").and_then(|t| t.as_str())? {
+        "auto" => json!({"mode": "AUTO"}),
+        "any" => json!({"mode": "ANY"}),
+        "none" => json!({"mode": "NONE"}),
+        "tool" => json!({"mode": "ANY", "allowedFunctionNames": [tool_choice.get("name")?.as_str()?]}),
+        _ => return None,
+    };
+    Some(json!({"functionCallingConfig": config}))
+}
+
+/// Content of the first candidate, split the way OpenAI messages carry it
+struct CandidateContent {
+    text: Option<String>,
+    reasoning: Option<String>,
+    tool_calls: Vec<Value>,
+    finish_reason: Option<String>,
+}
+
+/// Translates Gemini responses into OpenAI chat completion shapes so the existing
+/// Claude conversion (`convert_openai_to_claude_format`, `StreamTransformer`) can be reused.
+/// Keeps the state needed across stream chunks.
+#[derive(Debug, Default)]
+pub struct GeminiResponseMapper {
+    next_tool_index: usize,
+    saw_tool_call: bool,
+}
+
+impl GeminiResponseMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert a complete `generateContent` response into a `chat.completion`
+    pub fn to_openai_response(&mut self, response: &Value) -> Value {
+        let candidate = self.convert_candidate(response);
+        let mut message = json!({"role": "assistant", "content": candidate.text});
+        if let Some(reasoning) = candidate.reasoning {
+            message["reasoning_content"] = json!(reasoning);
+        }
+        if !candidate.tool_calls.is_empty() {
+            message["tool_calls"] = json!(candidate.tool_calls);
+        }
+
+        let mut completion = json!({
+            "object": "chat.completion",
+            "choices": [{"index": 0, "message": message, "finish_reason": candidate.finish_reason}]
+        });
+        if let Some(usage) = map_usage(response) {
+            completion["usage"] = usage;
+        }
+        completion
+    }
+
+    /// Convert one `streamGenerateContent` event into a `chat.completion.chunk`
+    pub fn to_openai_chunk(&mut self, chunk: &Value) -> Value {
+        let candidate = self.convert_candidate(chunk);
+        let mut delta = json!({});
+        if let Some(text) = candidate.text {
+            delta["content"] = json!(text);
+        }
+        if let Some(reasoning) = candidate.reasoning {
+            delta["reasoning_content"] = json!(reasoning);
+        }
+        if !candidate.tool_calls.is_empty() {
+            delta["tool_calls"] = json!(candidate.tool_calls);
+        }
+
+        let mut openai_chunk = json!({
+            "object": "chat.completion.chunk",
+            "choices": [{"index": 0, "delta": delta, "finish_reason": candidate.finish_reason}]
+        });
+        if let Some(usage) = map_usage(chunk) {
+            openai_chunk["usage"] = usage;
+        }
+        openai_chunk
+    }
+
+    fn convert_candidate(&mut self, response: &Value) -> CandidateContent {
+        let candidate = response.pointer("/candidates/0");
+        let parts = candidate
+            .and_then(|c| c.pointer("/content/parts"))
+            .and_then(|p| p.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        let mut text = String::new();
+        let mut reasoning = String::new();
+        let mut tool_calls = Vec::new();
+        for part in &parts {
+            if let Some(function_call) = part.get("functionCall") {
+                let id = function_call
+                    .get("id")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| format!("toolu_{}", uuid::Uuid::new_v4().simple()));
+                let args = function_call.get("args").cloned().unwrap_or_else(|| json!({}));
+                tool_calls.push(json!({
+                    "index": self.next_tool_index,
+                    "id": id,
+                    "type": "function",
+                    "function": {
+                        "name": function_call.get("name").and_then(|v| v.as_str()).unwrap_or_default(),
+                        "arguments": args.to_string()
+                    }
+                }));
+                self.next_tool_index += 1;
+                self.saw_tool_call = true;
+            } else if let Some(part_text) = part.get("text").and_then(|t| t.as_str()) {
+                if part.get("thought").and_then(|t| t.as_bool()).unwrap_or(false) {
+                    reasoning.push_str(part_text);
+                } else {
+                    text.push_str(part_text);
+                }
+            }
+        }
+
+        let finish_reason = candidate
+            .and_then(|c| c.get("finishReason"))
+            .and_then(|r| r.as_str())
+            .map(|reason| self.map_finish_reason(reason).to_string());
+
+        CandidateContent {
+            text: (!text.is_empty()).then_some(text),
+            reasoning: (!reasoning.is_empty()).then_some(reasoning),
+            tool_calls,
+            finish_reason,
+        }
+    }
+
+    /// Gemini reports STOP even when the turn ended with function calls
+    fn map_finish_reason(&self, reason: &str) -> &'static str {
+        match reason {
+            "MAX_TOKENS" => "length",
+            "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY" => "content_filter",
+            _ if self.saw_tool_call => "tool_calls",
+            _ => "stop",
+        }
+    }
+}
+
+/// `usageMetadata` as OpenAI `usage`; thinking tokens count as output
+fn map_usage(response: &Value) -> Option<Value> {
+    let metadata = response.get("usageMetadata")?;
+    let count = |key: &str| metadata.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+    let mut usage = json!({
+        "prompt_tokens": count("promptTokenCount"),
+        "completion_tokens": count("candidatesTokenCount") + count("thoughtsTokenCount")
+    });
+    if metadata.get("cachedContentTokenCount").is_some() {
+        usage["prompt_tokens_details"] = json!({"cached_tokens": count("cachedContentTokenCount")});
+    }
+    Some(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool_names::sanitize_tool_name;
+
+    #[test]
+    fn test_urls() {
+        let provider: Provider = serde_json::from_value(json!({
+            "name": "gemini",
+            "type": "gemini",
+            "api_base_url": "https://generativelanguage.googleapis.com/v1beta/",
+            "api_key": "k",
+            "models": []
+        }))
+        .unwrap();
+        assert_eq!(
+            generate_url(&provider, "gemini-2.5-pro"),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-pro:generateContent"
+        );
+        assert_eq!(
+            stream_url(&provider, "models/gemini-2.5-flash"),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse"
+        );
+    }
+
+    #[test]
+    fn test_build_body() {
+        let claude_req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-sonnet",
+            "system": [{"type": "text", "text": "Be brief."}],
+            "max_tokens": 256,
+            "thinking": {"type": "enabled", "budget_tokens": 2048},
+            "top_k": 40,
+            "stop_sequences": ["END"],
+            "tool_choice": {"type": "tool", "name": "search"},
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What is in this picture?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBOR"}}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "Let me look it up."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "search", "input": {"q": "cat"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": [{"type": "text", "text": "a cat"}]}
+                ]},
+                {"role": "user", "content": "Thanks"}
+            ],
+            "tools": [{
+                "name": "search",
+                "description": "Search the web",
+                "input_schema": {
+                    "$schema": "http://json-schema.org/draft-07/schema#",
+                    "type": "object",
+                    "properties": {"q": {"type": "string"}},
+                    "additionalProperties": false
+                }
+            }]
+        }))
+        .unwrap();
+
+        let body = build_body(&claude_req);
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "Be brief.");
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 256);
+        assert_eq!(body["generationConfig"]["topK"], 40);
+        assert_eq!(body["generationConfig"]["stopSequences"], json!(["END"]));
+        assert_eq!(
+            body["toolConfig"],
+            json!({"functionCallingConfig": {"mode": "ANY", "allowedFunctionNames": ["search"]}})
+        );
+        assert_eq!(body["generationConfig"]["thinkingConfig"], json!({"includeThoughts": true, "thinkingBudget": 2048}));
+
+        let contents = body["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[0]["parts"][1]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(contents[1]["parts"][1]["functionCall"]["args"]["q"], "cat");
+        // The tool result and the follow-up text are merged into one user turn
+        assert_eq!(contents[2]["parts"][0]["functionResponse"]["name"], "search");
+        assert_eq!(contents[2]["parts"][0]["functionResponse"]["response"]["content"], "a cat");
+        assert_eq!(contents[2]["parts"][1]["text"], "Thanks");
+
+        let declaration = &body["tools"][0]["functionDeclarations"][0];
+        assert_eq!(declaration["name"], "search");
+        assert!(declaration["parameters"].get("$schema").is_none());
+        assert!(declaration["parameters"].get("additionalProperties").is_none());
+    }
+
+    #[test]
+    fn test_build_body_sanitizes_tool_names() {
+        let claude_req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-sonnet",
+            "tool_choice": {"type": "tool", "name": "files.read"},
+            "messages": [
+                {"role": "user", "content": "Read it"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "files.read", "input": {"path": "a"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "contents"}
+                ]}
+            ],
+            "tools": [
+                {"name": "files.read", "input_schema": {"type": "object"}},
+                {"name": "Bash", "input_schema": {"type": "object"}}
+            ]
+        }))
+        .unwrap();
+        let sanitized = sanitize_tool_name("files.read");
+
+        let body = build_body(&claude_req);
+        assert_eq!(body["tools"][0]["functionDeclarations"][0]["name"], sanitized);
+        assert_eq!(body["tools"][0]["functionDeclarations"][1]["name"], "Bash");
+        assert_eq!(body["contents"][1]["parts"][0]["functionCall"]["name"], sanitized);
+        assert_eq!(body["contents"][2]["parts"][0]["functionResponse"]["name"], sanitized);
+        assert_eq!(body["toolConfig"]["functionCallingConfig"]["allowedFunctionNames"], json!([sanitized]));
+    }
+
+    #[test]
+    fn test_response_mapping() {
+        let response = json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "Checking", "thought": true},
+                    {"text": "Calling search"},
+                    {"functionCall": {"name": "search", "args": {"q": "rust"}}}
+                ]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 12, "candidatesTokenCount": 5, "thoughtsTokenCount": 3}
+        });
+
+        let completion = GeminiResponseMapper::new().to_openai_response(&response);
+        let message = &completion["choices"][0]["message"];
+        assert_eq!(message["content"], "Calling search");
+        assert_eq!(message["reasoning_content"], "Checking");
+        assert_eq!(message["tool_calls"][0]["function"]["name"], "search");
+        assert_eq!(message["tool_calls"][0]["function"]["arguments"], r#"{"q":"rust"}"#);
+        assert_eq!(completion["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(completion["usage"]["prompt_tokens"], 12);
+        assert_eq!(completion["usage"]["completion_tokens"], 8);
+    }
+
+    #[test]
+    fn test_stream_chunks_keep_tool_state() {
+        let mut mapper = GeminiResponseMapper::new();
+        let first = mapper.to_openai_chunk(&json!({
+            "candidates": [{"content": {"parts": [{"functionCall": {"name": "a", "args": {}}}]}}]
+        }));
+        assert_eq!(first["choices"][0]["delta"]["tool_calls"][0]["index"], 0);
+
+        let last = mapper.to_openai_chunk(&json!({
+            "candidates": [{"content": {"parts": [{"functionCall": {"name": "b", "args": {}}}]}, "finishReason": "STOP"}]
+        }));
+        assert_eq!(last["choices"][0]["delta"]["tool_calls"][0]["index"], 1);
+        assert_eq!(last["choices"][0]["finish_reason"], "tool_calls");
+
+        let blocked = GeminiResponseMapper::new().to_openai_chunk(&json!({
+            "candidates": [{"finishReason": "SAFETY"}]
+        }));
+        assert_eq!(blocked["choices"][0]["finish_reason"], "content_filter");
+    }
+}
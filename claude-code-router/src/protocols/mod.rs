@@ -0,0 +1,6 @@
+//! Request/response handling for providers that do not speak the
+//! OpenAI chat completions protocol
+pub mod anthropic;
+pub mod azure;
+pub mod gemini;
+pub mod ollama;
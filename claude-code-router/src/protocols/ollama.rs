@@ -0,0 +1,418 @@
+use serde_json::{json, Map, Value};
+
+use crate::config::Provider;
+use crate::router::Message;
+use crate::server::ClaudeRequest;
+use crate::thinking::ThinkingConfig;
+use crate::tool_names::ToolNameMap;
+
+/// Per-model settings that Ollama takes at the top level of the request
+/// rather than inside `options`
+const TOP_LEVEL_OPTIONS: [&str; 3] = ["keep_alive", "format", "think"];
+
+/// `/api/chat` endpoint; the base URL may be the server root, end in `/api`,
+/// or be the OpenAI-compat `/v1` URL users often configure
+pub fn chat_url(provider: &Provider) -> String {
+    let base = provider.api_base_url.trim_end_matches('/');
+    if base.ends_with("/api/chat") {
+        return base.to_string();
+    }
+    let base = base.strip_suffix("/v1").unwrap_or(base);
+    let base = base.strip_suffix("/api").unwrap_or(base);
+    format!("{}/api/chat", base)
+}
+
+/// Build an Ollama chat request. Settings from the provider's `model_options`
+/// entry for this model are applied on top: `keep_alive`, `format` and `think`
+/// at the top level, everything else (e.g. `num_ctx`) inside `options`. Tool names
+/// are sanitized as for OpenAI providers; the response mapping restores them.
+pub fn build_body(claude_req: &ClaudeRequest, model: &str, provider: &Provider, stream: bool) -> Value {
+    let mut messages = Vec::new();
+    if let Some(system) = claude_req.system.as_ref().and_then(text_of) {
+        messages.push(json!({"role": "system", "content": system}));
+    }
+    for message in &claude_req.messages {
+        convert_message(message, &mut messages);
+    }
+
+    let mut body = json!({
+        "model": model,
+        "messages": messages,
+        "stream": stream
+    });
+
+    if let Some(tools) = &claude_req.tools {
+        let tools: Vec<Value> = tools
+            .iter()
+            .filter_map(|tool| {
+                Some(json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.get("name")?.as_str()?,
+                        "description": tool.get("description").and_then(|d| d.as_str()).unwrap_or_default(),
+                        "parameters": tool.get("input_schema").cloned().unwrap_or_else(|| json!({"type": "object"}))
+                    }
+                }))
+            })
+            .collect();
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+    }
+
+    if claude_req.thinking.as_ref().and_then(ThinkingConfig::from_value).is_some() {
+        body["think"] = json!(true);
+    }
+
+    let mut options = Map::new();
+    if let Some(max_tokens) = claude_req.max_tokens {
+        options.insert("num_predict".to_string(), json!(max_tokens));
+    }
+    if let Some(temperature) = claude_req.temperature {
+        options.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(top_p) = claude_req.top_p {
+        options.insert("top_p".to_string(), json!(top_p));
+    }
+    if let Some(top_k) = claude_req.top_k {
+        options.insert("top_k".to_string(), json!(top_k));
+    }
+    if let Some(stop_sequences) = claude_req.stop_sequences.as_ref().filter(|s| !s.is_empty()) {
+        options.insert("stop".to_string(), json!(stop_sequences));
+    }
+    if let Some(model_options) = provider
+        .model_options
+        .as_ref()
+        .and_then(|all| all.get(model))
+        .and_then(|o| o.as_object())
+    {
+        for (key, value) in model_options {
+            if TOP_LEVEL_OPTIONS.contains(&key.as_str()) {
+                body[key] = value.clone();
+            } else {
+                options.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    if !options.is_empty() {
+        body["options"] = Value::Object(options);
+    }
+
+    ToolNameMap::from_request(claude_req).apply_to_openai_body(&mut body);
+    body
+}
+
+/// Append the Ollama messages for one Claude message. Tool results become
+/// separate `tool` messages placed before the rest of the user turn.
+fn convert_message(message: &Message, out: &mut Vec<Value>) {
+    let blocks = match &message.content {
+        Value::String(text) => {
+            out.push(json!({"role": message.role, "content": text}));
+            return;
+        }
+        Value::Array(blocks) => blocks,
+        _ => return,
+    };
+
+    let mut text = Vec::new();
+    let mut images = Vec::new();
+    let mut tool_calls = Vec::new();
+    for block in blocks {
+        match block.get("type").and_then(|t| t.as_str()) {
+            Some("text") => {
+                if let Some(t) = block.get("text").and_then(|t| t.as_str()) {
+                    text.push(t.to_string());
+                }
+            }
+            Some("image") => match block.get("source") {
+                Some(source) if source.get("type").and_then(|t| t.as_str()) == Some("base64") => {
+                    if let Some(data) = source.get("data").and_then(|d| d.as_str()) {
+                        images.push(json!(data));
+                    }
+                }
+                _ => log::warn!("Ollama only accepts base64 images, dropping image block"),
+            },
+            Some("tool_use") => tool_calls.push(json!({
+                "function": {
+                    "name": block.get("name").and_then(|n| n.as_str()).unwrap_or_default(),
+                    "arguments": block.get("input").cloned().unwrap_or_else(|| json!({}))
+                }
+            })),
+            Some("tool_result") => {
+                let content = block.get("content").and_then(text_of).unwrap_or_default();
+                out.push(json!({"role": "tool", "content": content}));
+            }
+            _ => {}
+        }
+    }
+
+    if text.is_empty() && images.is_empty() && tool_calls.is_empty() {
+        return;
+    }
+    let mut converted = json!({"role": message.role, "content": text.join("\n")});
+    if !images.is_empty() {
+        converted["images"] = json!(images);
+    }
+    if !tool_calls.is_empty() {
+        converted["tool_calls"] = json!(tool_calls);
+    }
+    out.push(converted);
+}
+
+/// Plain text of a string or an array of text blocks
+fn text_of(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(blocks) => Some(
+            blocks
+                .iter()
+                .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        _ => None,
+    }
+}
+
+/// Translates Ollama chat responses into OpenAI chat completion shapes so the
+/// existing Claude conversion can be reused. Ollama sends no tool call ids,
+/// so they are generated here.
+#[derive(Debug, Default)]
+pub struct OllamaResponseMapper {
+    next_tool_index: usize,
+    saw_tool_call: bool,
+}
+
+impl OllamaResponseMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert a non-streaming `/api/chat` response into a `chat.completion`
+    pub fn to_openai_response(&mut self, response: &Value) -> Value {
+        let mut message = json!({"role": "assistant", "content": ""});
+        self.fill_message(response, &mut message);
+        let mut completion = json!({
+            "object": "chat.completion",
+            "choices": [{"index": 0, "message": message, "finish_reason": self.finish_reason(response)}]
+        });
+        if let Some(usage) = map_usage(response) {
+            completion["usage"] = usage;
+        }
+        completion
+    }
+
+    /// Convert one NDJSON stream line into a `chat.completion.chunk`
+    pub fn to_openai_chunk(&mut self, chunk: &Value) -> Value {
+        let mut delta = json!({});
+        self.fill_message(chunk, &mut delta);
+        let mut openai_chunk = json!({
+            "object": "chat.completion.chunk",
+            "choices": [{"index": 0, "delta": delta, "finish_reason": self.finish_reason(chunk)}]
+        });
+        if let Some(usage) = map_usage(chunk) {
+            openai_chunk["usage"] = usage;
+        }
+        openai_chunk
+    }
+
+    fn fill_message(&mut self, response: &Value, target: &mut Value) {
+        let message = match response.get("message") {
+            Some(message) => message,
+            None => return,
+        };
+        if let Some(content) = message.get("content").and_then(|c| c.as_str()).filter(|c| !c.is_empty()) {
+            target["content"] = json!(content);
+        }
+        if let Some(thinking) = message.get("thinking").and_then(|t| t.as_str()).filter(|t| !t.is_empty()) {
+            target["reasoning_content"] = json!(thinking);
+        }
+        if let Some(calls) = message.get("tool_calls").and_then(|c| c.as_array()).filter(|c| !c.is_empty()) {
+            let tool_calls: Vec<Value> = calls
+                .iter()
+                .map(|call| {
+                    let function = call.get("function").cloned().unwrap_or_else(|| json!({}));
+                    let arguments = match function.get("arguments") {
+                        Some(Value::String(raw)) => raw.clone(),
+                        Some(args) => args.to_string(),
+                        None => "{}".to_string(),
+                    };
+                    let tool_call = json!({
+                        "index": self.next_tool_index,
+                        "id": format!("toolu_{}", uuid::Uuid::new_v4().simple()),
+                        "type": "function",
+                        "function": {
+                            "name": function.get("name").and_then(|n| n.as_str()).unwrap_or_default(),
+                            "arguments": arguments
+                        }
+                    });
+                    self.next_tool_index += 1;
+                    tool_call
+                })
+                .collect();
+            self.saw_tool_call = true;
+            target["tool_calls"] = json!(tool_calls);
+        }
+    }
+
+    /// Only the final (`done: true`) message carries a finish reason
+    fn finish_reason(&self, response: &Value) -> Option<&'static str> {
+        if !response.get("done").and_then(|d| d.as_bool()).unwrap_or(false) {
+            return None;
+        }
+        Some(match response.get("done_reason").and_then(|r| r.as_str()) {
+            Some("length") => "length",
+            _ if self.saw_tool_call => "tool_calls",
+            _ => "stop",
+        })
+    }
+}
+
+/// `prompt_eval_count` / `eval_count` as OpenAI `usage`, present on the final message
+fn map_usage(response: &Value) -> Option<Value> {
+    let prompt = response.get("prompt_eval_count").and_then(|v| v.as_u64());
+    let completion = response.get("eval_count").and_then(|v| v.as_u64());
+    if prompt.is_none() && completion.is_none() {
+        return None;
+    }
+    Some(json!({
+        "prompt_tokens": prompt.unwrap_or(0),
+        "completion_tokens": completion.unwrap_or(0)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool_names::sanitize_tool_name;
+
+    fn provider(base: &str) -> Provider {
+        serde_json::from_value(json!({
+            "name": "ollama",
+            "type": "ollama",
+            "api_base_url": base,
+            "api_key": "",
+            "models": ["qwen3"],
+            "model_options": {
+                "qwen3": {"num_ctx": 32768, "keep_alive": "30m", "format": "json"}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_chat_url() {
+        assert_eq!(chat_url(&provider("http://localhost:11434")), "http://localhost:11434/api/chat");
+        assert_eq!(chat_url(&provider("http://localhost:11434/v1/")), "http://localhost:11434/api/chat");
+        assert_eq!(chat_url(&provider("http://localhost:11434/api")), "http://localhost:11434/api/chat");
+        assert_eq!(chat_url(&provider("http://gpu:8080/api/chat")), "http://gpu:8080/api/chat");
+    }
+
+    #[test]
+    fn test_build_body() {
+        let claude_req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-sonnet",
+            "system": "You are terse.",
+            "max_tokens": 100,
+            "thinking": {"type": "enabled", "budget_tokens": 1024},
+            "top_k": 20,
+            "stop_sequences": ["</answer>"],
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "Describe"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBOR"}}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "lookup", "input": {"id": 7}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "found"},
+                    {"type": "text", "text": "and?"}
+                ]}
+            ],
+            "tools": [{"name": "lookup", "description": "Find", "input_schema": {"type": "object"}}]
+        }))
+        .unwrap();
+
+        let body = build_body(&claude_req, "qwen3", &provider("http://localhost:11434"), true);
+        assert_eq!(body["model"], "qwen3");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["think"], true);
+        assert_eq!(body["keep_alive"], "30m");
+        assert_eq!(body["format"], "json");
+        assert_eq!(body["options"]["num_ctx"], 32768);
+        assert_eq!(body["options"]["num_predict"], 100);
+        assert_eq!(body["options"]["top_k"], 20);
+        assert_eq!(body["options"]["stop"], json!(["</answer>"]));
+        assert_eq!(body["tools"][0]["function"]["name"], "lookup");
+
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[0], json!({"role": "system", "content": "You are terse."}));
+        assert_eq!(messages[1]["images"][0], "iVBOR");
+        assert_eq!(messages[2]["tool_calls"][0]["function"]["arguments"]["id"], 7);
+        assert_eq!(messages[3], json!({"role": "tool", "content": "found"}));
+        assert_eq!(messages[4], json!({"role": "user", "content": "and?"}));
+    }
+
+    #[test]
+    fn test_build_body_sanitizes_tool_names() {
+        let claude_req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [
+                {"role": "user", "content": "Read it"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "files.read", "input": {"path": "a"}}
+                ]}
+            ],
+            "tools": [{"name": "files.read", "input_schema": {"type": "object"}}]
+        }))
+        .unwrap();
+        let sanitized = sanitize_tool_name("files.read");
+
+        let body = build_body(&claude_req, "qwen3", &provider("http://localhost:11434"), false);
+        assert_eq!(body["tools"][0]["function"]["name"], sanitized);
+        assert_eq!(body["messages"][1]["tool_calls"][0]["function"]["name"], sanitized);
+    }
+
+    #[test]
+    fn test_response_mapping() {
+        let completion = OllamaResponseMapper::new().to_openai_response(&json!({
+            "model": "qwen3",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "thinking": "Need a lookup",
+                "tool_calls": [{"function": {"name": "lookup", "arguments": {"id": 7}}}]
+            },
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 30,
+            "eval_count": 9
+        }));
+
+        let message = &completion["choices"][0]["message"];
+        assert_eq!(message["reasoning_content"], "Need a lookup");
+        assert_eq!(message["tool_calls"][0]["function"]["arguments"], r#"{"id":7}"#);
+        assert!(message["tool_calls"][0]["id"].as_str().unwrap().starts_with("toolu_"));
+        assert_eq!(completion["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(completion["usage"], json!({"prompt_tokens": 30, "completion_tokens": 9}));
+    }
+
+    #[test]
+    fn test_stream_chunks() {
+        let mut mapper = OllamaResponseMapper::new();
+        let partial = mapper.to_openai_chunk(&json!({"message": {"role": "assistant", "content": "Hel"}, "done": false}));
+        assert_eq!(partial["choices"][0]["delta"]["content"], "Hel");
+        assert!(partial["choices"][0]["finish_reason"].is_null());
+        assert!(partial.get("usage").is_none());
+
+        let last = mapper.to_openai_chunk(&json!({
+            "message": {"role": "assistant", "content": ""},
+            "done": true,
+            "done_reason": "length",
+            "eval_count": 4
+        }));
+        assert_eq!(last["choices"][0]["finish_reason"], "length");
+        assert_eq!(last["usage"]["completion_tokens"], 4);
+    }
+}
@@ -1,24 +1,219 @@
 use serde_json::{json, Value};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 
-use crate::config::{Config, Provider};
-use crate::router::RouterRequest;
-use crate::server::ClaudeRequest;
+use crate::config::{Config, ImageSupport, Provider, ProviderType, ReasoningParam, SystemPrompt, Timeouts};
+use crate::error::{anthropic_error_body, openai_error_body, ProviderError};
+use crate::health::{self, HealthTracker};
+use crate::keys::{KeyOutcome, KeyPool};
+use crate::message_transformer::MessageTransformer;
+use crate::proxy::{self, ProxySetting};
+use crate::protocols::{anthropic, azure};
+use crate::protocols::gemini::{self, GeminiResponseMapper};
+use crate::protocols::ollama::{self, OllamaResponseMapper};
+use crate::retry::{self, RetryPolicy};
+use crate::schema;
+use crate::router::{Message, Router, RouterRequest};
+use crate::server::{self, ClaudeRequest};
+use crate::stream_transformer::{format_sse_event, NdjsonParser, SseEvent, SseParser, StreamParser, StreamTransformer};
+use crate::text_tool_calls::{self, TextToolCallStream};
+use crate::thinking::{self, ThinkingConfig};
+use crate::tool_args;
+use crate::tool_names::ToolNameMap;
 use crate::transformers;
 
+/// Map an OpenAI `finish_reason` to the Anthropic `stop_reason` vocabulary. Filtered
+/// content becomes `refusal`; reasons Claude clients would not understand end the turn.
+pub fn map_finish_reason(finish_reason: &str) -> String {
+    match finish_reason {
+        "stop" | "end_turn" | "eos" => "end_turn",
+        "length" | "max_tokens" => "max_tokens",
+        "tool_calls" | "function_call" | "tool_use" => "tool_use",
+        "content_filter" | "refusal" | "safety" => "refusal",
+        "stop_sequence" => "stop_sequence",
+        "pause_turn" => "pause_turn",
+        other => {
+            log::debug!("Unknown finish_reason {:?}, reporting end_turn", other);
+            "end_turn"
+        }
+    }
+    .to_string()
+}
+
+/// Anthropic usage for an OpenAI `usage` object. Cached prompt tokens
+/// (`prompt_tokens_details.cached_tokens`, DeepSeek's `prompt_cache_hit_tokens`) become
+/// `cache_read_input_tokens`, cache writes (`prompt_tokens_details.cache_write_tokens`)
+/// `cache_creation_input_tokens`, and `input_tokens` counts the rest as Anthropic does.
+pub fn map_usage(usage: &Value) -> Value {
+    let count = |value: Option<&Value>| value.and_then(|v| v.as_u64()).unwrap_or(0);
+    let details = usage.get("prompt_tokens_details");
+    let cache_read = count(
+        details
+            .and_then(|d| d.get("cached_tokens"))
+            .or_else(|| usage.get("prompt_cache_hit_tokens"))
+            .or_else(|| usage.get("cache_read_input_tokens")),
+    );
+    let cache_creation = count(
+        details
+            .and_then(|d| d.get("cache_write_tokens"))
+            .or_else(|| usage.get("cache_creation_input_tokens")),
+    );
+    json!({
+        "input_tokens": count(usage.get("prompt_tokens")).saturating_sub(cache_read + cache_creation),
+        "output_tokens": count(usage.get("completion_tokens")),
+        "cache_creation_input_tokens": cache_creation,
+        "cache_read_input_tokens": cache_read
+    })
+}
+
+/// The requested stop sequence an OpenAI choice stopped on. Plain OpenAI does not say;
+/// vLLM reports it as `stop_reason` and SGLang as `matched_stop`.
+pub fn matched_stop_sequence(choice: &Value, stop_sequences: &[String]) -> Option<String> {
+    ["stop_reason", "matched_stop"]
+        .iter()
+        .filter_map(|field| choice.get(*field).and_then(|s| s.as_str()))
+        .find(|matched| stop_sequences.iter().any(|s| s == matched))
+        .map(|matched| matched.to_string())
+}
+
+/// Outcome of forwarding a batch of upstream stream events
+enum StreamStep {
+    Continue,
+    Done,
+    Disconnected,
+}
+
 #[derive(Clone)]
 pub struct ProviderClient {
-    client: reqwest::Client,
+    /// One HTTP client per provider, built with that provider's connect timeout and proxy,
+    /// or the reason it could not be built
+    clients: Arc<HashMap<String, Result<reqwest::Client, ProviderError>>>,
+    default_client: reqwest::Client,
+    keys: Arc<KeyPool>,
+    health: Arc<HealthTracker>,
+    /// `DISPLAY_MODEL`: model name reported in responses instead of the requested one
+    display_model: Option<String>,
 }
 
 impl ProviderClient {
-    pub fn new() -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
-            .user_agent("router/0.1")
+    pub fn new(config: &Config) -> Self {
+        let clients = config
+            .providers
+            .iter()
+            .map(|provider| {
+                let client = Self::build_client(
+                    Timeouts::for_provider(config, provider),
+                    ProxySetting::for_provider(config, provider),
+                    config.no_proxy.as_deref(),
+                );
+                (provider.name.clone(), client)
+            })
+            .collect();
+        Self {
+            clients: Arc::new(clients),
+            default_client: Self::build_client(Timeouts::default(), ProxySetting::System, None)
+                .expect("Failed to build HTTP client"),
+            keys: Arc::new(KeyPool::new(config)),
+            health: Arc::new(HealthTracker::new(config)),
+            display_model: config.display_model.clone(),
+        }
+    }
+
+    /// Circuit state, failures and latency of every provider
+    pub fn health_snapshot(&self) -> Value {
+        self.health.snapshot()
+    }
+
+    /// Drop routes whose provider circuit is open, unless that would leave none:
+    /// then the caller gets a fast `Unavailable` error instead of no attempt at all
+    pub fn skip_open_circuits(&self, routes: Vec<String>) -> Vec<String> {
+        let (open, available): (Vec<String>, Vec<String>) = routes
+            .iter()
+            .cloned()
+            .partition(|route| self.health.is_open(route.split(',').next().unwrap_or_default()));
+        if open.is_empty() || available.is_empty() {
+            return routes;
+        }
+        log::info!("🚧 Skipping routes with open circuits: {}", open.join(", "));
+        available
+    }
+
+    /// Periodically send a one-token request to providers whose circuit is open and due
+    /// for a trial, so they recover without risking user traffic. Returns None when
+    /// probing is disabled (`HEALTH.probe_interval_ms` is 0).
+    pub fn spawn_health_probe(&self, config: Config) -> Option<tokio::task::JoinHandle<()>> {
+        let interval = health::probe_interval(&config)?;
+        let client = self.clone();
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                for name in client.health.due_for_probe() {
+                    client.probe(&name, &config).await;
+                }
+            }
+        }))
+    }
+
+    async fn probe(&self, provider_name: &str, config: &Config) {
+        let Some(model) = config
+            .providers
+            .iter()
+            .find(|p| p.name == provider_name)
+            .and_then(|p| p.models.first())
+        else {
+            return;
+        };
+        let probe_req = ClaudeRequest {
+            model: model.clone(),
+            messages: vec![Message { role: "user".to_string(), content: json!("ping") }],
+            max_tokens: Some(1),
+            ..Default::default()
+        };
+        let messages = MessageTransformer::transform_messages_to_openai(&probe_req.messages);
+        let route = format!("{},{}", provider_name, model);
+        match self.send_claude_request(&route, &probe_req, config, messages, None).await {
+            Ok(_) => log::info!("🩺 Probe of provider {} succeeded", provider_name),
+            Err(e) => log::warn!("🩺 Probe of provider {} failed: {}", provider_name, e),
+        }
+    }
+
+    /// Per-key usage counters of every provider, keys masked
+    pub fn key_usage(&self) -> Value {
+        self.keys.usage()
+    }
+
+    /// A bad proxy URL only fails requests to the provider using it; load_config() reports
+    /// it earlier, but configs built in code never went through it
+    fn build_client(
+        timeouts: Timeouts,
+        proxy: ProxySetting,
+        no_proxy: Option<&str>,
+    ) -> Result<reqwest::Client, ProviderError> {
+        let builder = reqwest::Client::builder()
+            .connect_timeout(timeouts.connect)
+            .user_agent("router/0.1");
+        let builder = match proxy {
+            ProxySetting::System => builder,
+            ProxySetting::Direct => builder.no_proxy(),
+            ProxySetting::Proxy(url) => {
+                let proxy = proxy::build_proxy(&url, no_proxy).map_err(ProviderError::Config)?;
+                log::debug!("Using proxy {}", proxy::redact(&url));
+                builder.proxy(proxy)
+            }
+        };
+        builder
             .build()
-            .expect("Failed to build HTTP client");
-        Self { client }
+            .map_err(|e| ProviderError::Config(format!("failed to build HTTP client: {}", e)))
+    }
+
+    fn client_for(&self, provider: &Provider) -> Result<&reqwest::Client, ProviderError> {
+        match self.clients.get(&provider.name) {
+            Some(Ok(client)) => Ok(client),
+            Some(Err(e)) => Err(ProviderError::Config(format!("Provider '{}' is not usable: {}", provider.name, e))),
+            None => Ok(&self.default_client),
+        }
     }
 
     pub async fn send_request(
@@ -27,24 +222,9 @@ impl ProviderClient {
         request: &RouterRequest,
         config: &Config,
     ) -> Result<Value, Box<dyn std::error::Error>> {
-        // 1. Parse route
-        let (provider_name, model_name) = provider_route
-            .split_once(',')
-            .ok_or("Invalid provider route format: expected \"provider,model\"")?;
+        let (provider, model_name) = Self::resolve_route(provider_route, config)?;
+        let url = Self::chat_completions_url(provider, model_name);
 
-        // 2. Find provider config
-        let provider = config
-            .providers
-            .iter()
-            .find(|p| p.name == provider_name)
-            .ok_or_else(|| format!("Provider '{}' not found in config", provider_name))?;
-
-        // 3. Build request
-        let url = if provider.api_base_url.contains("/chat/completions") {
-            provider.api_base_url.clone()
-        } else {
-            format!("{}{}", provider.api_base_url.trim_end_matches('/'), "/chat/completions")
-        };
         let mut body = json!({
             "model": model_name,
             "messages": request.messages,
@@ -58,100 +238,772 @@ impl ProviderClient {
             body["tools"] = json!(tools);
         }
 
-        let req = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .bearer_auth(&provider.api_key);
+        let resp = self.post_json(&url, provider, config, &body).await?;
+        Self::read_json(resp).await
+    }
+    
+    pub async fn send_claude_request(
+        &self,
+        provider_route: &str,
+        claude_req: &ClaudeRequest,
+        config: &Config,
+        transformed_messages: Vec<Value>,
+        transformed_tools: Option<Vec<Value>>,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let (provider, model_name) = Self::resolve_route(provider_route, config)?;
+        match provider.provider_type {
+            ProviderType::Anthropic => return self.send_anthropic_request(provider, model_name, claude_req, config).await,
+            ProviderType::Gemini => return self.send_gemini_request(provider, model_name, claude_req, config).await,
+            ProviderType::Ollama => return self.send_ollama_request(provider, model_name, claude_req, config).await,
+            ProviderType::Openai | ProviderType::Azure => {}
+        }
+        let url = Self::chat_completions_url(provider, model_name);
+
+        let mut body = Self::build_openai_body(model_name, claude_req, transformed_messages, transformed_tools);
+        body["stream"] = json!(false);
+        ToolNameMap::from_request(claude_req).apply_to_openai_body(&mut body);
+        Self::apply_image_support(&mut body, provider)?;
+        Self::apply_system_prompt(&mut body, provider);
+        Self::apply_reasoning(&mut body, claude_req, provider);
 
-        // 4. Send request
-        let resp = req.json(&body).send().await?;
-        let status = resp.status();
+        // Apply transformers to modify the request
+        self.apply_transformers(&mut body, claude_req, provider)?;
+        // After the transformers, which may convert Claude-format tools
+        schema::sanitize_openai_tools(&mut body, schema::profile_for(provider));
 
-        // 5. Handle response
-        if !status.is_success() {
-            let error_text = resp.text().await?;
-            return Err(format!("HTTP {}: {}", status, error_text).into());
-        }
+        // Debug: Log the complete request being sent to provider
+        log::debug!("Sending request to provider {} at {}", provider.name, url);
+        log::debug!("Complete request body: {}", serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("{:?}", body)));
 
-        let json: Value = resp.json().await?;
-        Ok(json)
+        let resp = self.post_json(&url, provider, config, &body).await?;
+        let mut json = Self::read_json(resp).await?;
+        if let Some(parser) = text_tool_calls::parser_for(provider, claude_req) {
+            parser.extract_from_response(&mut json);
+        }
+        
+        // Convert OpenAI response format to Claude format for compatibility
+        let claude_response = self.convert_openai_to_claude_format(json, claude_req)?;
+        Ok(claude_response)
     }
-    
-    pub async fn send_claude_request(
+
+    /// Send a streaming request and return a channel of Anthropic SSE frames.
+    /// Upstream HTTP errors are reported before the channel is handed out,
+    /// so callers can still answer with a regular error response.
+    pub async fn send_claude_stream_request(
         &self,
         provider_route: &str,
         claude_req: &ClaudeRequest,
         config: &Config,
         transformed_messages: Vec<Value>,
         transformed_tools: Option<Vec<Value>>,
+    ) -> Result<mpsc::Receiver<String>, Box<dyn std::error::Error>> {
+        let (provider, model_name) = Self::resolve_route(provider_route, config)?;
+        match provider.provider_type {
+            ProviderType::Anthropic => {
+                return self.send_anthropic_stream_request(provider, model_name, claude_req, config).await
+            }
+            ProviderType::Gemini => {
+                return self.send_gemini_stream_request(provider, model_name, claude_req, config).await
+            }
+            ProviderType::Ollama => {
+                return self.send_ollama_stream_request(provider, model_name, claude_req, config).await
+            }
+            ProviderType::Openai | ProviderType::Azure => {}
+        }
+        let url = Self::chat_completions_url(provider, model_name);
+
+        let mut body = Self::build_openai_body(model_name, claude_req, transformed_messages, transformed_tools);
+        body["stream"] = json!(true);
+        body["stream_options"] = json!({"include_usage": true});
+        ToolNameMap::from_request(claude_req).apply_to_openai_body(&mut body);
+        Self::apply_image_support(&mut body, provider)?;
+        Self::apply_system_prompt(&mut body, provider);
+        Self::apply_reasoning(&mut body, claude_req, provider);
+
+        self.apply_transformers(&mut body, claude_req, provider)?;
+        schema::sanitize_openai_tools(&mut body, schema::profile_for(provider));
+
+        log::debug!("Sending streaming request to provider {} at {}", provider.name, url);
+        log::debug!("Complete request body: {}", serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("{:?}", body)));
+
+        let resp = self.post_stream(&url, provider, config, &body).await?;
+        let parser = StreamParser::Sse(SseParser::new());
+        Ok(Self::spawn_claude_stream(resp, parser, claude_req, provider, config, |chunk| chunk))
+    }
+
+    /// Convert an upstream event stream (SSE or NDJSON, per `parser`) into Anthropic
+    /// events on a background task.
+    /// `map_chunk` turns each upstream event into an OpenAI `chat.completion.chunk`
+    /// (identity for OpenAI providers) before it reaches the StreamTransformer; providers
+    /// with `text_tool_calls` then pass it through a TextToolCallStream.
+    fn spawn_claude_stream<F>(
+        mut resp: reqwest::Response,
+        mut parser: StreamParser,
+        claude_req: &ClaudeRequest,
+        provider: &Provider,
+        config: &Config,
+        mut map_chunk: F,
+    ) -> mpsc::Receiver<String>
+    where
+        F: FnMut(Value) -> Value + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel::<String>(64);
+        let model = config.display_model.clone().unwrap_or_else(|| claude_req.model.clone());
+        let estimated_input_tokens = Self::estimate_input_tokens(claude_req);
+        let tool_names = ToolNameMap::from_request(claude_req);
+        let tool_schemas = tool_args::input_schemas(claude_req);
+        let mut text_tool_calls = text_tool_calls::parser_for(provider, claude_req).map(TextToolCallStream::new);
+        let stop_sequences = claude_req.stop_sequences.clone().unwrap_or_default();
+        let provider_name = provider.name.clone();
+        let idle_timeout = Timeouts::for_provider(config, provider).stream_idle;
+
+        tokio::spawn(async move {
+            let mut transformer = StreamTransformer::new(&model)
+                .with_stop_sequences(stop_sequences)
+                .with_estimated_input_tokens(estimated_input_tokens)
+                .with_tool_names(tool_names)
+                .with_tool_schemas(tool_schemas);
+
+            loop {
+                let (sse_events, end_of_body) = match Self::next_chunk(&mut resp, idle_timeout, &provider_name).await {
+                    Ok(Some(bytes)) => (parser.feed(&bytes), false),
+                    Ok(None) => (parser.finish().into_iter().collect(), true),
+                    Err(error) => {
+                        log::error!("Stream from provider {} failed: {}", provider_name, error);
+                        let body = anthropic_error_body(error.error_type(), &format!("Upstream stream error: {}", error));
+                        let _ = tx.send(format_sse_event("error", &body)).await;
+                        return;
+                    }
+                };
+
+                match Self::forward_chunks(&sse_events, &mut map_chunk, &mut text_tool_calls, &mut transformer, &tx).await {
+                    StreamStep::Disconnected => return,
+                    StreamStep::Done => break,
+                    StreamStep::Continue if end_of_body => break,
+                    StreamStep::Continue => {}
+                }
+            }
+
+            let held_text = text_tool_calls.as_mut().and_then(TextToolCallStream::finish);
+            let held_events = held_text.map(|chunk| transformer.process_chunk(&chunk)).unwrap_or_default();
+            for (name, data) in held_events.into_iter().chain(transformer.finish()) {
+                if tx.send(format_sse_event(&name, &data)).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        rx
+    }
+
+    /// Send the Claude request to a native Gemini provider via `generateContent`
+    async fn send_gemini_request(
+        &self,
+        provider: &Provider,
+        model_name: &str,
+        claude_req: &ClaudeRequest,
+        config: &Config,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let url = gemini::generate_url(provider, model_name);
+        let body = gemini::build_body(claude_req);
+
+        log::debug!("Sending Gemini request to provider {} at {}", provider.name, url);
+        log::debug!("Complete request body: {}", serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("{:?}", body)));
+
+        let resp = self.post_json(&url, provider, config, &body).await?;
+        let json = Self::read_json(resp).await?;
+        let mut completion = GeminiResponseMapper::new().to_openai_response(&json);
+        if let Some(parser) = text_tool_calls::parser_for(provider, claude_req) {
+            parser.extract_from_response(&mut completion);
+        }
+        self.convert_openai_to_claude_format(completion, claude_req)
+    }
+
+    /// Streaming variant of send_gemini_request using `streamGenerateContent?alt=sse`
+    async fn send_gemini_stream_request(
+        &self,
+        provider: &Provider,
+        model_name: &str,
+        claude_req: &ClaudeRequest,
+        config: &Config,
+    ) -> Result<mpsc::Receiver<String>, Box<dyn std::error::Error>> {
+        let url = gemini::stream_url(provider, model_name);
+        let body = gemini::build_body(claude_req);
+
+        log::debug!("Sending streaming Gemini request to provider {} at {}", provider.name, url);
+
+        let resp = self.post_stream(&url, provider, config, &body).await?;
+        let mut mapper = GeminiResponseMapper::new();
+        let parser = StreamParser::Sse(SseParser::new());
+        Ok(Self::spawn_claude_stream(resp, parser, claude_req, provider, config, move |chunk| {
+            mapper.to_openai_chunk(&chunk)
+        }))
+    }
+
+    /// Send the Claude request to Ollama's native `/api/chat`
+    async fn send_ollama_request(
+        &self,
+        provider: &Provider,
+        model_name: &str,
+        claude_req: &ClaudeRequest,
+        config: &Config,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let url = ollama::chat_url(provider);
+        let body = ollama::build_body(claude_req, model_name, provider, false);
+
+        log::debug!("Sending Ollama request to provider {} at {}", provider.name, url);
+        log::debug!("Complete request body: {}", serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("{:?}", body)));
+
+        let resp = self.post_json(&url, provider, config, &body).await?;
+        let json = Self::read_json(resp).await?;
+        let mut completion = OllamaResponseMapper::new().to_openai_response(&json);
+        if let Some(parser) = text_tool_calls::parser_for(provider, claude_req) {
+            parser.extract_from_response(&mut completion);
+        }
+        self.convert_openai_to_claude_format(completion, claude_req)
+    }
+
+    /// Streaming variant of send_ollama_request; Ollama streams NDJSON, not SSE
+    async fn send_ollama_stream_request(
+        &self,
+        provider: &Provider,
+        model_name: &str,
+        claude_req: &ClaudeRequest,
+        config: &Config,
+    ) -> Result<mpsc::Receiver<String>, Box<dyn std::error::Error>> {
+        let url = ollama::chat_url(provider);
+        let body = ollama::build_body(claude_req, model_name, provider, true);
+
+        log::debug!("Sending streaming Ollama request to provider {} at {}", provider.name, url);
+
+        let resp = self.post_stream(&url, provider, config, &body).await?;
+        let mut mapper = OllamaResponseMapper::new();
+        let parser = StreamParser::Ndjson(NdjsonParser::new());
+        Ok(Self::spawn_claude_stream(resp, parser, claude_req, provider, config, move |chunk| {
+            mapper.to_openai_chunk(&chunk)
+        }))
+    }
+
+    /// Forward the Claude request to a native Anthropic provider; the response
+    /// is already in Messages API format and is returned unchanged
+    async fn send_anthropic_request(
+        &self,
+        provider: &Provider,
+        model_name: &str,
+        claude_req: &ClaudeRequest,
+        config: &Config,
     ) -> Result<Value, Box<dyn std::error::Error>> {
-        // 1. Parse route
+        let url = anthropic::messages_url(provider);
+        let body = anthropic::build_body(claude_req, model_name, false)?;
+
+        log::debug!("Forwarding Anthropic request to provider {} at {}", provider.name, url);
+
+        let resp = self.post_json(&url, provider, config, &body).await?;
+        let mut response = Self::read_json(resp).await?;
+        anthropic::set_model(&mut response, &self.reported_model(claude_req));
+        Ok(response)
+    }
+
+    /// Streaming variant of send_anthropic_request: upstream Anthropic SSE events
+    /// are passed through as they arrive, with the model of `message_start` replaced
+    async fn send_anthropic_stream_request(
+        &self,
+        provider: &Provider,
+        model_name: &str,
+        claude_req: &ClaudeRequest,
+        config: &Config,
+    ) -> Result<mpsc::Receiver<String>, Box<dyn std::error::Error>> {
+        let url = anthropic::messages_url(provider);
+        let body = anthropic::build_body(claude_req, model_name, true)?;
+
+        log::debug!("Forwarding streaming Anthropic request to provider {} at {}", provider.name, url);
+
+        let mut resp = self.post_stream(&url, provider, config, &body).await?;
+
+        let (tx, rx) = mpsc::channel::<String>(64);
+        let model = self.reported_model(claude_req);
+        let provider_name = provider.name.clone();
+        let idle_timeout = Timeouts::for_provider(config, provider).stream_idle;
+
+        tokio::spawn(async move {
+            let mut parser = SseParser::new();
+            loop {
+                let (sse_events, end_of_body) = match Self::next_chunk(&mut resp, idle_timeout, &provider_name).await {
+                    Ok(Some(bytes)) => (parser.feed(&bytes), false),
+                    Ok(None) => (parser.finish().into_iter().collect(), true),
+                    Err(error) => {
+                        log::error!("Stream from provider {} failed: {}", provider_name, error);
+                        let body = anthropic_error_body(error.error_type(), &format!("Upstream stream error: {}", error));
+                        let _ = tx.send(format_sse_event("error", &body)).await;
+                        return;
+                    }
+                };
+
+                for sse_event in &sse_events {
+                    if tx.send(anthropic::format_passthrough_event(sse_event, &model)).await.is_err() {
+                        log::debug!("Client disconnected, dropping stream");
+                        return;
+                    }
+                }
+                if end_of_body {
+                    return;
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Forward a request that is already in OpenAI chat completions format.
+    /// Only the model is rewritten and provider transformers are applied;
+    /// the upstream response is returned unchanged.
+    pub async fn send_openai_request(
+        &self,
+        provider_route: &str,
+        openai_req: &Value,
+        claude_req: &ClaudeRequest,
+        config: &Config,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let (provider, model_name) = Self::resolve_route(provider_route, config)?;
+        Self::require_openai_protocol(provider)?;
+        let url = Self::chat_completions_url(provider, model_name);
+
+        let mut body = openai_req.clone();
+        body["model"] = json!(model_name);
+        body["stream"] = json!(false);
+        Self::apply_image_support(&mut body, provider)?;
+        Self::apply_system_prompt(&mut body, provider);
+        self.apply_transformers(&mut body, claude_req, provider)?;
+
+        log::debug!("Forwarding OpenAI request to provider {} at {}", provider.name, url);
+        log::debug!("Complete request body: {}", serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("{:?}", body)));
+
+        let resp = self.post_json(&url, provider, config, &body).await?;
+        let json = Self::read_json(resp).await?;
+        Ok(json)
+    }
+
+    /// Streaming variant of send_openai_request: upstream `chat.completion.chunk`
+    /// events are re-framed and passed through without conversion
+    pub async fn send_openai_stream_request(
+        &self,
+        provider_route: &str,
+        openai_req: &Value,
+        claude_req: &ClaudeRequest,
+        config: &Config,
+    ) -> Result<mpsc::Receiver<String>, Box<dyn std::error::Error>> {
+        let (provider, model_name) = Self::resolve_route(provider_route, config)?;
+        Self::require_openai_protocol(provider)?;
+        let url = Self::chat_completions_url(provider, model_name);
+
+        let mut body = openai_req.clone();
+        body["model"] = json!(model_name);
+        Self::apply_image_support(&mut body, provider)?;
+        Self::apply_system_prompt(&mut body, provider);
+        body["stream"] = json!(true);
+        self.apply_transformers(&mut body, claude_req, provider)?;
+
+        log::debug!("Forwarding streaming OpenAI request to provider {} at {}", provider.name, url);
+        log::debug!("Complete request body: {}", serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("{:?}", body)));
+
+        let mut resp = self.post_stream(&url, provider, config, &body).await?;
+
+        let (tx, rx) = mpsc::channel::<String>(64);
+        let provider_name = provider.name.clone();
+        let idle_timeout = Timeouts::for_provider(config, provider).stream_idle;
+
+        tokio::spawn(async move {
+            let mut parser = SseParser::new();
+            loop {
+                let (sse_events, end_of_body) = match Self::next_chunk(&mut resp, idle_timeout, &provider_name).await {
+                    Ok(Some(bytes)) => (parser.feed(&bytes), false),
+                    Ok(None) => (parser.finish().into_iter().collect(), true),
+                    Err(error) => {
+                        log::error!("Stream from provider {} failed: {}", provider_name, error);
+                        let body = openai_error_body(error.error_type(), &format!("Upstream stream error: {}", error), error.openai_status_code());
+                        let _ = tx.send(format!("data: {}\n\n", body)).await;
+                        return;
+                    }
+                };
+
+                for sse_event in sse_events {
+                    let done = sse_event.data.trim() == "[DONE]";
+                    if tx.send(format!("data: {}\n\n", sse_event.data)).await.is_err() || done {
+                        return;
+                    }
+                }
+                if end_of_body {
+                    // Some providers close the stream without a [DONE] marker
+                    let _ = tx.send("data: [DONE]\n\n".to_string()).await;
+                    return;
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    /// Read the next body chunk, failing with a Timeout when the provider stays
+    /// silent for longer than `idle_timeout`
+    async fn next_chunk(
+        resp: &mut reqwest::Response,
+        idle_timeout: Duration,
+        provider_name: &str,
+    ) -> Result<Option<hyper::body::Bytes>, ProviderError> {
+        match tokio::time::timeout(idle_timeout, resp.chunk()).await {
+            Ok(chunk) => chunk.map_err(ProviderError::from),
+            Err(_) => Err(ProviderError::Timeout(format!(
+                "provider {} sent no data for {}ms",
+                provider_name,
+                idle_timeout.as_millis()
+            ))),
+        }
+    }
+
+    /// Convert parsed upstream SSE events and push them to the client channel
+    async fn forward_chunks(
+        sse_events: &[SseEvent],
+        map_chunk: &mut impl FnMut(Value) -> Value,
+        text_tool_calls: &mut Option<TextToolCallStream>,
+        transformer: &mut StreamTransformer,
+        tx: &mpsc::Sender<String>,
+    ) -> StreamStep {
+        for sse_event in sse_events {
+            if sse_event.data.trim() == "[DONE]" {
+                return StreamStep::Done;
+            }
+            let chunk: Value = match serde_json::from_str(&sse_event.data) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    log::warn!("Skipping unparseable stream chunk ({}): {}", e, sse_event.data);
+                    continue;
+                }
+            };
+            let mut chunk = map_chunk(chunk);
+            if let Some(text_tool_calls) = text_tool_calls.as_mut() {
+                chunk = text_tool_calls.process_chunk(chunk);
+            }
+            for (name, data) in transformer.process_chunk(&chunk) {
+                if tx.send(format_sse_event(&name, &data)).await.is_err() {
+                    log::debug!("Client disconnected, dropping stream");
+                    return StreamStep::Disconnected;
+                }
+            }
+        }
+        StreamStep::Continue
+    }
+
+    fn resolve_route<'a>(
+        provider_route: &'a str,
+        config: &'a Config,
+    ) -> Result<(&'a Provider, &'a str), Box<dyn std::error::Error>> {
         let (provider_name, model_name) = provider_route
             .split_once(',')
-            .ok_or("Invalid provider route format: expected \"provider,model\"")?;
+            .ok_or_else(|| ProviderError::Config(format!(
+                "Invalid provider route format '{}': expected \"provider,model\"",
+                provider_route
+            )))?;
 
-        // 2. Find provider config
         let provider = config
             .providers
             .iter()
             .find(|p| p.name == provider_name)
-            .ok_or_else(|| format!("Provider '{}' not found in config", provider_name))?;
+            .ok_or_else(|| ProviderError::Config(format!("Provider '{}' not found in config", provider_name)))?;
+        if let Some(error) = &provider.config_error {
+            return Err(ProviderError::Config(format!("Provider '{}' is not usable: {}", provider_name, error)).into());
+        }
+
+        Ok((provider, model_name))
+    }
+
+    /// OpenAI-format requests can only be passed through to OpenAI-compatible providers
+    fn require_openai_protocol(provider: &Provider) -> Result<(), ProviderError> {
+        match provider.provider_type {
+            ProviderType::Openai | ProviderType::Azure => Ok(()),
+            other => Err(ProviderError::Unsupported(format!(
+                "Provider '{}' uses the {:?} protocol and cannot serve /v1/chat/completions requests",
+                provider.name, other
+            ))),
+        }
+    }
 
-        // 3. Build request URL
-        let url = if provider.api_base_url.contains("/chat/completions") {
+    fn chat_completions_url(provider: &Provider, model_name: &str) -> String {
+        if provider.provider_type == ProviderType::Azure {
+            return azure::chat_completions_url(provider, model_name);
+        }
+        if provider.api_base_url.contains("/chat/completions") {
             provider.api_base_url.clone()
         } else {
             format!("{}{}", provider.api_base_url.trim_end_matches('/'), "/chat/completions")
-        };
-        
-        // 4. Build complete request body with all Claude Code fields
+        }
+    }
+
+    /// Build the OpenAI-compatible request body with all Claude Code fields
+    fn build_openai_body(
+        model_name: &str,
+        claude_req: &ClaudeRequest,
+        transformed_messages: Vec<Value>,
+        transformed_tools: Option<Vec<Value>>,
+    ) -> Value {
+        let mut messages = transformed_messages;
+        if let Some(system) = claude_req.system.as_ref().and_then(MessageTransformer::system_message) {
+            messages.insert(0, system);
+        }
         let mut body = json!({
             "model": model_name,
-            "messages": transformed_messages,
+            "messages": messages,
         });
         
-        // Add optional fields from Claude request
         if let Some(max_tokens) = claude_req.max_tokens {
             body["max_tokens"] = json!(max_tokens);
         }
         if let Some(temperature) = claude_req.temperature {
             body["temperature"] = json!(temperature);
         }
-        // Always disable streaming for now - we don't handle streaming responses yet
-        body["stream"] = json!(false);
+        if let Some(top_p) = claude_req.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(top_k) = claude_req.top_k {
+            body["top_k"] = json!(top_k);
+        }
+        if let Some(stop_sequences) = claude_req.stop_sequences.as_ref().filter(|s| !s.is_empty()) {
+            body["stop"] = json!(stop_sequences);
+        }
+        if let Some(user_id) = claude_req
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get("user_id"))
+            .and_then(|u| u.as_str())
+            .filter(|u| !u.is_empty())
+        {
+            body["user"] = json!(user_id);
+        }
         if let Some(tools) = &transformed_tools {
             body["tools"] = json!(tools);
+            // OpenAI rejects tool_choice and parallel_tool_calls without tools
+            if let Some(tool_choice) = &claude_req.tool_choice {
+                if let Some(choice) = MessageTransformer::tool_choice_to_openai(tool_choice) {
+                    body["tool_choice"] = choice;
+                }
+                if tool_choice.get("disable_parallel_tool_use").and_then(|d| d.as_bool()) == Some(true) {
+                    body["parallel_tool_calls"] = json!(false);
+                }
+            }
         }
-        
-        // Apply transformers to modify the request
-        self.apply_transformers(&mut body, claude_req, provider)?;
+        body
+    }
 
-        // Debug: Log the complete request being sent to provider
-        log::debug!("Sending request to provider {} at {}", provider_name, url);
-        log::debug!("Complete request body: {}", serde_json::to_string_pretty(&body).unwrap_or_else(|_| format!("{:?}", body)));
+    /// POST a JSON body for a complete response; the total timeout covers reading the body
+    async fn post_json(
+        &self,
+        url: &str,
+        provider: &Provider,
+        config: &Config,
+        body: &Value,
+    ) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        self.post(url, provider, config, body, false).await
+    }
 
-        let req = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .bearer_auth(&provider.api_key);
+    /// POST a JSON body for a streamed response. The total timeout only covers getting the
+    /// response headers; the body is bounded by the stream idle timeout in `next_chunk()`,
+    /// so a stream that keeps producing tokens is never cut off.
+    async fn post_stream(
+        &self,
+        url: &str,
+        provider: &Provider,
+        config: &Config,
+        body: &Value,
+    ) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        self.post(url, provider, config, body, true).await
+    }
+
+    /// POST a JSON body to the provider, retrying transient failures according
+    /// to the provider's retry policy. The circuit breaker admits the request once and
+    /// sees one outcome per request, whatever the number of attempts; when all attempts
+    /// fail the last upstream error is returned.
+    async fn post(
+        &self,
+        url: &str,
+        provider: &Provider,
+        config: &Config,
+        body: &Value,
+        stream: bool,
+    ) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        let policy = RetryPolicy::for_provider(config, provider);
+        let timeouts = Timeouts::for_provider(config, provider);
+        let mut attempt = 1;
+        let mut key_switches = 0;
 
-        // 5. Send request
-        let resp = req.json(&body).send().await?;
-        let status = resp.status();
+        let client = self.client_for(provider)?;
+        self.health.try_acquire(provider)?;
+        loop {
+            let started = Instant::now();
+            let lease = self.keys.acquire(provider);
+            let request = client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .json(body);
+            let request = if stream { request } else { request.timeout(timeouts.total) };
+            let sent = Self::authorize(request, provider, &lease.key).send();
+            let response = if stream {
+                match tokio::time::timeout(timeouts.total, sent).await {
+                    Ok(result) => result.map_err(|e| Self::request_error(e, provider, timeouts)),
+                    Err(_) => Err(Self::response_timeout(provider, timeouts)),
+                }
+            } else {
+                sent.await.map_err(|e| Self::request_error(e, provider, timeouts))
+            };
+            let (error, server_delay) = match response {
+                Ok(resp) if resp.status().is_success() => {
+                    self.keys.report(provider, &lease, KeyOutcome::Success);
+                    self.health.record_success(provider, started.elapsed());
+                    return Ok(resp);
+                }
+                Ok(resp) => {
+                    let status = resp.status();
+                    let server_delay = retry::server_requested_delay(status.as_u16(), resp.headers());
+                    let retry_after = resp
+                        .headers()
+                        .get("retry-after")
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| v.to_string());
+                    let error_text = resp.text().await.unwrap_or_default();
+                    log::error!("HTTP {} from provider {}: {}", status, provider.name, error_text);
+                    (ProviderError::from_response(status.as_u16(), &error_text, retry_after), server_delay)
+                }
+                Err(error) => (error, None),
+            };
+
+            let outcome = match &error {
+                ProviderError::Http { status: 429, .. } => KeyOutcome::RateLimited(server_delay),
+                ProviderError::Http { status: 401, .. } => KeyOutcome::Unauthorized,
+                _ => KeyOutcome::Failed,
+            };
+            self.keys.report(provider, &lease, outcome);
+
+            // A limited or rejected key says nothing about the provider: move straight
+            // to the next usable key without spending a retry attempt
+            if matches!(outcome, KeyOutcome::RateLimited(_) | KeyOutcome::Unauthorized)
+                && key_switches + 1 < self.keys.key_count(provider)
+                && self.keys.has_usable_key(provider)
+            {
+                log::warn!("🔑 Switching key for provider {}: {}", provider.name, error);
+                key_switches += 1;
+                continue;
+            }
 
-        if !status.is_success() {
-            let error_text = resp.text().await.unwrap_or_default();
-            log::error!("HTTP {}: {}", status, error_text);
-            return Err(format!("HTTP {}: {}", status, error_text).into());
+            match policy.delay_before_retry(attempt, &error, server_delay) {
+                Some(delay) => {
+                    log::warn!(
+                        "🔁 Retrying provider {} in {:?} (attempt {}/{}): {}",
+                        provider.name, delay, attempt + 1, policy.max_attempts, error
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => {
+                    self.health.record_error(provider, &error);
+                    return Err(error.into());
+                }
+            }
         }
+    }
 
-        let json: Value = resp.json().await?;
-        
-        // Convert OpenAI response format to Claude format for compatibility
-        let claude_response = self.convert_openai_to_claude_format(json)?;
-        Ok(claude_response)
+    /// Add the authentication headers the provider's protocol expects
+    fn authorize(request: reqwest::RequestBuilder, provider: &Provider, key: &str) -> reqwest::RequestBuilder {
+        match provider.provider_type {
+            ProviderType::Openai => request.bearer_auth(key),
+            ProviderType::Anthropic => request
+                .header("x-api-key", key)
+                .header("anthropic-version", anthropic::ANTHROPIC_VERSION),
+            ProviderType::Gemini => request.header("x-goog-api-key", key),
+            ProviderType::Azure => request.header("api-key", key),
+            // Local Ollama servers need no key; one behind an authenticating proxy may
+            ProviderType::Ollama if key.is_empty() => request,
+            ProviderType::Ollama => request.bearer_auth(key),
+        }
+    }
+
+    /// Enforce the provider's `images` setting on an OpenAI request body: text-only
+    /// providers get placeholders or a clear error instead of `image_url` parts
+    fn apply_image_support(body: &mut Value, provider: &Provider) -> Result<(), ProviderError> {
+        let Some(messages) = body.get_mut("messages").and_then(|m| m.as_array_mut()) else {
+            return Ok(());
+        };
+        match provider.images.unwrap_or_default() {
+            ImageSupport::Native => Ok(()),
+            ImageSupport::Placeholder => {
+                MessageTransformer::replace_images_with_placeholder(messages);
+                Ok(())
+            }
+            ImageSupport::Reject => match MessageTransformer::count_images(messages) {
+                0 => Ok(()),
+                count => Err(ProviderError::Unsupported(format!(
+                    "provider {} does not accept images ({} in this request); route image requests to a vision model",
+                    provider.name, count
+                ))),
+            },
+        }
+    }
+
+    /// Merge system messages into the first user message for providers configured with
+    /// `system_prompt: merge_into_user`
+    fn apply_system_prompt(body: &mut Value, provider: &Provider) {
+        if provider.system_prompt.unwrap_or_default() != SystemPrompt::MergeIntoUser {
+            return;
+        }
+        if let Some(messages) = body.get_mut("messages").and_then(|m| m.as_array_mut()) {
+            MessageTransformer::merge_system_into_first_user(messages);
+        }
+    }
+
+    /// Forward an enabled `thinking` setting in the request field the provider understands
+    fn apply_reasoning(body: &mut Value, claude_req: &ClaudeRequest, provider: &Provider) {
+        let Some(thinking) = claude_req.thinking.as_ref().and_then(ThinkingConfig::from_value) else {
+            return;
+        };
+        match provider.reasoning.unwrap_or_default() {
+            ReasoningParam::None => {}
+            ReasoningParam::ReasoningEffort => body["reasoning_effort"] = json!(thinking.reasoning_effort()),
+            ReasoningParam::Reasoning => body["reasoning"] = thinking.reasoning_object(),
+        }
+    }
+
+    /// Map a failed send, naming the provider and the limit that was hit on timeouts
+    fn request_error(e: reqwest::Error, provider: &Provider, timeouts: Timeouts) -> ProviderError {
+        if !e.is_timeout() {
+            return ProviderError::from(e);
+        }
+        if e.is_connect() {
+            return ProviderError::Timeout(format!(
+                "provider {} could not be reached within {}ms",
+                provider.name,
+                timeouts.connect.as_millis()
+            ));
+        }
+        Self::response_timeout(provider, timeouts)
+    }
+
+    /// The total timeout ran out before the response (or, when not streaming, its body) arrived
+    fn response_timeout(provider: &Provider, timeouts: Timeouts) -> ProviderError {
+        ProviderError::Timeout(format!(
+            "provider {} did not respond within {}ms",
+            provider.name,
+            timeouts.total.as_millis()
+        ))
+    }
+
+    /// Model name reported to the client: `DISPLAY_MODEL`, else the requested model
+    fn reported_model(&self, claude_req: &ClaudeRequest) -> String {
+        self.display_model.clone().unwrap_or_else(|| claude_req.model.clone())
+    }
+
+    async fn read_json(resp: reqwest::Response) -> Result<Value, Box<dyn std::error::Error>> {
+        let json: Value = resp
+            .json()
+            .await
+            .map_err(|e| ProviderError::InvalidResponse(e.to_string()))?;
+        Ok(json)
     }
     
     fn apply_transformers(
@@ -195,81 +1047,153 @@ impl ProviderClient {
         }
     }
     
-    pub fn convert_openai_to_claude_format(&self, openai_response: Value) -> Result<Value, Box<dyn std::error::Error>> {
-        let mut claude_response = json!({
-            "type": "message",
-            "role": "assistant",
-            "content": []
-        });
-        
-        // Handle choices
-        if let Some(choices) = openai_response.get("choices").and_then(|v| v.as_array()) {
-            if let Some(first_choice) = choices.first() {
-                // Handle message content
-                if let Some(message) = first_choice.get("message") {
-                    let mut content_blocks = vec![];
-                    
-                    // Handle text content
-                    if let Some(content) = message.get("content").and_then(|v| v.as_str()) {
-                        if !content.is_empty() {
-                            content_blocks.push(json!({
-                                "type": "text",
-                                "text": content
-                            }));
-                        }
-                    }
-                    
-                    // Handle tool calls
-                    if let Some(tool_calls) = message.get("tool_calls").and_then(|v| v.as_array()) {
-                        for tool_call in tool_calls {
-                            if let (Some(id), Some(tool_type), Some(function)) = (
-                                tool_call.get("id").and_then(|v| v.as_str()),
-                                tool_call.get("type").and_then(|v| v.as_str()),
-                                tool_call.get("function")
-                            ) {
-                                if tool_type == "function" {
-                                    let name = function.get("name").and_then(|v| v.as_str()).unwrap_or("");
-                                    let arguments = function.get("arguments").and_then(|v| v.as_str()).unwrap_or("{}");
-                                    
-                                    content_blocks.push(json!({
-                                        "type": "tool_use",
-                                        "id": id,
-                                        "name": name,
-                                        "input": serde_json::from_str::<Value>(arguments).unwrap_or(json!({}))
-                                    }));
-                                }
-                            }
-                        }
+    /// Convert an OpenAI chat completion into an Anthropic Messages response. Content from
+    /// every choice is merged in order (some providers put text and tool calls in separate
+    /// choices); usage is estimated when the provider sent none.
+    pub fn convert_openai_to_claude_format(
+        &self,
+        openai_response: Value,
+        claude_req: &ClaudeRequest,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let choices = openai_response
+            .get("choices")
+            .and_then(|v| v.as_array())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let stop_sequences = claude_req.stop_sequences.as_deref().unwrap_or_default();
+
+        let tool_names = ToolNameMap::from_request(claude_req);
+        let mut content_blocks = vec![];
+        let mut stop_reasons = vec![];
+        let mut stop_sequence = None;
+        let mut refused = false;
+        for choice in choices {
+            if let Some(message) = choice.get("message") {
+                refused |= Self::append_message_blocks(message, claude_req, &tool_names, &mut content_blocks)?;
+            }
+            if let Some(finish_reason) = choice.get("finish_reason").and_then(|v| v.as_str()) {
+                match matched_stop_sequence(choice, stop_sequences) {
+                    Some(matched) if finish_reason == "stop" && stop_sequence.is_none() => {
+                        stop_sequence = Some(matched);
+                        stop_reasons.push("stop_sequence".to_string());
                     }
-                    
-                    claude_response["content"] = json!(content_blocks);
-                }
-                
-                // Handle finish reason
-                if let Some(finish_reason) = first_choice.get("finish_reason").and_then(|v| v.as_str()) {
-                    let mapped_reason = match finish_reason {
-                        "stop" => "end_turn",
-                        "length" => "max_tokens",
-                        "tool_calls" => "tool_use",
-                        _ => finish_reason
-                    };
-                    claude_response["stop_reason"] = json!(mapped_reason);
+                    _ => stop_reasons.push(map_finish_reason(finish_reason)),
                 }
             }
         }
-        
-        // Handle usage
-        if let Some(usage) = openai_response.get("usage") {
-            let mut usage_map = json!({});
-            if let Some(prompt_tokens) = usage.get("prompt_tokens") {
-                usage_map["input_tokens"] = prompt_tokens.clone();
+
+        let has_tool_use = content_blocks.iter().any(|b| b["type"] == "tool_use");
+        let stop_reason = if refused {
+            "refusal".to_string()
+        } else if stop_reasons.iter().any(|r| r == "max_tokens" || r == "refusal") {
+            stop_reasons.into_iter().find(|r| r == "max_tokens" || r == "refusal").unwrap_or_default()
+        } else if has_tool_use {
+            "tool_use".to_string()
+        } else {
+            stop_reasons.into_iter().next().unwrap_or_else(|| "end_turn".to_string())
+        };
+        if stop_reason != "stop_sequence" {
+            stop_sequence = None;
+        }
+
+        let usage = match openai_response.get("usage").filter(|u| u.is_object()) {
+            Some(usage) => map_usage(usage),
+            None => Self::estimate_usage(claude_req, &content_blocks),
+        };
+
+        Ok(json!({
+            "id": format!("msg_{}", uuid::Uuid::new_v4().simple()),
+            "type": "message",
+            "role": "assistant",
+            "model": self.reported_model(claude_req),
+            "content": content_blocks,
+            "stop_reason": stop_reason,
+            "stop_sequence": stop_sequence,
+            "usage": usage
+        }))
+    }
+
+    /// Append the Claude content blocks of one OpenAI message: a signed thinking block for
+    /// reasoning, then text, refusal text and tool calls (with their original names and
+    /// repaired arguments). Returns whether it was a refusal, or an InvalidResponse error for
+    /// tool arguments that cannot be repaired.
+    fn append_message_blocks(
+        message: &Value,
+        claude_req: &ClaudeRequest,
+        tool_names: &ToolNameMap,
+        content_blocks: &mut Vec<Value>,
+    ) -> Result<bool, ProviderError> {
+        // Handle reasoning, which Claude clients expect as a signed thinking block first
+        if let Some(reasoning) = thinking::reasoning_text(message) {
+            content_blocks.push(json!({
+                "type": "thinking",
+                "thinking": reasoning,
+                "signature": thinking::signature(reasoning)
+            }));
+        }
+
+        // Handle text content
+        if let Some(content) = message.get("content").and_then(|v| v.as_str()) {
+            if !content.is_empty() {
+                content_blocks.push(json!({
+                    "type": "text",
+                    "text": content
+                }));
             }
-            if let Some(completion_tokens) = usage.get("completion_tokens") {
-                usage_map["output_tokens"] = completion_tokens.clone();
+        }
+
+        // OpenAI reports refusals in a separate field; Claude shows them as text
+        let refusal = message.get("refusal").and_then(|v| v.as_str()).filter(|r| !r.is_empty());
+        if let Some(refusal) = refusal {
+            content_blocks.push(json!({
+                "type": "text",
+                "text": refusal
+            }));
+        }
+
+        // Handle tool calls
+        if let Some(tool_calls) = message.get("tool_calls").and_then(|v| v.as_array()) {
+            for tool_call in tool_calls {
+                if let (Some(id), Some(tool_type), Some(function)) = (
+                    tool_call.get("id").and_then(|v| v.as_str()),
+                    tool_call.get("type").and_then(|v| v.as_str()),
+                    tool_call.get("function")
+                ) {
+                    if tool_type == "function" {
+                        let name = tool_names.restore(function.get("name").and_then(|v| v.as_str()).unwrap_or(""));
+                        let arguments = function.get("arguments").and_then(|v| v.as_str()).unwrap_or("{}");
+                        let input = tool_args::parse_tool_input(arguments, tool_args::input_schema(claude_req, name))
+                            .map_err(|e| {
+                                ProviderError::InvalidResponse(format!("tool call `{}` ({}): {}", name, id, e))
+                            })?;
+
+                        content_blocks.push(json!({
+                            "type": "tool_use",
+                            "id": id,
+                            "name": name,
+                            "input": input
+                        }));
+                    }
+                }
             }
-            claude_response["usage"] = usage_map;
         }
-        
-        Ok(claude_response)
+
+        Ok(refusal.is_some())
+    }
+
+    /// Rough input tokens of a request, counted like `/v1/messages/count_tokens` does
+    fn estimate_input_tokens(claude_req: &ClaudeRequest) -> u64 {
+        let router_request = server::build_router_request(claude_req, server::parse_claude_tools(claude_req));
+        Router::estimate_tokens(&router_request) as u64
+    }
+
+    /// Usage for a response that came without one
+    fn estimate_usage(claude_req: &ClaudeRequest, content_blocks: &[Value]) -> Value {
+        json!({
+            "input_tokens": Self::estimate_input_tokens(claude_req),
+            "output_tokens": Router::estimate_content_tokens(&Value::Array(content_blocks.to_vec())),
+            "cache_creation_input_tokens": 0,
+            "cache_read_input_tokens": 0
+        })
     }
 }
\ No newline at end of file
@@ -0,0 +1,296 @@
+use reqwest::header::HeaderMap;
+use std::time::{Duration, SystemTime};
+
+use crate::config::{Config, Provider, RetryConfig};
+use crate::error::ProviderError;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_BASE_DELAY_MS: u64 = 500;
+const DEFAULT_MAX_DELAY_MS: u64 = 10_000;
+/// Ceiling for delays read from provider headers; anything longer already exceeds every
+/// sensible `max_delay`, so `delay_before_retry` gives up on it either way
+const MAX_SERVER_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Effective retry settings for one provider: provider overrides win over the
+/// global `RETRY` block, which wins over the built-in defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+            max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn for_provider(config: &Config, provider: &Provider) -> Self {
+        let global = config.retry.as_ref();
+        let local = provider.retry.as_ref();
+        let pick = |field: fn(&RetryConfig) -> Option<u64>| {
+            local.and_then(field).or_else(|| global.and_then(field))
+        };
+
+        let defaults = Self::default();
+        Self {
+            max_attempts: pick(|r| r.max_attempts.map(u64::from))
+                .map(|v| v.max(1) as u32)
+                .unwrap_or(defaults.max_attempts),
+            base_delay: pick(|r| r.base_delay_ms).map(Duration::from_millis).unwrap_or(defaults.base_delay),
+            max_delay: pick(|r| r.max_delay_ms).map(Duration::from_millis).unwrap_or(defaults.max_delay),
+        }
+    }
+
+    /// Jittered exponential backoff for the given (1-based) failed attempt:
+    /// a random delay between half and all of `base * 2^(attempt-1)`, capped at `max_delay`
+    pub fn backoff_delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let ceiling = self.base_delay.saturating_mul(1 << exponent).min(self.max_delay);
+        let ceiling_ms = ceiling.as_millis() as u64;
+        Duration::from_millis(fastrand::u64(ceiling_ms / 2..=ceiling_ms))
+    }
+
+    /// How long to wait before retrying after `error` on the given attempt,
+    /// or None when the request should not be retried on this provider.
+    /// A server-requested delay longer than `max_delay` also gives up, leaving the
+    /// decision to fallback routes or the client.
+    pub fn delay_before_retry(
+        &self,
+        attempt: u32,
+        error: &ProviderError,
+        server_delay: Option<Duration>,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || !is_transient(error) {
+            return None;
+        }
+        match server_delay {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff_delay(attempt)),
+        }
+    }
+}
+
+/// Failures worth repeating against the same provider: connection resets and
+/// 429/500/502/503/504/529 responses. Timeouts are left to fallback routes.
+pub fn is_transient(error: &ProviderError) -> bool {
+    match error {
+        ProviderError::Http { status, .. } => matches!(status, 429 | 500 | 502 | 503 | 504 | 529),
+        ProviderError::Connection(_) => true,
+        _ => false,
+    }
+}
+
+/// Delay requested by the provider through `Retry-After` (seconds or HTTP date) or, for
+/// 429 responses, the OpenAI-style `x-ratelimit-reset-requests` / `x-ratelimit-reset-tokens`
+/// headers. Those come on every response and only say when a window resets, so they are
+/// read for rate limits alone: the reset of the exhausted limit (`x-ratelimit-remaining-*`
+/// of `0`) wins, otherwise the soonest reset.
+pub fn server_requested_delay(status: u16, headers: &HeaderMap) -> Option<Duration> {
+    if let Some(value) = headers.get("retry-after").and_then(|v| v.to_str().ok()) {
+        if let Some(delay) = parse_retry_after(value) {
+            return Some(delay);
+        }
+    }
+    if status != 429 {
+        return None;
+    }
+
+    let header = |name: String| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+    let resets: Vec<(bool, Duration)> = ["requests", "tokens"]
+        .iter()
+        .filter_map(|limit| {
+            let reset = header(format!("x-ratelimit-reset-{}", limit)).and_then(parse_reset_duration)?;
+            let exhausted = header(format!("x-ratelimit-remaining-{}", limit)) == Some("0");
+            Some((exhausted, reset))
+        })
+        .collect();
+
+    let exhausted = resets.iter().filter(|(exhausted, _)| *exhausted).map(|(_, reset)| *reset).max();
+    exhausted.or_else(|| resets.iter().map(|(_, reset)| *reset).min())
+}
+
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return seconds_to_delay(seconds);
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO).min(MAX_SERVER_DELAY))
+}
+
+/// Parse durations such as "1s", "6m0s", "2m59.56s", "20ms" or a bare number of seconds
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return seconds_to_delay(seconds);
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        let unit_seconds = match c {
+            'h' => 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                0.001
+            }
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return None,
+        };
+        total += amount * unit_seconds;
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    seconds_to_delay(total)
+}
+
+/// Header-supplied seconds as a delay capped at `MAX_SERVER_DELAY`. Infinite or
+/// out-of-range values such as `inf` or `1e30` saturate instead of panicking.
+fn seconds_to_delay(seconds: f64) -> Option<Duration> {
+    if seconds.is_nan() || seconds < 0.0 {
+        return None;
+    }
+    Some(Duration::try_from_secs_f64(seconds).map_or(MAX_SERVER_DELAY, |delay| delay.min(MAX_SERVER_DELAY)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use serde_json::json;
+
+    fn policy(max_attempts: u32, base_ms: u64, max_ms: u64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(base_ms),
+            max_delay: Duration::from_millis(max_ms),
+        }
+    }
+
+    #[test]
+    fn test_policy_resolution() {
+        let config: Config = serde_json::from_value(json!({
+            "Providers": [
+                {"name": "a", "api_base_url": "http://a", "api_key": "k", "models": [], "retry": {"max_attempts": 5}},
+                {"name": "b", "api_base_url": "http://b", "api_key": "k", "models": []}
+            ],
+            "Router": {"default": "a,m"},
+            "RETRY": {"max_attempts": 2, "base_delay_ms": 100}
+        })).unwrap();
+
+        let a = RetryPolicy::for_provider(&config, &config.providers[0]);
+        assert_eq!(a.max_attempts, 5);
+        assert_eq!(a.base_delay, Duration::from_millis(100));
+        assert_eq!(a.max_delay, Duration::from_millis(DEFAULT_MAX_DELAY_MS));
+
+        let b = RetryPolicy::for_provider(&config, &config.providers[1]);
+        assert_eq!(b.max_attempts, 2);
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = policy(10, 100, 1000);
+        for _ in 0..20 {
+            let first = policy.backoff_delay(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = policy.backoff_delay(3);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            assert!(policy.backoff_delay(12) <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn test_delay_decision() {
+        let policy = policy(3, 100, 5000);
+        let overloaded = ProviderError::from_response(529, "", None);
+        let bad_request = ProviderError::from_response(400, "", None);
+
+        assert!(policy.delay_before_retry(1, &overloaded, None).is_some());
+        assert!(policy.delay_before_retry(3, &overloaded, None).is_none());
+        assert!(policy.delay_before_retry(1, &bad_request, None).is_none());
+        assert!(policy.delay_before_retry(1, &ProviderError::Timeout("slow".into()), None).is_none());
+        assert_eq!(
+            policy.delay_before_retry(1, &overloaded, Some(Duration::from_secs(2))),
+            Some(Duration::from_secs(2))
+        );
+        assert!(policy.delay_before_retry(1, &overloaded, Some(Duration::from_secs(60))).is_none());
+    }
+
+    #[test]
+    fn test_server_requested_delay_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("3"));
+        assert_eq!(server_requested_delay(429, &headers), Some(Duration::from_secs(3)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-reset-requests", HeaderValue::from_static("2m59.5s"));
+        headers.insert("x-ratelimit-reset-tokens", HeaderValue::from_static("250ms"));
+        assert_eq!(server_requested_delay(429, &headers), Some(Duration::from_millis(250)));
+        // Window resets on a server error are not a request to wait
+        assert_eq!(server_requested_delay(503, &headers), None);
+        let overloaded = ProviderError::from_response(503, "", None);
+        assert!(policy(3, 100, 5000)
+            .delay_before_retry(1, &overloaded, server_requested_delay(503, &headers))
+            .is_some());
+
+        // Only the token limit is exhausted
+        headers.insert("x-ratelimit-remaining-requests", HeaderValue::from_static("499"));
+        headers.insert("x-ratelimit-remaining-tokens", HeaderValue::from_static("0"));
+        assert_eq!(server_requested_delay(429, &headers), Some(Duration::from_millis(250)));
+        headers.insert("x-ratelimit-remaining-requests", HeaderValue::from_static("0"));
+        headers.insert("x-ratelimit-remaining-tokens", HeaderValue::from_static("1200"));
+        assert_eq!(server_requested_delay(429, &headers), Some(Duration::from_secs_f64(179.5)));
+
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_str(&date).unwrap());
+        let delay = server_requested_delay(429, &headers).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+
+        assert_eq!(server_requested_delay(429, &HeaderMap::new()), None);
+
+        for value in ["inf", "1e30", "99999999999999999999"] {
+            let mut headers = HeaderMap::new();
+            headers.insert("retry-after", HeaderValue::from_static(value));
+            let delay = server_requested_delay(429, &headers);
+            assert_eq!(delay, Some(MAX_SERVER_DELAY));
+            let overloaded = ProviderError::from_response(529, "", None);
+            assert!(policy(3, 100, 5000).delay_before_retry(1, &overloaded, delay).is_none());
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("NaN"));
+        assert_eq!(server_requested_delay(429, &headers), None);
+    }
+
+    #[test]
+    fn test_parse_reset_duration() {
+        assert_eq!(parse_reset_duration("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_reset_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset_duration("1h2m"), Some(Duration::from_secs(3720)));
+        assert_eq!(parse_reset_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset_duration("0.5"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_reset_duration("soon"), None);
+        assert_eq!(parse_reset_duration("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_reset_duration("inf"), Some(MAX_SERVER_DELAY));
+        assert_eq!(parse_reset_duration("1e30"), Some(MAX_SERVER_DELAY));
+        assert_eq!(parse_reset_duration("99999999999999999999"), Some(MAX_SERVER_DELAY));
+        assert_eq!(parse_reset_duration("99999999999999999999s"), Some(MAX_SERVER_DELAY));
+    }
+}
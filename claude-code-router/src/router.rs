@@ -1,11 +1,21 @@
 use log;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::balancer::Balancer;
 use crate::config::Config;
 
+/// Rough token cost of an image block (Anthropic bills ~1600 tokens for a typical screenshot)
+const IMAGE_TOKENS: usize = 1600;
+/// Rough token cost of a binary (e.g. PDF) document block
+const DOCUMENT_TOKENS: usize = 3000;
+
 #[derive(Debug, Clone)]
 pub struct Router {
     config: Config,
+    /// Model pool state, shared by all clones of the router
+    balancer: Arc<Balancer>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,6 +25,9 @@ pub struct RouterRequest {
     pub system: Option<Value>,
     pub tools: Option<Vec<ClaudeTool>>,
     pub thinking: Option<bool>,
+    /// Identifies the conversation so pooled routes can stick to one backend
+    #[serde(default)]
+    pub conversation: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,35 +39,52 @@ pub struct Message {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClaudeTool {
     pub name: String,
+    /// Optional in the Claude API, and absent on server tools such as `web_search`
+    #[serde(default)]
     pub description: String,
+    #[serde(default)]
     pub input_schema: Value,
 }
 
 impl Router {
     pub fn new(config: Config) -> Self {
-        Router { config }
+        let balancer = Arc::new(Balancer::new(&config.router));
+        Router { config, balancer }
+    }
+
+    /// Pick the routes for a request: the scenario's primary "provider,model" route
+    /// first, followed by its fallbacks in the configured order. Model pool names are
+    /// expanded into their members, the one chosen for this conversation first.
+    pub fn route_request(&self, request: &RouterRequest) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let routes = self
+            .balancer
+            .expand(self.determine_route(request), request.conversation.as_deref());
+        log::debug!("Routing decision: {}", routes.join(" -> "));
+        if routes.is_empty() {
+            return Err("No route configured for this request".into());
+        }
+        Ok(routes)
     }
 
-    pub fn route_request(&self, request: &RouterRequest) -> Result<String, Box<dyn std::error::Error>> {
-        let route = self.determine_route(request);
-        log::debug!("Routing decision: {}", route);
-        Ok(route)
+    /// Feed the outcome of a request to `route` back into pool weights and stickiness
+    pub fn record_result(&self, request: &RouterRequest, route: &str, latency: Duration, success: bool) {
+        self.balancer.record(route, request.conversation.as_deref(), latency, success);
     }
 
-    fn determine_route(&self, request: &RouterRequest) -> String {
+    fn determine_route(&self, request: &RouterRequest) -> Vec<String> {
         // 1. Direct model specification
         if let Some(model) = &request.model {
             if model.contains(',') {
-                return model.clone();
+                return vec![model.clone()];
             }
         }
 
         // 2. Token count check
-        let token_count = self.estimate_tokens(request);
+        let token_count = Self::estimate_tokens(request);
         if token_count > 60_000 {
             if let Some(ref long_context) = self.config.router.long_context {
                 if !long_context.is_empty() {
-                    return long_context.clone();
+                    return long_context.routes();
                 }
             }
         }
@@ -64,7 +94,7 @@ impl Router {
             if model.contains("claude-3-5-haiku") {
                 if let Some(ref background) = self.config.router.background {
                     if !background.is_empty() {
-                        return background.clone();
+                        return background.routes();
                     }
                 }
             }
@@ -74,7 +104,7 @@ impl Router {
         if request.thinking.unwrap_or(false) {
             if let Some(ref think) = self.config.router.think {
                 if !think.is_empty() {
-                    return think.clone();
+                    return think.routes();
                 }
             }
         }
@@ -84,50 +114,35 @@ impl Router {
             if tools.iter().any(|t| t.name.starts_with("web_search")) {
                 if let Some(ref web_search) = self.config.router.web_search {
                     if !web_search.is_empty() {
-                        return web_search.clone();
+                        return web_search.routes();
                     }
                 }
             }
         }
 
         // 6. Default route
-        self.config.router.default.clone()
+        self.config.router.default.routes()
     }
 
-    fn estimate_tokens(&self, request: &RouterRequest) -> usize {
+    /// Approximate the number of input tokens for a request, counting the system prompt,
+    /// tool definitions and every content block type (~4 characters per token)
+    pub fn estimate_tokens(request: &RouterRequest) -> usize {
         let mut chars = 0;
+        let mut fixed_tokens = 0;
 
         // Messages
         for msg in &request.messages {
-            match &msg.content {
-                Value::String(s) => chars += s.len(),
-                Value::Array(arr) => {
-                    for item in arr {
-                        if let Some(s) = item.as_str() {
-                            chars += s.len();
-                        } else if let Value::Object(obj) = item {
-                            if let Some(content) = obj.get("content") {
-                                if let Some(s) = content.as_str() {
-                                    chars += s.len();
-                                }
-                            }
-                        }
-                    }
-                }
-                Value::Object(obj) => {
-                    if let Some(content) = obj.get("content") {
-                        if let Some(s) = content.as_str() {
-                            chars += s.len();
-                        }
-                    }
-                }
-                _ => {}
-            }
+            chars += msg.role.len();
+            let (content_chars, content_tokens) = Self::content_size(&msg.content);
+            chars += content_chars;
+            fixed_tokens += content_tokens;
         }
 
         // System prompt
         if let Some(system) = &request.system {
-            chars += system.to_string().len();
+            let (system_chars, system_tokens) = Self::content_size(system);
+            chars += system_chars;
+            fixed_tokens += system_tokens;
         }
 
         // Tools
@@ -139,6 +154,179 @@ impl Router {
             }
         }
 
-        chars / 4
+        chars.div_ceil(4) + fixed_tokens
+    }
+
+    /// Approximate tokens of one content value (a string, block or block array)
+    pub fn estimate_content_tokens(content: &Value) -> usize {
+        let (chars, fixed_tokens) = Self::content_size(content);
+        chars.div_ceil(4) + fixed_tokens
+    }
+
+    /// Size of a content value as (characters, fixed token cost of media blocks)
+    fn content_size(content: &Value) -> (usize, usize) {
+        match content {
+            Value::String(s) => (s.len(), 0),
+            Value::Array(blocks) => blocks.iter().fold((0, 0), |(chars, tokens), block| {
+                let (block_chars, block_tokens) = Self::content_size(block);
+                (chars + block_chars, tokens + block_tokens)
+            }),
+            Value::Object(block) => match block.get("type").and_then(|t| t.as_str()) {
+                Some("text") => Self::field_chars(block.get("text")),
+                Some("thinking") => Self::field_chars(block.get("thinking")),
+                Some("redacted_thinking") => Self::field_chars(block.get("data")),
+                Some("tool_use") | Some("server_tool_use") => {
+                    let name = Self::field_chars(block.get("name")).0;
+                    let input = block.get("input").map(|v| v.to_string().len()).unwrap_or(0);
+                    (name + input, 0)
+                }
+                Some("tool_result") | Some("web_search_tool_result") => block
+                    .get("content")
+                    .map(Self::content_size)
+                    .unwrap_or((0, 0)),
+                Some("image") => (0, IMAGE_TOKENS),
+                Some("document") => match block.get("source") {
+                    Some(source) if source.get("type").and_then(|t| t.as_str()) == Some("text") => {
+                        Self::field_chars(source.get("data"))
+                    }
+                    Some(source) if source.get("type").and_then(|t| t.as_str()) == Some("content") => {
+                        source.get("content").map(Self::content_size).unwrap_or((0, 0))
+                    }
+                    _ => (0, DOCUMENT_TOKENS),
+                },
+                _ => match block.get("content") {
+                    Some(content) => Self::content_size(content),
+                    None => (block.get("text").and_then(|t| t.as_str()).map(|s| s.len()).unwrap_or(0), 0),
+                },
+            },
+            _ => (0, 0),
+        }
+    }
+
+    fn field_chars(value: Option<&Value>) -> (usize, usize) {
+        (value.and_then(|v| v.as_str()).map(|s| s.len()).unwrap_or(0), 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn test_router() -> Router {
+        Router::new(serde_json::from_value(json!({
+            "Providers": [],
+            "Router": {"default": "p,default", "longContext": "p,long"}
+        })).unwrap())
+    }
+
+    fn request(messages: Value) -> RouterRequest {
+        RouterRequest {
+            model: Some("claude-3-5-sonnet".to_string()),
+            messages: serde_json::from_value(messages).unwrap(),
+            system: None,
+            tools: None,
+            thinking: None,
+            conversation: None,
+        }
+    }
+
+    #[test]
+    fn test_estimate_counts_text_blocks() {
+        let plain = request(json!([{"role": "user", "content": "a".repeat(400)}]));
+        let blocks = request(json!([{"role": "user", "content": [{"type": "text", "text": "a".repeat(400)}]}]));
+        assert_eq!(Router::estimate_tokens(&plain), 101);
+        assert_eq!(Router::estimate_tokens(&blocks), 101);
+    }
+
+    #[test]
+    fn test_estimate_counts_tool_blocks_and_images() {
+        let req = request(json!([
+            {"role": "assistant", "content": [
+                {"type": "tool_use", "id": "t1", "name": "read", "input": {"path": "/tmp/file.txt"}}
+            ]},
+            {"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "t1", "content": [{"type": "text", "text": "x".repeat(80)}]},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAAA"}}
+            ]}
+        ]));
+        let tokens = Router::estimate_tokens(&req);
+        assert!(tokens > IMAGE_TOKENS + 20, "got {}", tokens);
+    }
+
+    #[test]
+    fn test_estimate_counts_system_and_tools() {
+        let mut req = request(json!([{"role": "user", "content": "hi"}]));
+        let base = Router::estimate_tokens(&req);
+        req.system = Some(json!([{"type": "text", "text": "s".repeat(400), "cache_control": {"type": "ephemeral"}}]));
+        req.tools = Some(vec![ClaudeTool {
+            name: "search".to_string(),
+            description: "d".repeat(400),
+            input_schema: json!({"type": "object"}),
+        }]);
+        assert!(Router::estimate_tokens(&req) >= base + 200);
+    }
+
+    #[test]
+    fn test_long_context_route() {
+        let router = test_router();
+        let req = request(json!([{"role": "user", "content": [{"type": "text", "text": "a".repeat(250_000)}]}]));
+        assert_eq!(router.route_request(&req).unwrap(), vec!["p,long"]);
+        let short = request(json!([{"role": "user", "content": "hello"}]));
+        assert_eq!(router.route_request(&short).unwrap(), vec!["p,default"]);
+    }
+
+    #[test]
+    fn test_fallback_chain_routes() {
+        let router = Router::new(serde_json::from_value(json!({
+            "Providers": [],
+            "Router": {
+                "default": ["groq,kimi", "openrouter,kimi", ""],
+                "background": "ollama,qwen"
+            }
+        })).unwrap());
+
+        let req = request(json!([{"role": "user", "content": "hello"}]));
+        assert_eq!(router.route_request(&req).unwrap(), vec!["groq,kimi", "openrouter,kimi"]);
+
+        let mut haiku = request(json!([{"role": "user", "content": "hello"}]));
+        haiku.model = Some("claude-3-5-haiku-20241022".to_string());
+        assert_eq!(router.route_request(&haiku).unwrap(), vec!["ollama,qwen"]);
+
+        let mut direct = request(json!([{"role": "user", "content": "hello"}]));
+        direct.model = Some("openrouter,some/model".to_string());
+        assert_eq!(router.route_request(&direct).unwrap(), vec!["openrouter,some/model"]);
+    }
+
+    #[test]
+    fn test_pool_routes_are_expanded_and_sticky() {
+        let router = Router::new(serde_json::from_value(json!({
+            "Providers": [],
+            "Router": {
+                "default": ["kimi", "ollama,qwen"],
+                "pools": {"kimi": [{"route": "groq,kimi", "weight": 2}, {"route": "openrouter,kimi"}]}
+            }
+        })).unwrap());
+
+        let mut req = request(json!([{"role": "user", "content": "hello"}]));
+        req.conversation = Some("session-a".to_string());
+        let routes = router.route_request(&req).unwrap();
+        assert_eq!(routes.len(), 3);
+        assert!(routes[..2].contains(&"groq,kimi".to_string()));
+        assert!(routes[..2].contains(&"openrouter,kimi".to_string()));
+        assert_eq!(routes[2], "ollama,qwen");
+        for _ in 0..20 {
+            assert_eq!(router.clone().route_request(&req).unwrap(), routes);
+        }
+    }
+
+    #[test]
+    fn test_empty_default_route_is_an_error() {
+        let router = Router::new(serde_json::from_value(json!({
+            "Providers": [],
+            "Router": {"default": ""}
+        })).unwrap());
+        let req = request(json!([{"role": "user", "content": "hello"}]));
+        assert!(router.route_request(&req).is_err());
     }
-}
\ No newline at end of file
+}
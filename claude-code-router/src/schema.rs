@@ -0,0 +1,407 @@
+use serde_json::{json, Map, Value};
+
+use crate::config::{Provider, ProviderType, SchemaProfile};
+use crate::transformers;
+
+/// Keywords that only describe the schema document itself
+const META_KEYWORDS: [&str; 6] = ["$schema", "$id", "$comment", "$defs", "definitions", "$anchor"];
+
+/// Keywords that Groq and many OpenRouter backends reject in tool parameters
+const STRICT_UNSUPPORTED: [&str; 11] = [
+    "additionalProperties",
+    "format",
+    "examples",
+    "patternProperties",
+    "unevaluatedProperties",
+    "propertyNames",
+    "dependentRequired",
+    "dependentSchemas",
+    "if",
+    "then",
+    "else",
+];
+
+/// Keywords Gemini function declarations accept (its OpenAPI 3.0 schema subset)
+const GEMINI_KEYWORDS: [&str; 20] = [
+    "type",
+    "format",
+    "title",
+    "description",
+    "nullable",
+    "enum",
+    "items",
+    "properties",
+    "required",
+    "minItems",
+    "maxItems",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "minimum",
+    "maximum",
+    "minProperties",
+    "maxProperties",
+    "anyOf",
+    "propertyOrdering",
+];
+
+/// String formats Gemini accepts; other formats are dropped
+const GEMINI_FORMATS: [&str; 2] = ["enum", "date-time"];
+
+/// Profile for a provider: its `schema` setting, else Gemini for native Gemini providers,
+/// else the profile of its first transformer that implies one, else Passthrough
+pub fn profile_for(provider: &Provider) -> SchemaProfile {
+    match (provider.schema, provider.provider_type) {
+        (Some(profile), _) => profile,
+        (None, ProviderType::Gemini) => SchemaProfile::Gemini,
+        (None, _) => provider
+            .transformer
+            .iter()
+            .flat_map(|transformer| &transformer.use_transformers)
+            .find_map(|transformer_use| transformers::schema_profile(transformer_use.name()?))
+            .unwrap_or(SchemaProfile::Passthrough),
+    }
+}
+
+/// Rewrite the `parameters` of every function tool in an OpenAI chat completions body
+pub fn sanitize_openai_tools(body: &mut Value, profile: SchemaProfile) {
+    if profile == SchemaProfile::Passthrough {
+        return;
+    }
+    if let Some(tools) = body.get_mut("tools").and_then(|t| t.as_array_mut()) {
+        for tool in tools {
+            if let Some(parameters) = tool.get_mut("function").and_then(|f| f.get_mut("parameters")) {
+                *parameters = sanitize(parameters, profile);
+            }
+        }
+    }
+}
+
+/// Rewrite a JSON schema for `profile`. `$ref`s pointing into the schema (`#/$defs/...`,
+/// `#/definitions/...`) are inlined for every profile except Passthrough.
+pub fn sanitize(schema: &Value, profile: SchemaProfile) -> Value {
+    if profile == SchemaProfile::Passthrough {
+        return schema.clone();
+    }
+    rewrite(schema, schema, profile, &mut Vec::new())
+}
+
+/// `refs` holds the `$ref`s being inlined on the path to `node`; meeting one of them again
+/// means the schema is recursive, and that reference becomes a plain object
+fn rewrite(node: &Value, root: &Value, profile: SchemaProfile, refs: &mut Vec<String>) -> Value {
+    let Value::Object(map) = node else {
+        return node.clone();
+    };
+    let mut map = map.clone();
+
+    if let Some(reference) = map.remove("$ref") {
+        let name = reference.as_str().unwrap_or_default().to_string();
+        let target = name
+            .strip_prefix('#')
+            .and_then(|pointer| root.pointer(pointer))
+            .filter(|_| !refs.contains(&name));
+        let resolved = match target {
+            Some(target) => {
+                refs.push(name);
+                let resolved = rewrite(target, root, profile, refs);
+                refs.pop();
+                resolved
+            }
+            None => {
+                log::debug!("Replacing unresolvable or recursive $ref {} with an object schema", reference);
+                json!({"type": "object"})
+            }
+        };
+        // Keywords next to the $ref (usually a description) win over the referenced ones
+        if let Value::Object(resolved) = resolved {
+            for (key, value) in resolved {
+                map.entry(key).or_insert(value);
+            }
+        }
+    }
+
+    if profile != SchemaProfile::Openai {
+        merge_all_of(&mut map);
+        if let Some(one_of) = map.remove("oneOf") {
+            map.entry("anyOf").or_insert(one_of);
+        }
+        if let Some(constant) = map.remove("const") {
+            map.entry("enum").or_insert_with(|| json!([constant]));
+        }
+        let nullable = flatten_null_union(&mut map) | drop_null_type(&mut map);
+        if nullable && profile == SchemaProfile::Gemini {
+            map.insert("nullable".to_string(), json!(true));
+        }
+    }
+
+    let mut result = Map::new();
+    for (key, value) in map {
+        if META_KEYWORDS.contains(&key.as_str()) || !keeps_keyword(&key, &value, profile) {
+            continue;
+        }
+        let value = match key.as_str() {
+            "properties" => match value {
+                Value::Object(properties) => Value::Object(
+                    properties
+                        .into_iter()
+                        .map(|(name, schema)| (name, rewrite(&schema, root, profile, refs)))
+                        .collect(),
+                ),
+                other => other,
+            },
+            "items" | "additionalProperties" | "not" | "contains" => rewrite(&value, root, profile, refs),
+            "anyOf" | "oneOf" | "allOf" | "prefixItems" => match value {
+                Value::Array(branches) => {
+                    Value::Array(branches.iter().map(|b| rewrite(b, root, profile, refs)).collect())
+                }
+                other => other,
+            },
+            _ => value,
+        };
+        result.insert(key, value);
+    }
+
+    if profile == SchemaProfile::Gemini {
+        // Gemini rejects object schemas with an empty properties map
+        if result.get("properties").and_then(|p| p.as_object()).is_some_and(|p| p.is_empty()) {
+            result.remove("properties");
+            result.remove("required");
+        }
+    }
+    Value::Object(result)
+}
+
+fn keeps_keyword(key: &str, value: &Value, profile: SchemaProfile) -> bool {
+    match profile {
+        SchemaProfile::Passthrough | SchemaProfile::Openai => true,
+        SchemaProfile::Strict => !STRICT_UNSUPPORTED.contains(&key),
+        SchemaProfile::Gemini => {
+            GEMINI_KEYWORDS.contains(&key)
+                && (key != "format" || value.as_str().is_some_and(|f| GEMINI_FORMATS.contains(&f)))
+        }
+    }
+}
+
+/// Merge `allOf` branches into the schema: properties and required lists are combined,
+/// other keywords of the schema itself win
+fn merge_all_of(map: &mut Map<String, Value>) {
+    let Some(Value::Array(branches)) = map.remove("allOf") else {
+        return;
+    };
+    for branch in branches {
+        let Value::Object(branch) = branch else {
+            continue;
+        };
+        for (key, value) in branch {
+            match (key.as_str(), map.get_mut(&key), value) {
+                ("properties", Some(Value::Object(existing)), Value::Object(more)) => {
+                    for (name, schema) in more {
+                        existing.entry(name).or_insert(schema);
+                    }
+                }
+                ("required", Some(Value::Array(existing)), Value::Array(more)) => {
+                    for name in more {
+                        if !existing.contains(&name) {
+                            existing.push(name);
+                        }
+                    }
+                }
+                (_, Some(_), _) => {}
+                (_, None, value) => {
+                    map.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+/// Remove `{"type": "null"}` branches from `anyOf`; a single remaining branch replaces the
+/// union. Returns whether a null branch was removed.
+fn flatten_null_union(map: &mut Map<String, Value>) -> bool {
+    let Some(Value::Array(branches)) = map.get("anyOf") else {
+        return false;
+    };
+    let is_null = |b: &Value| b.get("type").and_then(|t| t.as_str()) == Some("null");
+    if !branches.iter().any(is_null) {
+        return false;
+    }
+    let mut rest: Vec<Value> = branches.iter().filter(|b| !is_null(b)).cloned().collect();
+    if rest.len() == 1 {
+        map.remove("anyOf");
+        if let Value::Object(branch) = rest.remove(0) {
+            for (key, value) in branch {
+                map.entry(key).or_insert(value);
+            }
+        }
+    } else {
+        map.insert("anyOf".to_string(), Value::Array(rest));
+    }
+    true
+}
+
+/// Turn `"type": ["string", "null"]` into `"type": "string"`. Returns whether `null` was listed.
+fn drop_null_type(map: &mut Map<String, Value>) -> bool {
+    let Some(Value::Array(types)) = map.get("type") else {
+        return false;
+    };
+    let rest: Vec<Value> = types.iter().filter(|t| t.as_str() != Some("null")).cloned().collect();
+    let had_null = rest.len() < types.len();
+    let new_type = match rest.len() {
+        0 => json!("string"),
+        1 => rest[0].clone(),
+        _ => Value::Array(rest),
+    };
+    map.insert("type".to_string(), new_type);
+    had_null
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claude_code_schema() -> Value {
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "url": {"type": "string", "format": "uri"},
+                "when": {"type": "string", "format": "date-time"},
+                "limit": {"anyOf": [{"type": "integer", "minimum": 1}, {"type": "null"}]},
+                "mode": {"type": ["string", "null"], "const": "fast"},
+                "target": {"$ref": "#/$defs/target", "description": "Where to write"}
+            },
+            "required": ["url"],
+            "$defs": {
+                "target": {"type": "object", "properties": {"path": {"type": "string"}}, "additionalProperties": false}
+            }
+        })
+    }
+
+    #[test]
+    fn test_profile_for_provider() {
+        let provider = |settings: Value| -> Provider {
+            let mut provider = json!({"name": "p", "api_base_url": "http://p", "api_key": "k", "models": []});
+            provider.as_object_mut().unwrap().extend(settings.as_object().unwrap().clone());
+            serde_json::from_value(provider).unwrap()
+        };
+
+        assert_eq!(profile_for(&provider(json!({}))), SchemaProfile::Passthrough);
+        assert_eq!(profile_for(&provider(json!({"type": "gemini"}))), SchemaProfile::Gemini);
+        assert_eq!(profile_for(&provider(json!({"transformer": {"use": ["openrouter"]}}))), SchemaProfile::Strict);
+        assert_eq!(
+            profile_for(&provider(json!({"transformer": {"use": [["maxtoken", {"max_tokens": 10}], "gemini"]}}))),
+            SchemaProfile::Gemini
+        );
+        assert_eq!(
+            profile_for(&provider(json!({"transformer": {"use": ["openrouter"]}, "schema": "passthrough"}))),
+            SchemaProfile::Passthrough
+        );
+    }
+
+    #[test]
+    fn test_passthrough_profile() {
+        assert_eq!(sanitize(&claude_code_schema(), SchemaProfile::Passthrough), claude_code_schema());
+    }
+
+    #[test]
+    fn test_openai_profile() {
+        let schema = sanitize(&claude_code_schema(), SchemaProfile::Openai);
+        assert!(schema.get("$schema").is_none());
+        assert!(schema.get("$defs").is_none());
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(schema["properties"]["url"]["format"], "uri");
+        assert_eq!(schema["properties"]["limit"]["anyOf"][1]["type"], "null");
+        assert_eq!(schema["properties"]["target"], json!({
+            "type": "object",
+            "description": "Where to write",
+            "properties": {"path": {"type": "string"}},
+            "additionalProperties": false
+        }));
+    }
+
+    #[test]
+    fn test_strict_profile() {
+        let schema = sanitize(&claude_code_schema(), SchemaProfile::Strict);
+        assert_eq!(schema, json!({
+            "type": "object",
+            "properties": {
+                "url": {"type": "string"},
+                "when": {"type": "string"},
+                "limit": {"type": "integer", "minimum": 1},
+                "mode": {"type": "string", "enum": ["fast"]},
+                "target": {"type": "object", "description": "Where to write", "properties": {"path": {"type": "string"}}}
+            },
+            "required": ["url"]
+        }));
+
+        let merged = sanitize(&json!({
+            "allOf": [
+                {"type": "object", "properties": {"a": {"type": "string"}}, "required": ["a"]},
+                {"properties": {"b": {"type": "number"}}, "required": ["b"]}
+            ],
+            "oneOf": [{"type": "string"}, {"type": "number"}, {"type": "null"}]
+        }), SchemaProfile::Strict);
+        assert_eq!(merged["type"], "object");
+        assert_eq!(merged["required"], json!(["a", "b"]));
+        assert_eq!(merged["properties"]["b"]["type"], "number");
+        assert_eq!(merged["anyOf"], json!([{"type": "string"}, {"type": "number"}]));
+    }
+
+    #[test]
+    fn test_gemini_profile() {
+        let schema = sanitize(&claude_code_schema(), SchemaProfile::Gemini);
+        assert_eq!(schema, json!({
+            "type": "object",
+            "properties": {
+                "url": {"type": "string"},
+                "when": {"type": "string", "format": "date-time"},
+                "limit": {"type": "integer", "minimum": 1, "nullable": true},
+                "mode": {"type": "string", "enum": ["fast"], "nullable": true},
+                "target": {"type": "object", "description": "Where to write", "properties": {"path": {"type": "string"}}}
+            },
+            "required": ["url"]
+        }));
+
+        let empty = sanitize(&json!({"type": "object", "properties": {}, "exclusiveMinimum": 0}), SchemaProfile::Gemini);
+        assert_eq!(empty, json!({"type": "object"}));
+    }
+
+    #[test]
+    fn test_recursive_ref_is_cut() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"node": {"$ref": "#/definitions/node"}},
+            "definitions": {"node": {"type": "object", "properties": {"child": {"$ref": "#/definitions/node"}}}}
+        });
+        let sanitized = sanitize(&schema, SchemaProfile::Strict);
+        assert_eq!(sanitized["properties"]["node"], json!({
+            "type": "object",
+            "properties": {"child": {"type": "object"}}
+        }));
+        assert!(sanitized.get("definitions").is_none());
+    }
+
+    #[test]
+    fn test_recursive_ref_with_several_self_references_stays_small() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"tree": {"$ref": "#/$defs/node"}},
+            "$defs": {"node": {"type": "object", "properties": {
+                "left": {"$ref": "#/$defs/node"},
+                "right": {"$ref": "#/$defs/node"},
+                "parent": {"anyOf": [{"$ref": "#/$defs/node"}, {"type": "null"}]}
+            }}}
+        });
+        let sanitized = sanitize(&schema, SchemaProfile::Openai);
+        assert_eq!(sanitized["properties"]["tree"], json!({
+            "type": "object",
+            "properties": {
+                "left": {"type": "object"},
+                "right": {"type": "object"},
+                "parent": {"anyOf": [{"type": "object"}, {"type": "null"}]}
+            }
+        }));
+        assert!(sanitized.to_string().len() < 300);
+    }
+}
@@ -0,0 +1,244 @@
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Config keys whose values are secrets: `file:` / `cmd:` references are only
+/// resolved here, and their values are never logged
+const SECRET_KEYS: [&str; 3] = ["api_key", "api_keys", "APIKEY"];
+
+/// Resolve `$VAR` / `${VAR}` references in every string of a raw config, then
+/// `file:` and `cmd:` references in secret fields. Errors and debug messages name
+/// the location, e.g. `Providers[0].api_key`.
+///
+/// A provider whose references cannot be resolved does not fail the config: it is left
+/// unresolved and its error is returned with its index, so that only requests routed to it
+/// fail. Errors outside `Providers` fail the whole config.
+pub fn resolve_config(value: &mut Value) -> Result<Vec<(usize, String)>, Box<dyn std::error::Error>> {
+    let mut provider_errors = Vec::new();
+    let Value::Object(map) = value else {
+        resolve_value(value, "", false)?;
+        return Ok(provider_errors);
+    };
+    for (key, item) in map.iter_mut() {
+        match (key.as_str(), item) {
+            ("Providers", Value::Array(providers)) => {
+                for (i, provider) in providers.iter_mut().enumerate() {
+                    let mut resolved = provider.clone();
+                    match resolve_value(&mut resolved, &format!("Providers[{}]", i), false) {
+                        Ok(()) => *provider = resolved,
+                        Err(e) => {
+                            log::warn!("{}; requests routed to this provider will fail", e);
+                            provider_errors.push((i, e.to_string()));
+                        }
+                    }
+                }
+            }
+            (key, item) => resolve_value(item, key, SECRET_KEYS.contains(&key))?,
+        }
+    }
+    Ok(provider_errors)
+}
+
+fn resolve_value(value: &mut Value, path: &str, secret: bool) -> Result<(), Box<dyn std::error::Error>> {
+    match value {
+        Value::String(text) => {
+            let (interpolated, variables) = interpolate_env(text).map_err(|e| format!("{}: {}", path, e))?;
+            *text = if secret {
+                let (resolved, source) = resolve_secret(&interpolated).map_err(|e| format!("{}: {}", path, e))?;
+                let source = match (source, variables.as_slice()) {
+                    (SecretSource::Inline, []) => "inline value".to_string(),
+                    (SecretSource::Inline, variables) => format!("environment variable {}", variables.join(", ")),
+                    (SecretSource::File(file), _) => format!("file {}", file),
+                    (SecretSource::Command(command), _) => format!("command `{}`", command),
+                };
+                log::debug!("{}: secret loaded from {}", path, source);
+                resolved
+            } else {
+                interpolated
+            };
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                resolve_value(item, &format!("{}[{}]", path, i), secret)?;
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let child_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                resolve_value(item, &child_path, secret || SECRET_KEYS.contains(&key.as_str()))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace `$VAR` and `${VAR}` with environment values; `$$` is a literal `$` and a `$`
+/// not followed by a variable name is kept as-is. Returns the names that were used.
+pub fn interpolate_env(text: &str) -> Result<(String, Vec<String>), String> {
+    let mut result = String::with_capacity(text.len());
+    let mut variables = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        let name = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                result.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unterminated ${{{}", name)),
+                    }
+                }
+                name
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                    name.push(*c);
+                    chars.next();
+                }
+                name
+            }
+            _ => {
+                result.push('$');
+                continue;
+            }
+        };
+        let value = std::env::var(&name).map_err(|_| format!("environment variable {} is not set", name))?;
+        result.push_str(&value);
+        variables.push(name);
+    }
+
+    Ok((result, variables))
+}
+
+/// Where a secret value came from, for logging
+#[derive(Debug, PartialEq)]
+enum SecretSource {
+    Inline,
+    File(String),
+    Command(String),
+}
+
+/// `file:<path>` reads the file, `cmd:<command>` runs it through `sh -c`;
+/// both are trimmed. Anything else is the secret itself.
+fn resolve_secret(value: &str) -> Result<(String, SecretSource), String> {
+    if let Some(path) = value.strip_prefix("file:") {
+        let path = path.trim();
+        let contents = fs::read_to_string(expand_home(path))
+            .map_err(|e| format!("cannot read secret file {}: {}", path, e))?;
+        return Ok((contents.trim().to_string(), SecretSource::File(path.to_string())));
+    }
+
+    if let Some(command) = value.strip_prefix("cmd:") {
+        let command = command.trim();
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .map_err(|e| format!("cannot run secret command `{}`: {}", command, e))?;
+        if !output.status.success() {
+            // stderr is not included, it might echo part of the secret
+            return Err(format!("secret command `{}` failed with {}", command, output.status));
+        }
+        let secret = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        return Ok((secret, SecretSource::Command(command.to_string())));
+    }
+
+    Ok((value.to_string(), SecretSource::Inline))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_interpolate_env() {
+        std::env::set_var("CCR_TEST_SECRET_HOST", "example.com");
+        std::env::set_var("CCR_TEST_SECRET_PORT", "8443");
+
+        let (text, variables) = interpolate_env("https://${CCR_TEST_SECRET_HOST}:$CCR_TEST_SECRET_PORT/v1").unwrap();
+        assert_eq!(text, "https://example.com:8443/v1");
+        assert_eq!(variables, vec!["CCR_TEST_SECRET_HOST", "CCR_TEST_SECRET_PORT"]);
+
+        assert_eq!(interpolate_env("costs $5 or $$HOME").unwrap().0, "costs $5 or $HOME");
+        assert!(interpolate_env("$CCR_TEST_SECRET_UNSET_VARIABLE").unwrap_err().contains("is not set"));
+        assert!(interpolate_env("${CCR_TEST_SECRET_HOST").is_err());
+    }
+
+    #[test]
+    fn test_secret_references() {
+        let dir = std::env::temp_dir().join(format!("ccr-secrets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("groq");
+        fs::write(&file, "gsk_from_file\n").unwrap();
+
+        let (secret, source) = resolve_secret(&format!("file:{}", file.display())).unwrap();
+        assert_eq!(secret, "gsk_from_file");
+        assert_eq!(source, SecretSource::File(file.display().to_string()));
+
+        let (secret, source) = resolve_secret("cmd: echo sk-from-command").unwrap();
+        assert_eq!(secret, "sk-from-command");
+        assert_eq!(source, SecretSource::Command("echo sk-from-command".to_string()));
+
+        assert!(resolve_secret("cmd:exit 3").unwrap_err().contains("failed"));
+        assert!(resolve_secret("file:/nonexistent/ccr/secret").is_err());
+        assert_eq!(resolve_secret("sk-inline").unwrap(), ("sk-inline".to_string(), SecretSource::Inline));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_config() {
+        std::env::set_var("CCR_TEST_SECRET_KEY", "sk-from-env");
+        let mut config = json!({
+            "Providers": [{
+                "name": "groq",
+                "api_base_url": "https://${CCR_TEST_SECRET_BASE_HOST:-x}",
+                "api_key": "$CCR_TEST_SECRET_KEY",
+                "models": ["cmd:not-a-secret"]
+            }],
+            "APIKEY": "cmd:echo router-key"
+        });
+        // ${VAR:-default} is not supported, so the whole name is looked up and missing.
+        // Only that provider is affected, and it is left unresolved.
+        let provider_errors = resolve_config(&mut config).unwrap();
+        assert_eq!(provider_errors.len(), 1);
+        assert_eq!(provider_errors[0].0, 0);
+        assert!(provider_errors[0].1.contains("Providers[0].api_base_url"), "{}", provider_errors[0].1);
+        assert_eq!(config["Providers"][0]["api_key"], "$CCR_TEST_SECRET_KEY");
+        assert_eq!(config["APIKEY"], "router-key");
+
+        config["Providers"][0]["api_base_url"] = json!("https://api.groq.com/openai/v1");
+        assert!(resolve_config(&mut config).unwrap().is_empty());
+        assert_eq!(config["Providers"][0]["api_key"], "sk-from-env");
+        assert_eq!(config["APIKEY"], "router-key");
+        // Secret references are only resolved in secret fields
+        assert_eq!(config["Providers"][0]["models"][0], "cmd:not-a-secret");
+
+        // Outside Providers an unresolvable reference still fails the config
+        let mut config = json!({"APIKEY": "$CCR_TEST_SECRET_UNSET_VARIABLE"});
+        assert!(resolve_config(&mut config).unwrap_err().to_string().contains("APIKEY"));
+    }
+}
@@ -1,16 +1,26 @@
+use std::collections::hash_map::DefaultHasher;
 use std::convert::Infallible;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
+use std::time::Instant;
 
 use hyper::service::{make_service_fn, service_fn};
+use hyper::header::HeaderValue;
 use hyper::{Body, Method, Request, Response, Server as HyperServer, StatusCode};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 
 use crate::config::Config;
+use crate::error::{anthropic_error_body, openai_error_body, ProviderError};
 use crate::router::{Router, RouterRequest, Message, ClaudeTool};
 use crate::provider::ProviderClient;
 use crate::message_transformer::MessageTransformer;
+use crate::thinking::ThinkingConfig;
+
+/// Response header naming the "provider,model" route that served the request
+pub const ROUTE_HEADER: &str = "x-ccr-route";
 
 pub struct Server {
     config: Config,
@@ -22,7 +32,7 @@ pub struct Server {
 impl Server {
     pub fn new(config: Config) -> Self {
         let router = Router::new(config.clone());
-        let provider_client = ProviderClient::new();
+        let provider_client = ProviderClient::new(&config);
         Self {
             config,
             router,
@@ -38,6 +48,7 @@ impl Server {
         let config = self.config.clone();
         let router = self.router.clone();
         let provider_client = self.provider_client.clone();
+        let probe = provider_client.spawn_health_probe(config.clone());
 
         let make_svc = make_service_fn(move |_conn| {
             let config = config.clone();
@@ -64,7 +75,11 @@ impl Server {
 
         println!("🚀 Server started on http://{}", addr);
 
-        if let Err(e) = server.await {
+        let result = server.await;
+        if let Some(probe) = probe {
+            probe.abort();
+        }
+        if let Err(e) = result {
             eprintln!("❌ Server error: {}", e);
             return Err(Box::new(e));
         }
@@ -81,24 +96,36 @@ impl Server {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ClaudeRequest {
     pub model: String,
     pub messages: Vec<Message>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub system: Option<Value>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<Value>>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub thinking: Option<Value>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    /// `{"type": "auto" | "any" | "tool" | "none", "name": ..., "disable_parallel_tool_use": ...}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Value>,
+    /// Any other Messages API fields, kept so native Anthropic providers receive them
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
 async fn handle_request(
@@ -111,7 +138,7 @@ async fn handle_request(
     let method = req.method();
 
     if !matches!((method, path), (&Method::GET, "/") | (&Method::GET, "/health")) {
-        if let Err(resp) = check_auth(&req, &config) {
+        if let Some(resp) = check_auth(&req, &config) {
             return Ok(resp);
         }
     }
@@ -127,22 +154,41 @@ async fn handle_request(
         (&Method::POST, "/v1/messages") => {
             handle_claude_request(req, router, provider_client, config).await
         }
-        _ => {
+        (&Method::POST, "/v1/messages/count_tokens") => {
+            handle_count_tokens(req).await
+        }
+        (&Method::POST, "/v1/chat/completions") => {
+            handle_openai_request(req, router, provider_client, config).await
+        }
+        (&Method::GET, "/admin/health") => {
             Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .header("Content-Type", "text/plain")
-                .body(Body::from("Not Found"))
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(provider_client.health_snapshot().to_string()))
                 .unwrap())
         }
+        (&Method::GET, "/admin/keys") => {
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(provider_client.key_usage().to_string()))
+                .unwrap())
+        }
+        _ => {
+            Ok(error_response(
+                ApiFormat::for_path(path),
+                StatusCode::NOT_FOUND,
+                "not_found_error",
+                &format!("No route for {} {}", method, path),
+                None,
+            ))
+        }
     }
 }
 
-fn check_auth(req: &Request<Body>, config: &Config) -> Result<(), Response<Body>> {
-    if config.apikey.is_none() {
-        return Ok(());
-    }
-
-    let api_key = config.apikey.as_ref().unwrap();
+/// Returns the rejection response when the request is not authorized
+fn check_auth(req: &Request<Body>, config: &Config) -> Option<Response<Body>> {
+    let api_key = config.apikey.as_ref()?;
     
     let auth_header = req
         .headers()
@@ -152,15 +198,226 @@ fn check_auth(req: &Request<Body>, config: &Config) -> Result<(), Response<Body>
         .or_else(|| req.headers().get("x-api-key").and_then(|h| h.to_str().ok()));
 
     match auth_header {
-        Some(key) if key == api_key => Ok(()),
-        _ => Err(Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .header("Content-Type", "text/plain")
-            .body(Body::from("Unauthorized"))
-            .unwrap()),
+        Some(key) if key == api_key => None,
+        _ => Some(error_response(
+            ApiFormat::for_path(req.uri().path()),
+            StatusCode::UNAUTHORIZED,
+            "authentication_error",
+            "Invalid or missing API key",
+            None,
+        )),
+    }
+}
+
+/// Which API dialect an error response should be shaped for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ApiFormat {
+    Anthropic,
+    OpenAi,
+}
+
+impl ApiFormat {
+    fn for_path(path: &str) -> Self {
+        if path.starts_with("/v1/chat/") {
+            ApiFormat::OpenAi
+        } else {
+            ApiFormat::Anthropic
+        }
+    }
+}
+
+/// Build a JSON error envelope in the caller's API dialect
+fn error_response(
+    format: ApiFormat,
+    status: StatusCode,
+    error_type: &str,
+    message: &str,
+    retry_after: Option<&str>,
+) -> Response<Body> {
+    let body = match format {
+        ApiFormat::Anthropic => anthropic_error_body(error_type, message),
+        ApiFormat::OpenAi => openai_error_body(error_type, message, status.as_u16()),
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json");
+    if let Some(retry_after) = retry_after {
+        builder = builder.header("retry-after", retry_after);
+    }
+    builder.body(Body::from(body.to_string())).unwrap()
+}
+
+/// Map a provider failure onto the matching status, error type and retry hint
+fn provider_error_response(format: ApiFormat, error: &(dyn std::error::Error + 'static)) -> Response<Body> {
+    match error.downcast_ref::<ProviderError>() {
+        Some(provider_error) => error_response(
+            format,
+            StatusCode::from_u16(match format {
+                ApiFormat::Anthropic => provider_error.status_code(),
+                ApiFormat::OpenAi => provider_error.openai_status_code(),
+            })
+            .unwrap_or(StatusCode::BAD_GATEWAY),
+            provider_error.error_type(),
+            &provider_error.to_string(),
+            provider_error.retry_after(),
+        ),
+        None => error_response(
+            format,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "api_error",
+            &format!("Provider request failed: {}", error),
+            None,
+        ),
+    }
+}
+
+/// Try each route in order until one succeeds. Retryable provider failures
+/// (connection errors, timeouts, 429s, 5xx) move on to the next route; any other
+/// failure, or a failure of the last route, is returned together with that route.
+/// Outcomes and response times are reported to the router for model pool balancing.
+async fn send_with_fallback<T, F, Fut>(
+    router: &Router,
+    router_request: &RouterRequest,
+    routes: &[String],
+    mut send: F,
+) -> (String, Result<T, Box<dyn std::error::Error>>)
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<T, Box<dyn std::error::Error>>>,
+{
+    for (attempt, route) in routes.iter().enumerate() {
+        log::info!("🧭 Routing request to: {} (attempt {}/{})", route, attempt + 1, routes.len());
+        let started = Instant::now();
+        let result = send(route.clone()).await;
+        match result {
+            Ok(value) => {
+                router.record_result(router_request, route, started.elapsed(), true);
+                return (route.clone(), Ok(value));
+            }
+            Err(e) => {
+                let provider_error = e.downcast_ref::<ProviderError>();
+                let retryable = provider_error.map(|provider_error| provider_error.is_retryable()).unwrap_or(false);
+                // The target cannot take this request (e.g. images for a text-only model);
+                // another one in the chain may, and this one did not misbehave
+                let unsupported = matches!(provider_error, Some(ProviderError::Unsupported(_)));
+                if retryable {
+                    router.record_result(router_request, route, started.elapsed(), false);
+                }
+                if !(retryable || unsupported) || attempt + 1 == routes.len() {
+                    return (route.clone(), Err(e));
+                }
+                log::warn!("⚠️  Route {} failed ({}), falling back to {}", route, e, routes[attempt + 1]);
+            }
+        }
+    }
+    (String::new(), Err("No route configured for this request".into()))
+}
+
+/// Report the "provider,model" route that served the request
+fn with_route_header(mut response: Response<Body>, route: &str) -> Response<Body> {
+    if let Ok(value) = HeaderValue::from_str(route) {
+        response.headers_mut().insert(ROUTE_HEADER, value);
+    }
+    response
+}
+
+/// Build a `text/event-stream` response fed by the frames arriving on `events`
+fn event_stream_response(mut events: mpsc::Receiver<String>) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if sender.send_data(event.into()).await.is_err() {
+                log::debug!("Client closed the event stream");
+                break;
+            }
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Connection", "keep-alive")
+        .body(body)
+        .unwrap()
+}
+
+/// Parse raw Claude Code tools into ClaudeTool format, skipping unparseable entries
+pub fn parse_claude_tools(claude_req: &ClaudeRequest) -> Option<Vec<ClaudeTool>> {
+    claude_req.tools.as_ref().map(|tools| {
+        let parsed: Vec<ClaudeTool> = tools.iter().filter_map(|tool| {
+            if let Ok(claude_tool) = serde_json::from_value::<ClaudeTool>(tool.clone()) {
+                Some(claude_tool)
+            } else {
+                log::warn!("Failed to parse tool: {:?}", tool);
+                None
+            }
+        }).collect();
+        
+        log::debug!("Successfully parsed {} Claude tools", parsed.len());
+        parsed
+    })
+}
+
+pub fn build_router_request(claude_req: &ClaudeRequest, parsed_tools: Option<Vec<ClaudeTool>>) -> RouterRequest {
+    RouterRequest {
+        model: Some(claude_req.model.clone()),
+        messages: claude_req.messages.clone(),
+        system: claude_req.system.clone(),
+        tools: parsed_tools,
+        thinking: claude_req.thinking.as_ref().and_then(ThinkingConfig::from_value).map(|_| true),
+        conversation: conversation_key(claude_req),
     }
 }
 
+/// Key identifying a conversation: Claude Code's `metadata.user_id`, which includes the
+/// session id, or else a hash of the system prompt and the first message
+fn conversation_key(claude_req: &ClaudeRequest) -> Option<String> {
+    let user_id = claude_req
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get("user_id"))
+        .and_then(|u| u.as_str())
+        .filter(|u| !u.is_empty());
+    if let Some(user_id) = user_id {
+        return Some(user_id.to_string());
+    }
+
+    let first = claude_req.messages.first()?;
+    let mut hasher = DefaultHasher::new();
+    claude_req.system.as_ref().map(|s| s.to_string()).hash(&mut hasher);
+    first.content.to_string().hash(&mut hasher);
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+async fn handle_count_tokens(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => b,
+        Err(_) => {
+            return Ok(error_response(ApiFormat::Anthropic, StatusCode::BAD_REQUEST, "invalid_request_error", "Invalid request body", None))
+        }
+    };
+
+    let claude_req: ClaudeRequest = match serde_json::from_slice(&bytes) {
+        Ok(req) => req,
+        Err(e) => {
+            log::error!("❌ Failed to parse count_tokens JSON: {}", e);
+            return Ok(error_response(ApiFormat::Anthropic, StatusCode::BAD_REQUEST, "invalid_request_error", &format!("Invalid JSON: {}", e), None));
+        }
+    };
+
+    let router_request = build_router_request(&claude_req, parse_claude_tools(&claude_req));
+    let input_tokens = Router::estimate_tokens(&router_request);
+    log::debug!("Counted {} input tokens", input_tokens);
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::json!({"input_tokens": input_tokens}).to_string()))
+        .unwrap())
+}
+
 async fn handle_claude_request(
     req: Request<Body>,
     router: Router,
@@ -170,11 +427,7 @@ async fn handle_claude_request(
     let bytes = match hyper::body::to_bytes(req.into_body()).await {
         Ok(b) => b,
         Err(_) => {
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header("Content-Type", "application/json")
-                .body(Body::from(r#"{"error":"Invalid request body"}"#))
-                .unwrap())
+            return Ok(error_response(ApiFormat::Anthropic, StatusCode::BAD_REQUEST, "invalid_request_error", "Invalid request body", None))
         }
     };
 
@@ -193,11 +446,7 @@ async fn handle_claude_request(
                 }
             }
             
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header("Content-Type", "application/json")
-                .body(Body::from(format!(r#"{{"error":"Invalid JSON: {}"}}"#, e)))
-                .unwrap());
+            return Ok(error_response(ApiFormat::Anthropic, StatusCode::BAD_REQUEST, "invalid_request_error", &format!("Invalid JSON: {}", e), None));
         }
     };
 
@@ -210,44 +459,19 @@ async fn handle_claude_request(
         }
     }
 
-    let parsed_tools: Option<Vec<ClaudeTool>> = claude_req.tools.as_ref().map(|tools| {
-        let parsed: Vec<ClaudeTool> = tools.iter().filter_map(|tool| {
-            if let Ok(claude_tool) = serde_json::from_value::<ClaudeTool>(tool.clone()) {
-                Some(claude_tool)
-            } else {
-                log::warn!("Failed to parse tool: {:?}", tool);
-                None
-            }
-        }).collect();
-        
-        log::debug!("Successfully parsed {} Claude tools", parsed.len());
-        parsed
-    });
-    
+    let parsed_tools = parse_claude_tools(&claude_req);
     log::debug!("Final parsed_tools: {:?}", parsed_tools.as_ref().map(|tools| tools.len()));
 
-    let router_request = RouterRequest {
-        model: Some(claude_req.model.clone()),
-        messages: claude_req.messages.clone(),
-        system: claude_req.system.clone(),
-        tools: parsed_tools.clone(),
-        thinking: claude_req.thinking.clone().and_then(|v| v.as_bool()),
-    };
+    let router_request = build_router_request(&claude_req, parsed_tools.clone());
 
-    let route = match router.route_request(&router_request) {
-        Ok(route) => route,
+    let routes = match router.route_request(&router_request) {
+        Ok(routes) => provider_client.skip_open_circuits(routes),
         Err(e) => {
             log::error!("Routing error: {}", e);
-            return Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .header("Content-Type", "application/json")
-                .body(Body::from(format!(r#"{{"error":"Routing failed: {}"}}"#, e)))
-                .unwrap());
+            return Ok(error_response(ApiFormat::Anthropic, StatusCode::INTERNAL_SERVER_ERROR, "api_error", &format!("Routing failed: {}", e), None));
         }
     };
 
-    log::info!("🧭 Routing request to: {}", route);
-
     log::debug!("Original tools: {:?}", claude_req.tools);
     
     let transformed_messages = MessageTransformer::transform_messages_to_openai(&claude_req.messages);
@@ -264,53 +488,195 @@ async fn handle_claude_request(
         }
     };
 
-    match provider_client.send_claude_request(
-        &route,
-        &claude_req,
-        &config,
-        transformed_messages,
-        transformed_tools,
-    ).await {
+    if claude_req.stream.unwrap_or(false) {
+        let (route, result) = send_with_fallback(&router, &router_request, &routes, |route| {
+            let (provider_client, claude_req, config) = (&provider_client, &claude_req, &config);
+            let (messages, tools) = (transformed_messages.clone(), transformed_tools.clone());
+            async move {
+                provider_client.send_claude_stream_request(&route, claude_req, config, messages, tools).await
+            }
+        }).await;
+        return match result {
+            Ok(events) => Ok(with_route_header(event_stream_response(events), &route)),
+            Err(e) => {
+                log::error!("Provider error: {}", e);
+                Ok(provider_error_response(ApiFormat::Anthropic, e.as_ref()))
+            }
+        };
+    }
+
+    let (route, result) = send_with_fallback(&router, &router_request, &routes, |route| {
+        let (provider_client, claude_req, config) = (&provider_client, &claude_req, &config);
+        let (messages, tools) = (transformed_messages.clone(), transformed_tools.clone());
+        async move {
+            provider_client.send_claude_request(&route, claude_req, config, messages, tools).await
+        }
+    }).await;
+    match result {
         Ok(provider_response) => {
-            Ok(Response::builder()
+            Ok(with_route_header(Response::builder()
                 .status(StatusCode::OK)
                 .header("Content-Type", "application/json")
                 .body(Body::from(provider_response.to_string()))
-                .unwrap())
+                .unwrap(), &route))
         }
         Err(e) => {
             log::error!("Provider error: {}", e);
-            Ok(Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
+            Ok(provider_error_response(ApiFormat::Anthropic, e.as_ref()))
+        }
+    }
+}
+
+/// Handle requests from clients that already speak OpenAI chat completions.
+/// They go through the same routing, provider list and transformers, but skip
+/// the Claude <-> OpenAI conversion in both directions.
+async fn handle_openai_request(
+    req: Request<Body>,
+    router: Router,
+    provider_client: ProviderClient,
+    config: Config,
+) -> Result<Response<Body>, Infallible> {
+    let bytes = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => b,
+        Err(_) => {
+            return Ok(error_response(ApiFormat::OpenAi, StatusCode::BAD_REQUEST, "invalid_request_error", "Invalid request body", None))
+        }
+    };
+
+    log::debug!("Incoming OpenAI request body: {}", String::from_utf8_lossy(&bytes));
+
+    let openai_req: Value = match serde_json::from_slice(&bytes) {
+        Ok(Value::Object(map)) if map.get("messages").map(|m| m.is_array()).unwrap_or(false) => Value::Object(map),
+        Ok(_) => {
+            return Ok(error_response(
+                ApiFormat::OpenAi,
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                "Invalid JSON: expected an object with a messages array",
+                None,
+            ));
+        }
+        Err(e) => {
+            log::error!("❌ Failed to parse OpenAI JSON: {}", e);
+            return Ok(error_response(ApiFormat::OpenAi, StatusCode::BAD_REQUEST, "invalid_request_error", &format!("Invalid JSON: {}", e), None));
+        }
+    };
+
+    let claude_req = claude_request_from_openai(&openai_req);
+    let parsed_tools = openai_req
+        .get("tools")
+        .and_then(|t| t.as_array())
+        .map(|tools| MessageTransformer::transform_tools_from_openai(tools));
+    let mut router_request = build_router_request(&claude_req, parsed_tools);
+    router_request.thinking = openai_req.get("reasoning_effort").map(|_| true);
+    if let Some(user) = openai_req.get("user").and_then(|u| u.as_str()).filter(|u| !u.is_empty()) {
+        router_request.conversation = Some(user.to_string());
+    }
+
+    let routes = match router.route_request(&router_request) {
+        Ok(routes) => provider_client.skip_open_circuits(routes),
+        Err(e) => {
+            log::error!("Routing error: {}", e);
+            return Ok(error_response(ApiFormat::OpenAi, StatusCode::INTERNAL_SERVER_ERROR, "api_error", &format!("Routing failed: {}", e), None));
+        }
+    };
+
+    if claude_req.stream.unwrap_or(false) {
+        let (route, result) = send_with_fallback(&router, &router_request, &routes, |route| {
+            let (provider_client, openai_req, claude_req, config) = (&provider_client, &openai_req, &claude_req, &config);
+            async move {
+                provider_client.send_openai_stream_request(&route, openai_req, claude_req, config).await
+            }
+        }).await;
+        return match result {
+            Ok(events) => Ok(with_route_header(event_stream_response(events), &route)),
+            Err(e) => {
+                log::error!("Provider error: {}", e);
+                Ok(provider_error_response(ApiFormat::OpenAi, e.as_ref()))
+            }
+        };
+    }
+
+    let (route, result) = send_with_fallback(&router, &router_request, &routes, |route| {
+        let (provider_client, openai_req, claude_req, config) = (&provider_client, &openai_req, &claude_req, &config);
+        async move {
+            provider_client.send_openai_request(&route, openai_req, claude_req, config).await
+        }
+    }).await;
+    match result {
+        Ok(provider_response) => {
+            Ok(with_route_header(Response::builder()
+                .status(StatusCode::OK)
                 .header("Content-Type", "application/json")
-                .body(Body::from(format!(r#"{{"error":"Provider request failed: {}"}}"#, e)))
-                .unwrap())
+                .body(Body::from(provider_response.to_string()))
+                .unwrap(), &route))
+        }
+        Err(e) => {
+            log::error!("Provider error: {}", e);
+            Ok(provider_error_response(ApiFormat::OpenAi, e.as_ref()))
         }
     }
 }
 
+/// Build the ClaudeRequest view of an OpenAI request that routing and transformers work on
+fn claude_request_from_openai(openai_req: &Value) -> ClaudeRequest {
+    let messages = openai_req
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .map(|messages| MessageTransformer::transform_messages_from_openai(messages))
+        .unwrap_or_default();
+
+    ClaudeRequest {
+        model: openai_req.get("model").and_then(|m| m.as_str()).unwrap_or_default().to_string(),
+        messages,
+        system: None,
+        tools: None,
+        thinking: None,
+        max_tokens: openai_req
+            .get("max_tokens")
+            .or_else(|| openai_req.get("max_completion_tokens"))
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32),
+        temperature: openai_req.get("temperature").and_then(|v| v.as_f64()).map(|v| v as f32),
+        stream: openai_req.get("stream").and_then(|v| v.as_bool()),
+        metadata: None,
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::Config;
     use hyper::Client;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn test_server_health_check() {
         let config = Config {
             providers: vec![],
             router: crate::config::RouterConfig {
-                default: "test".to_string(),
+                default: "test".into(),
                 background: None,
                 think: None,
                 long_context: None,
                 web_search: None,
+                pools: Default::default(),
             },
             apikey: None,
             host: Some("127.0.0.1:0".to_string()),
             log: None,
+            retry: None,
+            api_timeout_ms: None,
+            connect_timeout_ms: None,
+            stream_idle_timeout_ms: None,
+            health: None,
+            proxy_url: None,
+            no_proxy: None,
+            display_model: None,
         };
-        let mut server = Server::new(config);
+        let server = Server::new(config);
         
         let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
         let config = server.config.clone();
@@ -346,4 +712,1875 @@ mod tests {
         
         assert_eq!(resp.status(), 200);
     }
-}
\ No newline at end of file
+
+    /// Start a stub upstream that answers every request with `handler(request_json)`
+    async fn spawn_stub_provider<F>(handler: F) -> SocketAddr
+    where
+        F: Fn(Value) -> Response<Body> + Clone + Send + Sync + 'static,
+    {
+        spawn_stub_server(move |_, json| handler(json)).await
+    }
+
+    /// Like spawn_stub_provider, but the handler also sees the request line and headers
+    async fn spawn_stub_server<F>(handler: F) -> SocketAddr
+    where
+        F: Fn(hyper::http::request::Parts, Value) -> Response<Body> + Clone + Send + Sync + 'static,
+    {
+        let make_svc = make_service_fn(move |_conn| {
+            let handler = handler.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let handler = handler.clone();
+                    async move {
+                        let (parts, body) = req.into_parts();
+                        let bytes = hyper::body::to_bytes(body).await.unwrap();
+                        let json: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+                        Ok::<_, Infallible>(handler(parts, json))
+                    }
+                }))
+            }
+        });
+        let server = HyperServer::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            let _ = server.await;
+        });
+        addr
+    }
+
+    /// Start a stub upstream that waits `delay` before answering
+    async fn spawn_slow_provider(delay: std::time::Duration) -> SocketAddr {
+        let make_svc = make_service_fn(move |_conn| async move {
+            Ok::<_, Infallible>(service_fn(move |_req: Request<Body>| async move {
+                tokio::time::sleep(delay).await;
+                Ok::<_, Infallible>(Response::new(Body::from(r#"{"choices":[]}"#)))
+            }))
+        });
+        let server = HyperServer::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            let _ = server.await;
+        });
+        addr
+    }
+
+    /// Start the router itself and return its address
+    async fn spawn_router(config: Config) -> SocketAddr {
+        let server = Server::new(config);
+        let config = server.config.clone();
+        let router = server.router.clone();
+        let provider_client = server.provider_client.clone();
+
+        let make_svc = make_service_fn(move |_conn| {
+            let config = config.clone();
+            let router = router.clone();
+            let provider_client = provider_client.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    handle_request(req, config.clone(), router.clone(), provider_client.clone())
+                }))
+            }
+        });
+        let server = HyperServer::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            let _ = server.await;
+        });
+        addr
+    }
+
+    fn stub_config(upstream: SocketAddr) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "Providers": [{
+                "name": "stub",
+                "api_base_url": format!("http://{}/v1", upstream),
+                "api_key": "test-key",
+                "models": ["stub-model"]
+            }],
+            "Router": {"default": "stub,stub-model"},
+            "RETRY": {"base_delay_ms": 1}
+        }))
+        .unwrap()
+    }
+
+    async fn post_json(addr: SocketAddr, path: &str, body: Value) -> (StatusCode, hyper::HeaderMap, String) {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}{}", addr, path))
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let resp = Client::new().request(req).await.unwrap();
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, headers, String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    #[tokio::test]
+    async fn test_streaming_messages_are_converted_to_anthropic_events() {
+        let upstream = spawn_stub_provider(|request| {
+            assert_eq!(request["stream"], true);
+            let sse = concat!(
+                "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hi\"}}]}\n\n",
+                "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\" there\"},\"finish_reason\":\"stop\"}]}\n\n",
+                "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2}}\n\n",
+                "data: [DONE]\n\n"
+            );
+            Response::builder()
+                .header("Content-Type", "text/event-stream")
+                .body(Body::from(sse))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+
+        let (status, headers, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "max_tokens": 100,
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "text/event-stream");
+        let event_names: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("event: "))
+            .collect();
+        assert_eq!(event_names, vec![
+            "message_start",
+            "ping",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert!(body.contains(r#""text":" there""#));
+        assert!(body.contains(r#""output_tokens":2"#));
+    }
+
+    #[tokio::test]
+    async fn test_non_streaming_messages_still_return_json() {
+        let upstream = spawn_stub_provider(|request| {
+            assert_eq!(request["stream"], false);
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                    "usage": {"prompt_tokens": 5, "completion_tokens": 1}
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["text"], "Hi");
+        assert_eq!(json["stop_reason"], "end_turn");
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_endpoint() {
+        let addr = spawn_router(stub_config("127.0.0.1:9".parse().unwrap())).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages/count_tokens", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "system": [{"type": "text", "text": "You are a helpful assistant."}],
+            "tools": [{"name": "search", "description": "Search the web", "input_schema": {"type": "object"}}],
+            "messages": [
+                {"role": "user", "content": "What is the weather?"},
+                {"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "search", "input": {"q": "weather"}}]},
+                {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "t1", "content": "Sunny"}]}
+            ]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        let tokens = json["input_tokens"].as_u64().unwrap();
+        assert!(tokens > 20 && tokens < 100, "unexpected estimate {}", tokens);
+
+        // Tools without a description, including server tools, are counted too
+        let count = |tools: Value| {
+            post_json(addr, "/v1/messages/count_tokens", serde_json::json!({
+                "model": "claude-3-5-sonnet",
+                "tools": tools,
+                "messages": [{"role": "user", "content": "hi"}]
+            }))
+        };
+        let (_, _, body) = count(serde_json::json!([])).await;
+        let base = serde_json::from_str::<Value>(&body).unwrap()["input_tokens"].as_u64().unwrap();
+        let (status, _, body) = count(serde_json::json!([
+            {"name": "read_file", "input_schema": {"type": "object", "properties": {"path": {"type": "string"}}}},
+            {"type": "web_search_20250305", "name": "web_search"}
+        ]))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let with_tools = serde_json::from_str::<Value>(&body).unwrap()["input_tokens"].as_u64().unwrap();
+        assert!(with_tools >= base + 15, "tools were not counted: {} vs {}", with_tools, base);
+    }
+
+    #[tokio::test]
+    async fn test_openai_chat_completions_passthrough() {
+        let upstream = spawn_stub_provider(|request| {
+            assert_eq!(request["model"], "stub-model");
+            assert_eq!(request["messages"][0]["role"], "system");
+            assert_eq!(request["tools"][0]["function"]["name"], "search");
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/chat/completions", serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": "Hello"}
+            ],
+            "tools": [{"type": "function", "function": {"name": "search", "parameters": {"type": "object"}}}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["object"], "chat.completion");
+        assert_eq!(json["choices"][0]["message"]["content"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn test_openai_chat_completions_streaming() {
+        let upstream = spawn_stub_provider(|request| {
+            assert_eq!(request["stream"], true);
+            let sse = concat!(
+                "data: {\"object\":\"chat.completion.chunk\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+                "data: [DONE]\n\n"
+            );
+            Response::builder()
+                .header("Content-Type", "text/event-stream")
+                .body(Body::from(sse))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+
+        let (status, headers, body) = post_json(addr, "/v1/chat/completions", serde_json::json!({
+            "model": "gpt-4o",
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "text/event-stream");
+        let frames: Vec<&str> = body.split("\n\n").filter(|f| !f.is_empty()).collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].starts_with("data: {") && frames[0].contains("chat.completion.chunk"));
+        assert_eq!(frames[1], "data: [DONE]");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_maps_to_anthropic_error() {
+        let upstream = spawn_stub_provider(|_| {
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("retry-after", "12")
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"error":{"message":"Rate limit reached for model","type":"tokens"}}"#))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+
+        let (status, headers, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers["retry-after"], "12");
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["error"]["type"], "rate_limit_error");
+        assert_eq!(json["error"]["message"], "HTTP 429: Rate limit reached for model");
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_maps_to_openai_error_on_chat_completions() {
+        let upstream = spawn_stub_provider(|_| {
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Body::from("upstream overloaded"))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/chat/completions", serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"]["code"], 503);
+        assert_eq!(json["error"]["type"], "overloaded_error");
+        assert_eq!(json["error"]["message"], "HTTP 503: upstream overloaded");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_provider_and_bad_json_errors() {
+        // Port 9 (discard) is not listening, so the connection is refused
+        let addr = spawn_router(stub_config("127.0.0.1:9".parse().unwrap())).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"]["type"], "api_error");
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({"messages": "nope"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"]["type"], "invalid_request_error");
+    }
+
+    #[tokio::test]
+    async fn test_provider_timeout_is_reported_as_timeout_error() {
+        let upstream = spawn_slow_provider(std::time::Duration::from_secs(5)).await;
+        let mut config = stub_config(upstream);
+        config.api_timeout_ms = Some(5000);
+        config.providers[0].api_timeout_ms = Some(200);
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"]["type"], "timeout_error");
+        assert_eq!(json["error"]["message"], "Request timed out: provider stub did not respond within 200ms");
+    }
+
+    #[tokio::test]
+    async fn test_stalled_stream_ends_with_timeout_event() {
+        let upstream = spawn_stub_provider(|_| {
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                let chunk = "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n";
+                let _ = sender.send_data(chunk.into()).await;
+                // Keep the connection open without sending anything else
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                drop(sender);
+            });
+            Response::builder()
+                .header("Content-Type", "text/event-stream")
+                .body(body)
+                .unwrap()
+        })
+        .await;
+        let mut config = stub_config(upstream);
+        config.stream_idle_timeout_ms = Some(200);
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"text\":\"Hi\""));
+        assert!(body.contains("event: error"));
+        assert!(body.contains("timeout_error"));
+        assert!(body.contains("provider stub sent no data for 200ms"));
+    }
+
+    #[tokio::test]
+    async fn test_active_stream_outlives_total_timeout() {
+        let upstream = spawn_stub_provider(|_| {
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                for word in ["one ", "two ", "three ", "four ", "five"] {
+                    let chunk = format!("data: {{\"choices\":[{{\"index\":0,\"delta\":{{\"content\":\"{}\"}}}}]}}\n\n", word);
+                    let _ = sender.send_data(chunk.into()).await;
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                }
+                let end = "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n";
+                let _ = sender.send_data(end.into()).await;
+            });
+            Response::builder()
+                .header("Content-Type", "text/event-stream")
+                .body(body)
+                .unwrap()
+        })
+        .await;
+        let mut config = stub_config(upstream);
+        config.api_timeout_ms = Some(250);
+        config.stream_idle_timeout_ms = Some(2000);
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "stream": true,
+            "messages": [{"role": "user", "content": "Count"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"text\":\"five\""), "{}", body);
+        assert!(body.contains("event: message_stop"));
+        assert!(!body.contains("event: error"));
+    }
+
+    fn anthropic_stub_config(upstream: SocketAddr) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "Providers": [{
+                "name": "native",
+                "type": "anthropic",
+                "api_base_url": format!("http://{}", upstream),
+                "api_key": "native-key",
+                "models": ["claude-upstream"]
+            }],
+            "Router": {"default": "native,claude-upstream"}
+        }))
+        .unwrap()
+    }
+
+    fn assert_anthropic_request(parts: &hyper::http::request::Parts, request: &Value) {
+        assert_eq!(parts.uri.path(), "/v1/messages");
+        assert_eq!(parts.headers["x-api-key"], "native-key");
+        assert_eq!(parts.headers["anthropic-version"], "2023-06-01");
+        assert!(parts.headers.get("authorization").is_none());
+        assert_eq!(request["model"], "claude-upstream");
+        assert_eq!(request["top_k"], 3);
+        assert_eq!(request["messages"][0]["content"][0]["cache_control"]["type"], "ephemeral");
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_provider_passthrough() {
+        let upstream = spawn_stub_server(|parts, request| {
+            assert_anthropic_request(&parts, &request);
+            assert_eq!(request["stream"], false);
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "id": "msg_upstream",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude-upstream",
+                    "content": [{"type": "text", "text": "native hello"}],
+                    "stop_reason": "end_turn",
+                    "usage": {"input_tokens": 4, "output_tokens": 2}
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(anthropic_stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "top_k": 3,
+            "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello", "cache_control": {"type": "ephemeral"}}]}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["id"], "msg_upstream");
+        assert_eq!(json["model"], "claude-3-5-sonnet");
+        assert_eq!(json["content"][0]["text"], "native hello");
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_provider_streaming_passthrough() {
+        let sse = concat!(
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_upstream\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"
+        );
+        let upstream = spawn_stub_server(move |parts, request| {
+            assert_anthropic_request(&parts, &request);
+            assert_eq!(request["stream"], true);
+            Response::builder()
+                .header("Content-Type", "text/event-stream")
+                .body(Body::from(sse))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(anthropic_stub_config(upstream)).await;
+
+        let (status, headers, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "stream": true,
+            "top_k": 3,
+            "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello", "cache_control": {"type": "ephemeral"}}]}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["content-type"], "text/event-stream");
+        // Only the model of message_start is rewritten
+        let expected = sse.replacen(
+            r#"{"type":"message_start","message":{"id":"msg_upstream"}}"#,
+            &serde_json::json!({"type": "message_start", "message": {"id": "msg_upstream", "model": "claude-3-5-sonnet"}}).to_string(),
+            1,
+        );
+        assert_ne!(expected, sse);
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_chat_completions_cannot_target_anthropic_provider() {
+        let addr = spawn_router(anthropic_stub_config("127.0.0.1:9".parse().unwrap())).await;
+
+        let (status, _, body) = post_json(addr, "/v1/chat/completions", serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"]["type"], "invalid_request_error");
+    }
+
+    fn gemini_stub_config(upstream: SocketAddr) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "Providers": [{
+                "name": "gemini",
+                "type": "gemini",
+                "api_base_url": format!("http://{}/v1beta", upstream),
+                "api_key": "goog-key",
+                "models": ["gemini-2.5-pro"]
+            }],
+            "Router": {"default": "gemini,gemini-2.5-pro"}
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_gemini_provider_generate_content() {
+        let upstream = spawn_stub_server(|parts, request| {
+            assert_eq!(parts.uri.path(), "/v1beta/models/gemini-2.5-pro:generateContent");
+            assert_eq!(parts.headers["x-goog-api-key"], "goog-key");
+            assert_eq!(request["systemInstruction"]["parts"][0]["text"], "Be brief.");
+            assert_eq!(request["contents"][0]["parts"][0]["text"], "Weather in Paris?");
+            assert_eq!(request["tools"][0]["functionDeclarations"][0]["name"], "get_weather");
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "candidates": [{
+                        "content": {"role": "model", "parts": [{"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}]},
+                        "finishReason": "STOP"
+                    }],
+                    "usageMetadata": {"promptTokenCount": 20, "candidatesTokenCount": 7}
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(gemini_stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "system": "Be brief.",
+            "messages": [{"role": "user", "content": "Weather in Paris?"}],
+            "tools": [{"name": "get_weather", "description": "Weather lookup", "input_schema": {"type": "object", "properties": {"city": {"type": "string"}}}}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["type"], "tool_use");
+        assert_eq!(json["content"][0]["name"], "get_weather");
+        assert_eq!(json["content"][0]["input"]["city"], "Paris");
+        assert_eq!(json["stop_reason"], "tool_use");
+        assert_eq!(json["usage"]["input_tokens"], 20);
+        assert_eq!(json["usage"]["output_tokens"], 7);
+    }
+
+    #[tokio::test]
+    async fn test_gemini_provider_sanitizes_and_restores_tool_names() {
+        let upstream = spawn_stub_server(|_, request| {
+            let name = request["tools"][0]["functionDeclarations"][0]["name"].as_str().unwrap().to_string();
+            assert!(crate::tool_names::is_valid_tool_name(&name), "{}", name);
+            assert_eq!(request["contents"][1]["parts"][0]["functionCall"]["name"], name);
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "candidates": [{
+                        "content": {"role": "model", "parts": [{"functionCall": {"name": name, "args": {"path": "b"}}}]},
+                        "finishReason": "STOP"
+                    }]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(gemini_stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [
+                {"role": "user", "content": "Read a, then b"},
+                {"role": "assistant", "content": [{"type": "tool_use", "id": "toolu_1", "name": "files.read", "input": {"path": "a"}}]},
+                {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "toolu_1", "content": "A"}]}
+            ],
+            "tools": [{"name": "files.read", "input_schema": {"type": "object", "properties": {"path": {"type": "string"}}}}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["name"], "files.read");
+        assert_eq!(json["content"][0]["input"]["path"], "b");
+    }
+
+    #[tokio::test]
+    async fn test_gemini_provider_stream_generate_content() {
+        let upstream = spawn_stub_server(|parts, _| {
+            assert_eq!(parts.uri.path(), "/v1beta/models/gemini-2.5-pro:streamGenerateContent");
+            assert_eq!(parts.uri.query(), Some("alt=sse"));
+            let sse = concat!(
+                "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"Bon\"}]}}]}\r\n\r\n",
+                "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"jour\"}]},\"finishReason\":\"STOP\"}],",
+                "\"usageMetadata\":{\"promptTokenCount\":4,\"candidatesTokenCount\":2}}\r\n\r\n"
+            );
+            Response::builder()
+                .header("Content-Type", "text/event-stream")
+                .body(Body::from(sse))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(gemini_stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let deltas: Vec<Value> = body
+            .split("\n\n")
+            .filter_map(|frame| frame.lines().find_map(|line| line.strip_prefix("data: ")))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let text: String = deltas
+            .iter()
+            .filter_map(|event| event.pointer("/delta/text").and_then(|t| t.as_str()))
+            .collect();
+        assert_eq!(text, "Bonjour");
+        let message_delta = deltas.iter().find(|event| event["type"] == "message_delta").unwrap();
+        assert_eq!(message_delta["delta"]["stop_reason"], "end_turn");
+        assert_eq!(message_delta["usage"]["output_tokens"], 2);
+        assert_eq!(deltas.last().unwrap()["type"], "message_stop");
+    }
+
+    fn ollama_stub_config(upstream: SocketAddr) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "Providers": [{
+                "name": "local",
+                "type": "ollama",
+                "api_base_url": format!("http://{}", upstream),
+                "api_key": "",
+                "models": ["qwen3:8b"],
+                "model_options": {"qwen3:8b": {"num_ctx": 16384, "keep_alive": "1h"}}
+            }],
+            "Router": {"default": "local,qwen3:8b"}
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ollama_provider_chat() {
+        let upstream = spawn_stub_server(|parts, request| {
+            assert_eq!(parts.uri.path(), "/api/chat");
+            assert!(parts.headers.get("authorization").is_none());
+            assert_eq!(request["model"], "qwen3:8b");
+            assert_eq!(request["stream"], false);
+            assert_eq!(request["options"]["num_ctx"], 16384);
+            assert_eq!(request["keep_alive"], "1h");
+            assert_eq!(request["tools"][0]["function"]["name"], "read_file");
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "model": "qwen3:8b",
+                    "message": {"role": "assistant", "content": "", "tool_calls": [
+                        {"function": {"name": "read_file", "arguments": {"path": "Cargo.toml"}}}
+                    ]},
+                    "done": true,
+                    "done_reason": "stop",
+                    "prompt_eval_count": 42,
+                    "eval_count": 11
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(ollama_stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Open Cargo.toml"}],
+            "tools": [{"name": "read_file", "description": "Read a file", "input_schema": {"type": "object", "properties": {"path": {"type": "string"}}}}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["type"], "tool_use");
+        assert_eq!(json["content"][0]["input"]["path"], "Cargo.toml");
+        assert_eq!(json["stop_reason"], "tool_use");
+        assert_eq!(json["usage"]["input_tokens"], 42);
+    }
+
+    #[tokio::test]
+    async fn test_ollama_provider_ndjson_stream() {
+        let upstream = spawn_stub_server(|_, request| {
+            assert_eq!(request["stream"], true);
+            let ndjson = concat!(
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"Hel\"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"lo\"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":6,\"eval_count\":2}\n"
+            );
+            Response::builder()
+                .header("Content-Type", "application/x-ndjson")
+                .body(Body::from(ndjson))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(ollama_stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let events: Vec<Value> = body
+            .split("\n\n")
+            .filter_map(|frame| frame.lines().find_map(|line| line.strip_prefix("data: ")))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let text: String = events
+            .iter()
+            .filter_map(|event| event.pointer("/delta/text").and_then(|t| t.as_str()))
+            .collect();
+        assert_eq!(text, "Hello");
+        let message_delta = events.iter().find(|event| event["type"] == "message_delta").unwrap();
+        assert_eq!(message_delta["delta"]["stop_reason"], "end_turn");
+        assert_eq!(message_delta["usage"]["input_tokens"], 6);
+        assert_eq!(events.last().unwrap()["type"], "message_stop");
+    }
+
+    #[tokio::test]
+    async fn test_ollama_stream_restores_sanitized_tool_names() {
+        let upstream = spawn_stub_server(|_, request| {
+            let name = request["tools"][0]["function"]["name"].as_str().unwrap().to_string();
+            assert!(crate::tool_names::is_valid_tool_name(&name), "{}", name);
+            let done = serde_json::json!({
+                "message": {"role": "assistant", "content": "", "tool_calls": [
+                    {"function": {"name": name, "arguments": {"path": "Cargo.toml"}}}
+                ]},
+                "done": true,
+                "done_reason": "stop"
+            });
+            Response::builder()
+                .header("Content-Type", "application/x-ndjson")
+                .body(Body::from(format!("{}\n", done)))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(ollama_stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "stream": true,
+            "messages": [{"role": "user", "content": "Open Cargo.toml"}],
+            "tools": [{"name": "files.read", "input_schema": {"type": "object", "properties": {"path": {"type": "string"}}}}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let events: Vec<Value> = body
+            .split("\n\n")
+            .filter_map(|frame| frame.lines().find_map(|line| line.strip_prefix("data: ")))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let block_start = events.iter().find(|event| event["type"] == "content_block_start").unwrap();
+        assert_eq!(block_start["content_block"]["type"], "tool_use");
+        assert_eq!(block_start["content_block"]["name"], "files.read");
+    }
+
+    #[tokio::test]
+    async fn test_azure_provider_uses_deployment_urls_and_api_key() {
+        let upstream = spawn_stub_server(|parts, request| {
+            assert_eq!(parts.uri.path(), "/openai/deployments/prod-gpt4o/chat/completions");
+            assert_eq!(parts.uri.query(), Some("api-version=2024-06-01"));
+            assert_eq!(parts.headers["api-key"], "azure-key");
+            assert!(parts.headers.get("authorization").is_none());
+            assert_eq!(request["messages"][0]["content"], "Hello");
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "object": "chat.completion",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "from azure"}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [{
+                "name": "azure",
+                "type": "azure",
+                "api_base_url": format!("http://{}", upstream),
+                "api_key": "azure-key",
+                "models": ["gpt-4o"],
+                "api_version": "2024-06-01",
+                "deployments": {"gpt-4o": "prod-gpt4o"}
+            }],
+            "Router": {"default": "azure,gpt-4o"}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["text"], "from azure");
+
+        let (status, _, body) = post_json(addr, "/v1/chat/completions", serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["choices"][0]["message"]["content"], "from azure");
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_retried_on_same_provider() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let upstream = spawn_stub_provider(move |_| {
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from("warming up"))
+                    .unwrap(),
+                1 => Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header("retry-after", "0")
+                    .body(Body::from("slow down"))
+                    .unwrap(),
+                _ => Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#))
+                    .unwrap(),
+            }
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["text"], "ok");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_and_rejected_keys_are_rotated_out() {
+        let upstream = spawn_stub_server(|parts, _| {
+            let key = parts.headers.get("authorization").unwrap().to_str().unwrap().to_string();
+            match key.as_str() {
+                "Bearer key-limited-0001" => Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header("retry-after", "30")
+                    .body(Body::from("slow down"))
+                    .unwrap(),
+                "Bearer key-revoked-0002" => Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::from("invalid key"))
+                    .unwrap(),
+                _ => Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#))
+                    .unwrap(),
+            }
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [{
+                "name": "stub",
+                "api_base_url": format!("http://{}/v1", upstream),
+                "api_keys": ["key-limited-0001", "key-revoked-0002", "key-healthy-0003"],
+                "models": ["stub-model"]
+            }],
+            "Router": {"default": "stub,stub-model"},
+            "RETRY": {"max_attempts": 1}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        for _ in 0..2 {
+            let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+                "model": "claude-3-5-sonnet",
+                "messages": [{"role": "user", "content": "Hello"}]
+            }))
+            .await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+
+        let resp = Client::new()
+            .get(format!("http://{}/admin/keys", addr).parse().unwrap())
+            .await
+            .unwrap();
+        let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let usage: Value = serde_json::from_slice(&bytes).unwrap();
+        let keys = usage["stub"].as_array().unwrap();
+        assert_eq!(keys[0]["rate_limited"], 1);
+        assert!(keys[0]["cooldown_remaining_ms"].as_u64().unwrap() > 0);
+        assert_eq!(keys[1]["unauthorized"], 1);
+        assert_eq!(keys[1]["disabled"], true);
+        // The second request went straight to the only usable key
+        assert_eq!(keys[2]["requests"], 2);
+        assert_eq!(keys[2]["successes"], 2);
+        assert_eq!(keys[2]["key"], "key-...0003");
+    }
+
+    #[tokio::test]
+    async fn test_pool_conversation_sticks_to_answering_member() {
+        let failing_calls = Arc::new(AtomicUsize::new(0));
+        let counter = failing_calls.clone();
+        let failing = spawn_stub_provider(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Body::from("down"))
+                .unwrap()
+        })
+        .await;
+        let healthy = spawn_stub_provider(|_| {
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "down", "api_base_url": format!("http://{}/v1", failing), "api_key": "k", "models": ["kimi"]},
+                {"name": "up", "api_base_url": format!("http://{}/v1", healthy), "api_key": "k", "models": ["kimi"]}
+            ],
+            "Router": {
+                "default": "kimi",
+                "pools": {"kimi": [{"route": "down,kimi"}, {"route": "up,kimi"}]}
+            },
+            "RETRY": {"max_attempts": 1}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        for _ in 0..5 {
+            let (status, headers, _) = post_json(addr, "/v1/messages", serde_json::json!({
+                "model": "claude-3-5-sonnet",
+                "metadata": {"user_id": "user_abc_session_1"},
+                "messages": [{"role": "user", "content": "Hello"}]
+            }))
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers.get(ROUTE_HEADER).unwrap(), "up,kimi");
+        }
+        // At most the very first request tried the failing member
+        assert!(failing_calls.load(Ordering::SeqCst) <= 1);
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_is_skipped_when_fallback_exists() {
+        let failing_calls = Arc::new(AtomicUsize::new(0));
+        let counter = failing_calls.clone();
+        let failing = spawn_stub_provider(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(Body::from("down"))
+                .unwrap()
+        })
+        .await;
+        let healthy = spawn_stub_provider(|_| {
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"choices":[{"message":{"role":"assistant","content":"ok"},"finish_reason":"stop"}]}"#))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "down", "api_base_url": format!("http://{}/v1", failing), "api_key": "k", "models": ["m1"]},
+                {"name": "up", "api_base_url": format!("http://{}/v1", healthy), "api_key": "k", "models": ["m2"]}
+            ],
+            "Router": {"default": ["down,m1", "up,m2"]},
+            "RETRY": {"max_attempts": 1},
+            "HEALTH": {"failure_threshold": 1, "open_ms": 60000, "probe_interval_ms": 0}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        for _ in 0..3 {
+            let (status, headers, _) = post_json(addr, "/v1/messages", serde_json::json!({
+                "model": "claude-3-5-sonnet",
+                "messages": [{"role": "user", "content": "Hello"}]
+            }))
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers.get(ROUTE_HEADER).unwrap(), "up,m2");
+        }
+        assert_eq!(failing_calls.load(Ordering::SeqCst), 1);
+
+        let resp = Client::new()
+            .get(format!("http://{}/admin/health", addr).parse().unwrap())
+            .await
+            .unwrap();
+        let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let health: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(health["down"]["state"], "open");
+        assert!(health["down"]["last_error"].as_str().unwrap().contains("502"));
+        assert_eq!(health["up"]["state"], "closed");
+        assert_eq!(health["up"]["requests"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_retries_of_one_request_count_as_one_circuit_failure() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let upstream = spawn_stub_provider(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(Body::from("down"))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [{"name": "flaky", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"]}],
+            "Router": {"default": "flaky,m"},
+            "RETRY": {"max_attempts": 3, "base_delay_ms": 1},
+            "HEALTH": {"failure_threshold": 3, "open_ms": 60000, "probe_interval_ms": 0}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(body.contains("HTTP 502: down"), "{}", body);
+
+        let resp = Client::new()
+            .get(format!("http://{}/admin/health", addr).parse().unwrap())
+            .await
+            .unwrap();
+        let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let health: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(health["flaky"]["state"], "closed");
+        assert_eq!(health["flaky"]["consecutive_failures"], 1);
+        assert_eq!(health["flaky"]["requests"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_requests_go_through_configured_proxy() {
+        // A forward proxy receives the absolute upstream URL and the proxy credentials
+        let proxy = spawn_stub_server(|parts, _| {
+            assert_eq!(parts.uri.host(), Some("api.provider.invalid"));
+            assert_eq!(parts.uri.path(), "/v1/chat/completions");
+            assert_eq!(parts.headers.get("proxy-authorization").unwrap(), "Basic dXNlcjpzM2NyZXQ=");
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"choices":[{"message":{"role":"assistant","content":"via proxy"},"finish_reason":"stop"}]}"#))
+                .unwrap()
+        })
+        .await;
+        let direct = spawn_stub_server(|parts, _| {
+            assert!(parts.headers.get("proxy-authorization").is_none());
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"choices":[{"message":{"role":"assistant","content":"direct"},"finish_reason":"stop"}]}"#))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "remote", "api_base_url": "http://api.provider.invalid/v1", "api_key": "k", "models": ["m"]},
+                {"name": "local", "api_base_url": format!("http://{}/v1", direct), "api_key": "k", "models": ["m"], "proxy_url": ""}
+            ],
+            "Router": {"default": "remote,m", "background": "local,m"},
+            "PROXY_URL": format!("http://user:s3cret@{}", proxy)
+        }))
+        .unwrap();
+        crate::proxy::validate(&config).unwrap();
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body.contains("via proxy"));
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-haiku-20241022",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body.contains("direct"));
+    }
+
+    #[tokio::test]
+    async fn test_images_for_text_only_providers() {
+        let upstream = spawn_stub_provider(|request| {
+            let content = request["messages"][0]["content"].as_str().unwrap().to_string();
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": content}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "placeholder", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"], "images": "placeholder"},
+                {"name": "strict", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"], "images": "reject"}
+            ],
+            "Router": {"default": "placeholder,m", "background": "strict,m"}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+        let content = serde_json::json!([
+            {"type": "text", "text": "What is this?"},
+            {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0"}}
+        ]);
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": content}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("image omitted"), "{}", body);
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-haiku-20241022",
+            "messages": [{"role": "user", "content": content}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("provider strict does not accept images"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_image_rejection_falls_back_to_next_route() {
+        let upstream = spawn_stub_provider(|request| {
+            let parts = request["messages"][0]["content"].as_array().map(|parts| parts.len()).unwrap_or(0);
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": format!("parts={}", parts)}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "strict", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"], "images": "reject"},
+                {"name": "vision", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"]}
+            ],
+            "Router": {"default": ["strict,m", "vision,m"]}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        let (status, headers, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": [
+                {"type": "text", "text": "What is this?"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0"}}
+            ]}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(headers.get(ROUTE_HEADER).unwrap(), "vision,m");
+        assert!(body.contains("parts=2"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_thinking_request_uses_think_route_and_returns_thinking_block() {
+        let upstream = spawn_stub_provider(|request| {
+            let body = serde_json::json!({
+                "choices": [{
+                    "message": {
+                        "role": "assistant",
+                        "content": format!("effort={}", request["reasoning_effort"]),
+                        "reasoning_content": "6 times 7"
+                    },
+                    "finish_reason": "stop"
+                }]
+            });
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "fast", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"]},
+                {"name": "reasoner", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["r1"], "reasoning": "reasoning_effort"}
+            ],
+            "Router": {"default": "fast,m", "think": "reasoner,r1"}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        let (status, headers, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-sonnet-4",
+            "max_tokens": 20000,
+            "thinking": {"type": "enabled", "budget_tokens": 10000},
+            "messages": [{"role": "user", "content": "What is 6 times 7?"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(headers[ROUTE_HEADER], "reasoner,r1");
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["type"], "thinking");
+        assert_eq!(json["content"][0]["thinking"], "6 times 7");
+        assert_eq!(json["content"][0]["signature"], crate::thinking::signature("6 times 7"));
+        assert_eq!(json["content"][1]["text"], "effort=\"medium\"");
+
+        let (_, headers, _) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-sonnet-4",
+            "thinking": {"type": "disabled"},
+            "messages": [{"role": "user", "content": "Hi"}]
+        }))
+        .await;
+        assert_eq!(headers[ROUTE_HEADER], "fast,m");
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt_is_sent_as_system_message() {
+        let upstream = spawn_stub_provider(|request| {
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": request["messages"].to_string()}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "openai", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"]},
+                {"name": "nosystem", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"], "system_prompt": "merge_into_user"}
+            ],
+            "Router": {"default": "openai,m", "background": "nosystem,m"}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+        let request = |model: &str| serde_json::json!({
+            "model": model,
+            "system": [
+                {"type": "text", "text": "You are Claude Code."},
+                {"type": "text", "text": "Be brief.", "cache_control": {"type": "ephemeral"}}
+            ],
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+
+        let (status, _, body) = post_json(addr, "/v1/messages", request("claude-sonnet-4")).await;
+        assert_eq!(status, StatusCode::OK);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        let messages: Value = serde_json::from_str(json["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(messages, serde_json::json!([
+            {"role": "system", "content": "You are Claude Code.\n\nBe brief."},
+            {"role": "user", "content": "Hi"}
+        ]));
+
+        let (_, _, body) = post_json(addr, "/v1/messages", request("claude-3-5-haiku-20241022")).await;
+        let json: Value = serde_json::from_str(&body).unwrap();
+        let messages: Value = serde_json::from_str(json["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(messages, serde_json::json!([
+            {"role": "user", "content": "You are Claude Code.\n\nBe brief.\n\nHi"}
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_sampling_and_tool_choice_fields_are_mapped() {
+        let upstream = spawn_stub_provider(|request| {
+            let echoed = serde_json::json!({
+                "tool_choice": request["tool_choice"],
+                "parallel_tool_calls": request["parallel_tool_calls"],
+                "stop": request["stop"],
+                "top_p": request["top_p"],
+                "top_k": request["top_k"],
+                "user": request["user"]
+            });
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{
+                        "message": {"role": "assistant", "content": echoed.to_string()},
+                        "finish_reason": "stop",
+                        "stop_reason": "###"
+                    }]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-sonnet-4",
+            "messages": [{"role": "user", "content": "Weather?"}],
+            "tools": [{"name": "get_weather", "description": "Weather", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "tool", "name": "get_weather", "disable_parallel_tool_use": true},
+            "stop_sequences": ["###"],
+            "top_p": 0.5,
+            "top_k": 40,
+            "metadata": {"user_id": "user_abc_session_1"}
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        let echoed: Value = serde_json::from_str(json["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(echoed, serde_json::json!({
+            "tool_choice": {"type": "function", "function": {"name": "get_weather"}},
+            "parallel_tool_calls": false,
+            "stop": ["###"],
+            "top_p": 0.5,
+            "top_k": 40,
+            "user": "user_abc_session_1"
+        }));
+        assert_eq!(json["stop_reason"], "stop_sequence");
+        assert_eq!(json["stop_sequence"], "###");
+    }
+
+    #[tokio::test]
+    async fn test_responses_have_full_messages_shape() {
+        let upstream = spawn_stub_provider(|request| {
+            let body = match request["messages"][0]["content"].as_str().unwrap() {
+                "cached" => serde_json::json!({
+                    "choices": [
+                        {"index": 0, "message": {"role": "assistant", "content": "Checking"}, "finish_reason": "stop"},
+                        {"index": 1, "message": {"role": "assistant", "content": null, "tool_calls": [{
+                            "id": "call_1", "type": "function", "function": {"name": "ls", "arguments": "{}"}
+                        }]}, "finish_reason": "stop"}
+                    ],
+                    "usage": {"prompt_tokens": 1000, "completion_tokens": 20, "prompt_tokens_details": {"cached_tokens": 800}}
+                }),
+                "filtered" => serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": null, "refusal": "I can't help with that."}, "finish_reason": "content_filter"}]
+                }),
+                _ => serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": "Hello there, how can I help?"}, "finish_reason": "insufficient_system_resource"}]
+                }),
+            };
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+        let request = |content: &str| serde_json::json!({
+            "model": "claude-sonnet-4",
+            "messages": [{"role": "user", "content": content}]
+        });
+
+        let (status, _, body) = post_json(addr, "/v1/messages", request("cached")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert!(json["id"].as_str().unwrap().starts_with("msg_"));
+        assert_eq!(json["model"], "claude-sonnet-4");
+        assert_eq!(json["content"][0]["text"], "Checking");
+        assert_eq!(json["content"][1]["type"], "tool_use");
+        assert_eq!(json["stop_reason"], "tool_use");
+        assert_eq!(json["stop_sequence"], Value::Null);
+        assert_eq!(json["usage"], serde_json::json!({
+            "input_tokens": 200,
+            "output_tokens": 20,
+            "cache_creation_input_tokens": 0,
+            "cache_read_input_tokens": 800
+        }));
+
+        let (_, _, body) = post_json(addr, "/v1/messages", request("filtered")).await;
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["stop_reason"], "refusal");
+        assert_eq!(json["content"][0]["text"], "I can't help with that.");
+
+        // Unknown finish reasons end the turn; missing usage is estimated
+        let (_, _, body) = post_json(addr, "/v1/messages", request("hi")).await;
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["stop_reason"], "end_turn");
+        assert_eq!(json["usage"]["output_tokens"], 7);
+        let (_, _, counted) = post_json(addr, "/v1/messages/count_tokens", request("hi")).await;
+        let counted: Value = serde_json::from_str(&counted).unwrap();
+        assert_eq!(json["usage"]["input_tokens"], counted["input_tokens"]);
+
+        let mut config = stub_config(upstream);
+        config.display_model = Some("kimi-k2 via groq".to_string());
+        let addr = spawn_router(config).await;
+        let (_, _, body) = post_json(addr, "/v1/messages", request("hi")).await;
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["model"], "kimi-k2 via groq");
+    }
+
+    #[tokio::test]
+    async fn test_long_tool_names_are_sanitized_and_restored() {
+        let upstream = spawn_stub_provider(|request| {
+            let names: Vec<&str> = request["tools"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|tool| tool["function"]["name"].as_str().unwrap())
+                .collect();
+            assert!(names.iter().all(|name| crate::tool_names::is_valid_tool_name(name)), "{:?}", names);
+            let history_name = request["messages"][1]["tool_calls"][0]["function"]["name"].clone();
+            assert_eq!(history_name, names[0]);
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": null, "tool_calls": [{
+                        "id": "call_2", "type": "function", "function": {"name": names[0], "arguments": "{\"q\":\"x\"}"}
+                    }]}, "finish_reason": "tool_calls"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+        let long_name = "mcp__claude_ai_Atlassian_Confluence__searchConfluenceUsingCqlWithPagination";
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-sonnet-4",
+            "messages": [
+                {"role": "user", "content": "Find the design doc"},
+                {"role": "assistant", "content": [{"type": "tool_use", "id": "call_1", "name": long_name, "input": {"q": "design"}}]},
+                {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "call_1", "content": "nothing"}]}
+            ],
+            "tools": [
+                {"name": long_name, "description": "Search", "input_schema": {"type": "object"}},
+                {"name": "Bash", "description": "Run", "input_schema": {"type": "object"}}
+            ]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["name"], long_name);
+        assert_eq!(json["content"][0]["input"]["q"], "x");
+    }
+
+    #[tokio::test]
+    async fn test_schema_setting_overrides_transformer_profile() {
+        let upstream = spawn_stub_provider(|request| {
+            let parameters = request["tools"][0]["function"]["parameters"].to_string();
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": parameters}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "openrouter", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"],
+                 "transformer": {"use": ["openrouter"]}, "schema": "passthrough"},
+                {"name": "groq", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"],
+                 "transformer": {"use": ["openrouter"]}}
+            ],
+            "Router": {"default": "openrouter,m", "background": "groq,m"}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+        let input_schema = serde_json::json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "properties": {"path": {"type": "string", "format": "uri"}},
+            "additionalProperties": false
+        });
+        let parameters_sent = |model: &str, body: String| {
+            let json: Value = serde_json::from_str(&body).unwrap();
+            let text = json["content"][0]["text"].as_str().unwrap_or_else(|| panic!("{}: {}", model, body)).to_string();
+            serde_json::from_str::<Value>(&text).unwrap()
+        };
+
+        for (model, expected) in [
+            ("claude-sonnet-4", input_schema.clone()),
+            ("claude-3-5-haiku-20241022", serde_json::json!({"type": "object", "properties": {"path": {"type": "string"}}})),
+        ] {
+            let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+                "model": model,
+                "messages": [{"role": "user", "content": "Read it"}],
+                "tools": [{"name": "read", "description": "Read a URL", "input_schema": input_schema}]
+            }))
+            .await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            assert_eq!(parameters_sent(model, body), expected, "{}", model);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_tool_arguments_are_repaired_or_reported() {
+        let upstream = spawn_stub_provider(|request| {
+            let arguments = match request["messages"][0]["content"].as_str().unwrap() {
+                "repairable" => "```json\n{command: 'ls -la', timeout: 5,}\n```",
+                _ => "run ls please",
+            };
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": null, "tool_calls": [{
+                        "id": "call_1", "type": "function", "function": {"name": "Bash", "arguments": arguments}
+                    }]}, "finish_reason": "tool_calls"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+        let request = |content: &str| serde_json::json!({
+            "model": "claude-sonnet-4",
+            "messages": [{"role": "user", "content": content}],
+            "tools": [{"name": "Bash", "input_schema": {
+                "type": "object",
+                "properties": {"command": {"type": "string"}, "timeout": {"type": "number"}},
+                "required": ["command"]
+            }}]
+        });
+
+        let (status, _, body) = post_json(addr, "/v1/messages", request("repairable")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["input"], serde_json::json!({"command": "ls -la", "timeout": 5}));
+        assert_eq!(json["stop_reason"], "tool_use");
+
+        let (status, _, body) = post_json(addr, "/v1/messages", request("broken")).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY, "{}", body);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"]["type"], "api_error");
+        let message = json["error"]["message"].as_str().unwrap();
+        assert!(message.contains("tool call `Bash` (call_1): arguments are not valid JSON"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_tool_arguments_are_completed_or_reported() {
+        let upstream = spawn_stub_provider(|request| {
+            let fragments = match request["messages"][0]["content"].as_str().unwrap() {
+                "truncated" => ["{\"command\": \"ls", " -la\", \"timeout\": 5"],
+                _ => ["{'command':", " 'ls -la'}"],
+            };
+            let chunk = |delta: Value, finish: Value| {
+                format!("data: {}\n\n", serde_json::json!({
+                    "object": "chat.completion.chunk",
+                    "choices": [{"index": 0, "delta": delta, "finish_reason": finish}]
+                }))
+            };
+            let sse = chunk(serde_json::json!({"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "Bash", "arguments": fragments[0]}}]}), Value::Null)
+                + &chunk(serde_json::json!({"tool_calls": [{"index": 0, "function": {"arguments": fragments[1]}}]}), Value::Null)
+                + &chunk(serde_json::json!({}), serde_json::json!("tool_calls"))
+                + "data: [DONE]\n\n";
+            Response::builder()
+                .header("Content-Type", "text/event-stream")
+                .body(Body::from(sse))
+                .unwrap()
+        })
+        .await;
+        let addr = spawn_router(stub_config(upstream)).await;
+        let parse_events = |body: String| -> Vec<Value> {
+            body.split("\n\n")
+                .filter_map(|frame| frame.lines().find_map(|line| line.strip_prefix("data: ")))
+                .map(|data| serde_json::from_str(data).unwrap())
+                .collect()
+        };
+        let request = |content: &str| serde_json::json!({
+            "model": "claude-sonnet-4",
+            "stream": true,
+            "messages": [{"role": "user", "content": content}],
+            "tools": [{"name": "Bash", "description": "Run a command", "input_schema": {
+                "type": "object",
+                "properties": {"command": {"type": "string"}, "timeout": {"type": "number"}},
+                "required": ["command"]
+            }}]
+        });
+
+        // Fragments are forwarded as they arrive and the cut-off object is closed at the end
+        let (status, _, body) = post_json(addr, "/v1/messages", request("truncated")).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = parse_events(body);
+        let partial: Vec<&str> = events.iter().filter_map(|event| event.pointer("/delta/partial_json")?.as_str()).collect();
+        assert_eq!(partial, ["{\"command\": \"ls", " -la\", \"timeout\": 5", "}"]);
+        let input: Value = serde_json::from_str(&partial.concat()).unwrap();
+        assert_eq!(input, serde_json::json!({"command": "ls -la", "timeout": 5}));
+        let message_delta = events.iter().find(|event| event["type"] == "message_delta").unwrap();
+        assert_eq!(message_delta["delta"]["stop_reason"], "tool_use");
+
+        // Same api_error as the non-streaming 502, sent as the stream's last event
+        let (status, _, body) = post_json(addr, "/v1/messages", request("broken")).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = parse_events(body);
+        let error = events.last().unwrap();
+        assert_eq!(error["type"], "error");
+        assert_eq!(error["error"]["type"], "api_error");
+        let message = error["error"]["message"].as_str().unwrap();
+        assert!(message.contains("tool call `Bash` (call_1): arguments are not valid JSON"), "{}", message);
+        assert!(events.iter().all(|event| event["type"] != "message_stop"));
+    }
+
+    #[tokio::test]
+    async fn test_text_tool_calls_are_extracted_when_enabled() {
+        let upstream = spawn_stub_provider(|request| {
+            let text = "Checking.\n<tool_call>{\"name\": \"Bash\", \"arguments\": {\"command\": \"ls\"}}</tool_call>";
+            if request["stream"] == true {
+                let chunk = |delta: Value, finish: Value| {
+                    format!("data: {}\n\n", serde_json::json!({
+                        "object": "chat.completion.chunk",
+                        "choices": [{"index": 0, "delta": delta, "finish_reason": finish}]
+                    }))
+                };
+                let (first, second) = text.split_at(14);
+                let sse = chunk(serde_json::json!({"content": first}), Value::Null)
+                    + &chunk(serde_json::json!({"content": second}), Value::Null)
+                    + &chunk(serde_json::json!({}), serde_json::json!("stop"))
+                    + "data: [DONE]\n\n";
+                return Response::builder()
+                    .header("Content-Type", "text/event-stream")
+                    .body(Body::from(sse))
+                    .unwrap();
+            }
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": text}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let mut config = stub_config(upstream);
+        config.providers[0].text_tool_calls = Some(crate::config::TextToolCalls::Extract);
+        let addr = spawn_router(config).await;
+        let request = |stream: bool| serde_json::json!({
+            "model": "claude-sonnet-4",
+            "stream": stream,
+            "messages": [{"role": "user", "content": "List files"}],
+            "tools": [{"name": "Bash", "input_schema": {"type": "object"}}]
+        });
+
+        let (status, _, body) = post_json(addr, "/v1/messages", request(false)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0], serde_json::json!({"type": "text", "text": "Checking."}));
+        assert_eq!(json["content"][1]["type"], "tool_use");
+        assert_eq!(json["content"][1]["name"], "Bash");
+        assert_eq!(json["content"][1]["input"], serde_json::json!({"command": "ls"}));
+        assert!(json["content"][1]["id"].as_str().unwrap().starts_with("toolu_"));
+        assert_eq!(json["stop_reason"], "tool_use");
+
+        let (status, _, body) = post_json(addr, "/v1/messages", request(true)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(!body.contains("<tool_call>"), "{}", body);
+        assert!(body.contains(r#""text":"Checking.\n""#), "{}", body);
+        assert!(body.contains(r#""type":"tool_use""#) && body.contains(r#""name":"Bash""#), "{}", body);
+        assert!(body.contains(r#""partial_json":"{\"command\":\"ls\"}""#), "{}", body);
+        assert!(body.contains(r#""stop_reason":"tool_use""#), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_provider_with_unresolved_secret_fails_only_when_used() {
+        let upstream = spawn_stub_provider(|_| {
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": "ok"}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "stub", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"]},
+                {"name": "broken", "api_base_url": format!("http://{}/v1", upstream), "api_key": "$UNSET", "models": ["m"]}
+            ],
+            "Router": {"default": "stub,m", "background": "broken,m"}
+        }))
+        .unwrap();
+        config.providers[1].config_error = Some("Providers[1].api_key: environment variable UNSET is not set".into());
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-sonnet-4",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-haiku-20241022",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+        assert!(body.contains("Provider 'broken' is not usable") && body.contains("UNSET is not set"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_proxy_url_fails_only_its_provider() {
+        let upstream = spawn_stub_provider(|_| {
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": "ok"}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "stub", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"]},
+                {"name": "proxied", "api_base_url": format!("http://{}/v1", upstream), "api_key": "k", "models": ["m"],
+                 "proxy_url": "ftp://proxy.corp"}
+            ],
+            "Router": {"default": "stub,m", "background": "proxied,m"}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-sonnet-4",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-haiku-20241022",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+        assert!(body.contains("Provider 'proxied' is not usable: unsupported proxy scheme 'ftp'"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_to_next_route_on_retryable_failure() {
+        let failing = spawn_stub_provider(|_| {
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Body::from("down"))
+                .unwrap()
+        })
+        .await;
+        let healthy = spawn_stub_provider(|request| {
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": format!("served by {}", request["model"].as_str().unwrap())}, "finish_reason": "stop"}]
+                }).to_string()))
+                .unwrap()
+        })
+        .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "down", "api_base_url": format!("http://{}/v1", failing), "api_key": "k", "models": ["m1"]},
+                {"name": "unreachable", "api_base_url": "http://127.0.0.1:9/v1", "api_key": "k", "models": ["m2"]},
+                {"name": "up", "api_base_url": format!("http://{}/v1", healthy), "api_key": "k", "models": ["m3"]}
+            ],
+            "Router": {"default": ["down,m1", "unreachable,m2", "up,m3"]},
+            "RETRY": {"base_delay_ms": 1}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        let (status, headers, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[ROUTE_HEADER], "up,m3");
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["content"][0]["text"], "served by m3");
+    }
+
+    #[tokio::test]
+    async fn test_no_fallback_on_client_errors() {
+        let rejecting = spawn_stub_provider(|_| {
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(r#"{"error":{"message":"bad tool schema"}}"#))
+                .unwrap()
+        })
+        .await;
+        let healthy = spawn_stub_provider(|_| panic!("fallback must not be used for 400s")).await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "Providers": [
+                {"name": "strict", "api_base_url": format!("http://{}/v1", rejecting), "api_key": "k", "models": ["m1"]},
+                {"name": "up", "api_base_url": format!("http://{}/v1", healthy), "api_key": "k", "models": ["m2"]}
+            ],
+            "Router": {"default": ["strict,m1", "up,m2"]}
+        }))
+        .unwrap();
+        let addr = spawn_router(config).await;
+
+        let (status, _, body) = post_json(addr, "/v1/messages", serde_json::json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [{"role": "user", "content": "Hello"}]
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let json: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"]["message"], "HTTP 400: bad tool schema");
+    }
+}
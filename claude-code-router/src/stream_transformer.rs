@@ -0,0 +1,852 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::provider::{map_finish_reason, map_usage, matched_stop_sequence};
+use crate::thinking;
+use crate::error::{anthropic_error_body, ProviderError};
+use crate::tool_args::completion_suffix;
+use crate::tool_names::ToolNameMap;
+
+/// A single Server-Sent Event as parsed from an upstream stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Incremental SSE parser that tolerates events split across network chunks
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data_lines: Vec<String>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed raw bytes and return every event completed by them
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line_bytes: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line_bytes);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if let Some(event) = self.dispatch() {
+                    events.push(event);
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data_lines.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            } else if let Some(value) = line.strip_prefix("event:") {
+                self.event = Some(value.trim().to_string());
+            }
+            // Comments (":") and unknown fields such as "id:" are ignored
+        }
+
+        events
+    }
+
+    /// Flush a trailing event that was not terminated by a blank line
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buffer.is_empty() {
+            // A non-empty partial line can never complete an event on its own
+            self.feed(b"\n");
+        }
+        self.dispatch()
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        if self.data_lines.is_empty() {
+            self.event = None;
+            return None;
+        }
+        Some(SseEvent {
+            event: self.event.take(),
+            data: std::mem::take(&mut self.data_lines).join("\n"),
+        })
+    }
+}
+
+/// Incremental parser for newline-delimited JSON streams (e.g. Ollama), yielding
+/// each non-empty line as a nameless event so it can share the SSE pipeline
+#[derive(Debug, Default)]
+pub struct NdjsonParser {
+    buffer: Vec<u8>,
+}
+
+impl NdjsonParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed raw bytes and return every line completed by them
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line_bytes: Vec<u8> = self.buffer.drain(..=pos).collect();
+            if let Some(event) = Self::line_event(&line_bytes) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flush a trailing line that was not terminated by a newline
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let line_bytes = std::mem::take(&mut self.buffer);
+        Self::line_event(&line_bytes)
+    }
+
+    fn line_event(line_bytes: &[u8]) -> Option<SseEvent> {
+        let line = String::from_utf8_lossy(line_bytes);
+        let line = line.trim();
+        (!line.is_empty()).then(|| SseEvent { event: None, data: line.to_string() })
+    }
+}
+
+/// Upstream body framing understood by the provider stream tasks
+#[derive(Debug)]
+pub enum StreamParser {
+    Sse(SseParser),
+    Ndjson(NdjsonParser),
+}
+
+impl StreamParser {
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        match self {
+            StreamParser::Sse(parser) => parser.feed(chunk),
+            StreamParser::Ndjson(parser) => parser.feed(chunk),
+        }
+    }
+
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        match self {
+            StreamParser::Sse(parser) => parser.finish(),
+            StreamParser::Ndjson(parser) => parser.finish(),
+        }
+    }
+}
+
+/// Format a named event with a JSON payload as an SSE frame
+pub fn format_sse_event(event: &str, data: &Value) -> String {
+    format!("event: {}\ndata: {}\n\n", event, data)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockKind {
+    Thinking,
+    Text,
+    ToolUse,
+}
+
+/// A streamed tool call whose block is open; its arguments are checked when the block closes
+#[derive(Debug)]
+struct OpenToolCall {
+    id: String,
+    /// Original (restored) tool name
+    name: String,
+    arguments: String,
+}
+
+/// Converts OpenAI `chat.completion.chunk` objects into Anthropic streaming events
+/// (`message_start`, `content_block_*`, `message_delta`, `message_stop`)
+pub struct StreamTransformer {
+    message_id: String,
+    model: String,
+    started: bool,
+    finished: bool,
+    current_block: Option<(usize, BlockKind)>,
+    next_index: usize,
+    /// Upstream tool call index -> (block index, tool call id)
+    tool_blocks: HashMap<u64, (usize, String)>,
+    /// The tool call whose arguments are being streamed
+    open_tool_call: Option<OpenToolCall>,
+    /// `input_schema` of each request tool by name, to check repaired arguments
+    tool_schemas: HashMap<String, Value>,
+    /// Text of the open thinking block, signed when the block closes
+    thinking_text: String,
+    /// Stop sequences of the request, to recognise which one ended the message
+    stop_sequences: Vec<String>,
+    /// Restores tool names that were sanitized for the provider
+    tool_names: ToolNameMap,
+    stop_reason: Option<String>,
+    stop_sequence: Option<String>,
+    /// Anthropic usage from the last upstream usage object
+    usage: Option<Value>,
+    /// Reported as input tokens when the upstream sends no usage
+    estimated_input_tokens: u64,
+    /// Characters of text, reasoning and tool arguments, to estimate output tokens
+    streamed_chars: usize,
+}
+
+impl StreamTransformer {
+    pub fn new(model: &str) -> Self {
+        Self {
+            message_id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+            model: model.to_string(),
+            started: false,
+            finished: false,
+            current_block: None,
+            next_index: 0,
+            tool_blocks: HashMap::new(),
+            open_tool_call: None,
+            tool_schemas: HashMap::new(),
+            thinking_text: String::new(),
+            stop_sequences: Vec::new(),
+            tool_names: ToolNameMap::default(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: None,
+            estimated_input_tokens: 0,
+            streamed_chars: 0,
+        }
+    }
+
+    /// Report `stop_reason: stop_sequence` when the upstream names one of these as the stop
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.stop_sequences = stop_sequences;
+        self
+    }
+
+    /// Report the original names of tools that were sanitized for the provider
+    pub fn with_tool_names(mut self, tool_names: ToolNameMap) -> Self {
+        self.tool_names = tool_names;
+        self
+    }
+
+    /// Check repaired tool arguments against these `input_schema`s, keyed by original tool name
+    pub fn with_tool_schemas(mut self, tool_schemas: HashMap<String, Value>) -> Self {
+        self.tool_schemas = tool_schemas;
+        self
+    }
+
+    /// Input token estimate reported when the upstream stream carries no usage
+    pub fn with_estimated_input_tokens(mut self, input_tokens: u64) -> Self {
+        self.estimated_input_tokens = input_tokens;
+        self
+    }
+
+    /// Process one upstream chunk and return the Anthropic events it produces
+    pub fn process_chunk(&mut self, chunk: &Value) -> Vec<(String, Value)> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        self.ensure_started(&mut events);
+
+        if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+            self.usage = Some(map_usage(usage));
+        }
+
+        let choice = match chunk.get("choices").and_then(|c| c.as_array()).and_then(|c| c.first()) {
+            Some(choice) => choice,
+            None => return events,
+        };
+
+        if let Some(delta) = choice.get("delta") {
+            if let Some(reasoning) = thinking::reasoning_text(delta) {
+                let index = self.open_block(
+                    BlockKind::Thinking,
+                    json!({"type": "thinking", "thinking": "", "signature": ""}),
+                    &mut events,
+                );
+                self.thinking_text.push_str(reasoning);
+                self.streamed_chars += reasoning.len();
+                events.push(("content_block_delta".to_string(), json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "thinking_delta", "thinking": reasoning}
+                })));
+            }
+
+            if let Some(text) = delta.get("content").and_then(|v| v.as_str()) {
+                if !text.is_empty() {
+                    self.streamed_chars += text.len();
+                    let index = self.open_block(BlockKind::Text, json!({"type": "text", "text": ""}), &mut events);
+                    events.push(("content_block_delta".to_string(), json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": {"type": "text_delta", "text": text}
+                    })));
+                }
+            }
+
+            if let Some(tool_calls) = delta.get("tool_calls").and_then(|v| v.as_array()) {
+                for tool_call in tool_calls {
+                    self.process_tool_call_delta(tool_call, &mut events);
+                }
+            }
+        }
+
+        if let Some(finish_reason) = choice.get("finish_reason").and_then(|v| v.as_str()) {
+            match matched_stop_sequence(choice, &self.stop_sequences) {
+                Some(stop_sequence) if finish_reason == "stop" => {
+                    self.stop_reason = Some("stop_sequence".to_string());
+                    self.stop_sequence = Some(stop_sequence);
+                }
+                _ => self.stop_reason = Some(map_finish_reason(finish_reason)),
+            }
+        }
+
+        // A tool call that ended the stream with an error is the last thing sent
+        if let Some(error_at) = events.iter().position(|(name, _)| name == "error") {
+            events.truncate(error_at + 1);
+        }
+        events
+    }
+
+    /// Close any open block and emit the closing `message_delta`/`message_stop` pair
+    pub fn finish(&mut self) -> Vec<(String, Value)> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        self.ensure_started(&mut events);
+        self.close_block(&mut events);
+        if self.finished {
+            // Closing the last tool call ended the stream with an error
+            return events;
+        }
+
+        // Some providers finish tool call turns with "stop"
+        let stop_reason = match self.stop_reason.clone() {
+            Some(reason) if reason != "end_turn" => reason,
+            _ if !self.tool_blocks.is_empty() => "tool_use".to_string(),
+            _ => "end_turn".to_string(),
+        };
+        let usage = self.usage.clone().unwrap_or_else(|| json!({
+            "input_tokens": self.estimated_input_tokens,
+            "output_tokens": self.streamed_chars.div_ceil(4),
+            "cache_creation_input_tokens": 0,
+            "cache_read_input_tokens": 0
+        }));
+        events.push(("message_delta".to_string(), json!({
+            "type": "message_delta",
+            "delta": {
+                "stop_reason": stop_reason,
+                "stop_sequence": self.stop_sequence
+            },
+            "usage": usage
+        })));
+        events.push(("message_stop".to_string(), json!({"type": "message_stop"})));
+        self.finished = true;
+        events
+    }
+
+    fn process_tool_call_delta(&mut self, tool_call: &Value, events: &mut Vec<(String, Value)>) {
+        let tool_index = tool_call.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+        let function = tool_call.get("function");
+
+        // Continuation chunks may repeat the id or send it as null or ""
+        let id = tool_call.get("id").and_then(|v| v.as_str()).filter(|id| !id.is_empty());
+        let block_index = match self.tool_blocks.get(&tool_index) {
+            Some((index, open_id)) if id.is_none_or(|id| id == open_id) => {
+                if self.current_block != Some((*index, BlockKind::ToolUse)) {
+                    log::warn!("Dropping arguments for tool call {} after its block was closed", open_id);
+                    return;
+                }
+                *index
+            }
+            _ => {
+                let id = id
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| format!("toolu_{}", uuid::Uuid::new_v4().simple()));
+                let name = function
+                    .and_then(|f| f.get("name"))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("");
+                let name = self.tool_names.restore(name).to_string();
+
+                self.close_block(events);
+                let index = self.next_index;
+                self.next_index += 1;
+                self.current_block = Some((index, BlockKind::ToolUse));
+                self.tool_blocks.insert(tool_index, (index, id.clone()));
+                events.push(("content_block_start".to_string(), json!({
+                    "type": "content_block_start",
+                    "index": index,
+                    "content_block": {"type": "tool_use", "id": id, "name": name, "input": {}}
+                })));
+                self.open_tool_call = Some(OpenToolCall { id, name, arguments: String::new() });
+                index
+            }
+        };
+
+        if let Some(arguments) = function.and_then(|f| f.get("arguments")).and_then(|v| v.as_str()) {
+            if !arguments.is_empty() {
+                self.streamed_chars += arguments.len();
+                if let Some(tool_call) = self.open_tool_call.as_mut() {
+                    tool_call.arguments.push_str(arguments);
+                }
+                events.push(("content_block_delta".to_string(), json!({
+                    "type": "content_block_delta",
+                    "index": block_index,
+                    "delta": {"type": "input_json_delta", "partial_json": arguments}
+                })));
+            }
+        }
+    }
+
+    /// Check the streamed arguments of the closing tool call. Output cut off mid-value is
+    /// completed with one more `input_json_delta`; arguments that cannot be fixed by appending
+    /// end the stream with an `error` event, like the 502 of a non-streaming response.
+    /// Returns whether the block can be closed normally.
+    fn complete_tool_call(&mut self, index: usize, events: &mut Vec<(String, Value)>) -> bool {
+        let Some(tool_call) = self.open_tool_call.take() else {
+            return true;
+        };
+        match completion_suffix(&tool_call.arguments, self.tool_schemas.get(&tool_call.name)) {
+            Ok(suffix) => {
+                if !suffix.is_empty() {
+                    events.push(("content_block_delta".to_string(), json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": {"type": "input_json_delta", "partial_json": suffix}
+                    })));
+                }
+                true
+            }
+            Err(e) => {
+                let error = ProviderError::InvalidResponse(format!("tool call `{}` ({}): {}", tool_call.name, tool_call.id, e));
+                log::warn!("Ending stream: {}", error);
+                events.push(("error".to_string(), anthropic_error_body(error.error_type(), &error.to_string())));
+                self.finished = true;
+                false
+            }
+        }
+    }
+
+    fn ensure_started(&mut self, events: &mut Vec<(String, Value)>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        events.push(("message_start".to_string(), json!({
+            "type": "message_start",
+            "message": {
+                "id": self.message_id,
+                "type": "message",
+                "role": "assistant",
+                "model": self.model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": {"input_tokens": 0, "output_tokens": 0}
+            }
+        })));
+        events.push(("ping".to_string(), json!({"type": "ping"})));
+    }
+
+    /// Return the index of the open block of `kind`, starting a new one if needed
+    fn open_block(&mut self, kind: BlockKind, content_block: Value, events: &mut Vec<(String, Value)>) -> usize {
+        if let Some((index, current_kind)) = self.current_block {
+            if current_kind == kind {
+                return index;
+            }
+        }
+        self.close_block(events);
+        let index = self.next_index;
+        self.next_index += 1;
+        self.current_block = Some((index, kind));
+        events.push(("content_block_start".to_string(), json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": content_block
+        })));
+        index
+    }
+
+    fn close_block(&mut self, events: &mut Vec<(String, Value)>) {
+        if let Some((index, kind)) = self.current_block.take() {
+            if kind == BlockKind::ToolUse && !self.complete_tool_call(index, events) {
+                return;
+            }
+            if kind == BlockKind::Thinking {
+                let thinking = std::mem::take(&mut self.thinking_text);
+                events.push(("content_block_delta".to_string(), json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": {"type": "signature_delta", "signature": thinking::signature(&thinking)}
+                })));
+            }
+            events.push(("content_block_stop".to_string(), json!({
+                "type": "content_block_stop",
+                "index": index
+            })));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_names(events: &[(String, Value)]) -> Vec<&str> {
+        events.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_sse_parser_split_chunks() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"data: {\"a\"").is_empty());
+        let events = parser.feed(b":1}\n\ndata: [DONE]\n\n");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].data, "{\"a\":1}");
+        assert_eq!(events[1].data, "[DONE]");
+    }
+
+    #[test]
+    fn test_sse_parser_event_names_and_crlf() {
+        let mut parser = SseParser::new();
+        let events = parser.feed(b"event: ping\r\ndata: {}\r\n\r\n: comment\n\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event.as_deref(), Some("ping"));
+        assert_eq!(events[0].data, "{}");
+    }
+
+    #[test]
+    fn test_sse_parser_finish_flushes_trailing_event() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"data: tail").is_empty());
+        assert_eq!(parser.finish().unwrap().data, "tail");
+    }
+
+    #[test]
+    fn test_ndjson_parser_split_lines() {
+        let mut parser = NdjsonParser::new();
+        assert!(parser.feed(b"{\"a\":").is_empty());
+        let events = parser.feed(b"1}\n\n{\"b\":2}\r\n{\"c\"");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].data, r#"{"a":1}"#);
+        assert_eq!(events[1].data, r#"{"b":2}"#);
+        assert_eq!(parser.feed(b":3}"), vec![]);
+        assert_eq!(parser.finish().unwrap().data, r#"{"c":3}"#);
+        assert_eq!(parser.finish(), None);
+    }
+
+    #[test]
+    fn test_text_stream() {
+        let mut transformer = StreamTransformer::new("claude-3-5-sonnet");
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"index": 0, "delta": {"role": "assistant", "content": "Hel"}}]
+        }));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [{"index": 0, "delta": {"content": "lo"}, "finish_reason": "stop"}]
+        })));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 3}
+        })));
+        events.extend(transformer.finish());
+
+        assert_eq!(event_names(&events), vec![
+            "message_start",
+            "ping",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert_eq!(events[0].1["message"]["model"], "claude-3-5-sonnet");
+        assert_eq!(events[3].1["delta"]["text"], "Hel");
+        assert_eq!(events[6].1["delta"]["stop_reason"], "end_turn");
+        assert_eq!(events[6].1["usage"]["output_tokens"], 3);
+        assert_eq!(events[6].1["usage"]["input_tokens"], 12);
+    }
+
+    #[test]
+    fn test_tool_call_stream() {
+        let mut transformer = StreamTransformer::new("test");
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"content": "Let me check"}}]
+        }));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [{
+                "index": 0,
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "search", "arguments": ""}
+            }]}}]
+        })));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": "{\"q\":"}}]}}]
+        })));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": "\"rust\"}"}}]}, "finish_reason": "tool_calls"}]
+        })));
+        events.extend(transformer.finish());
+
+        assert_eq!(event_names(&events), vec![
+            "message_start",
+            "ping",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_stop",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert_eq!(events[5].1["index"], 1);
+        assert_eq!(events[5].1["content_block"]["type"], "tool_use");
+        assert_eq!(events[5].1["content_block"]["id"], "call_1");
+        assert_eq!(events[5].1["content_block"]["name"], "search");
+        assert_eq!(events[6].1["delta"]["type"], "input_json_delta");
+        assert_eq!(events[6].1["delta"]["partial_json"], "{\"q\":");
+        assert_eq!(events[9].1["delta"]["stop_reason"], "tool_use");
+    }
+
+    #[test]
+    fn test_reasoning_becomes_signed_thinking_block() {
+        let mut transformer = StreamTransformer::new("test");
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"reasoning_content": "Let me "}}]
+        }));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [{"delta": {"reasoning": "think"}}]
+        })));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [{"delta": {"content": "Done"}, "finish_reason": "stop"}]
+        })));
+        events.extend(transformer.finish());
+
+        assert_eq!(event_names(&events), vec![
+            "message_start",
+            "ping",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]);
+        assert_eq!(events[2].1["content_block"]["type"], "thinking");
+        assert_eq!(events[3].1["delta"], json!({"type": "thinking_delta", "thinking": "Let me "}));
+        assert_eq!(events[4].1["delta"]["thinking"], "think");
+        assert_eq!(events[5].1["delta"]["type"], "signature_delta");
+        assert_eq!(events[5].1["delta"]["signature"], thinking::signature("Let me think"));
+        assert_eq!(events[7].1["index"], 1);
+        assert_eq!(events[7].1["content_block"]["type"], "text");
+    }
+
+    #[test]
+    fn test_stop_sequence_is_reported() {
+        let mut transformer = StreamTransformer::new("test").with_stop_sequences(vec!["END".to_string()]);
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"content": "Done"}, "finish_reason": "stop", "stop_reason": "END"}]
+        }));
+        events.extend(transformer.finish());
+        let delta = &events.iter().find(|(name, _)| name == "message_delta").unwrap().1["delta"];
+        assert_eq!(delta["stop_reason"], "stop_sequence");
+        assert_eq!(delta["stop_sequence"], "END");
+
+        // A stop_reason that is not one of the request's sequences is a normal end of turn
+        let mut transformer = StreamTransformer::new("test");
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"content": "Done"}, "finish_reason": "stop", "stop_reason": 151645}]
+        }));
+        events.extend(transformer.finish());
+        let delta = &events.iter().find(|(name, _)| name == "message_delta").unwrap().1["delta"];
+        assert_eq!(delta["stop_reason"], "end_turn");
+        assert_eq!(delta["stop_sequence"], Value::Null);
+    }
+
+    #[test]
+    fn test_usage_is_mapped_or_estimated() {
+        let mut transformer = StreamTransformer::new("test");
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "ls", "arguments": "{}"}}]}, "finish_reason": "stop"}]
+        }));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [],
+            "usage": {"prompt_tokens": 50, "completion_tokens": 5, "prompt_cache_hit_tokens": 40}
+        })));
+        events.extend(transformer.finish());
+        let message_delta = &events.iter().find(|(name, _)| name == "message_delta").unwrap().1;
+        // Tool call turns finished with "stop" are still tool_use
+        assert_eq!(message_delta["delta"]["stop_reason"], "tool_use");
+        assert_eq!(message_delta["usage"], json!({
+            "input_tokens": 10,
+            "output_tokens": 5,
+            "cache_creation_input_tokens": 0,
+            "cache_read_input_tokens": 40
+        }));
+
+        let mut transformer = StreamTransformer::new("test").with_estimated_input_tokens(120);
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"content": "twelve chars"}, "finish_reason": "stop"}]
+        }));
+        events.extend(transformer.finish());
+        let message_delta = &events.iter().find(|(name, _)| name == "message_delta").unwrap().1;
+        assert_eq!(message_delta["usage"]["input_tokens"], 120);
+        assert_eq!(message_delta["usage"]["output_tokens"], 3);
+    }
+
+    #[test]
+    fn test_sanitized_tool_names_are_restored() {
+        let original = "mcp__my.server__lookup";
+        let sanitized = crate::tool_names::sanitize_tool_name(original);
+        let mut transformer = StreamTransformer::new("test")
+            .with_tool_names(ToolNameMap::from_names([original]));
+        let events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": sanitized, "arguments": "{}"}}]}}]
+        }));
+        let start = &events.iter().find(|(name, _)| name == "content_block_start").unwrap().1;
+        assert_eq!(start["content_block"]["name"], original);
+    }
+
+    #[test]
+    fn test_multiple_tool_calls_get_separate_blocks() {
+        let mut transformer = StreamTransformer::new("test");
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [
+                {"index": 0, "id": "call_a", "function": {"name": "a", "arguments": "{}"}},
+                {"index": 1, "id": "call_b", "function": {"name": "b", "arguments": "{}"}}
+            ]}}]
+        }));
+        events.extend(transformer.finish());
+
+        let starts: Vec<&Value> = events
+            .iter()
+            .filter(|(name, _)| name == "content_block_start")
+            .map(|(_, data)| data)
+            .collect();
+        assert_eq!(starts.len(), 2);
+        assert_eq!(starts[0]["index"], 0);
+        assert_eq!(starts[1]["index"], 1);
+        assert_eq!(starts[1]["content_block"]["name"], "b");
+    }
+
+    #[test]
+    fn test_tool_call_continuations_with_null_or_repeated_id() {
+        let mut transformer = StreamTransformer::new("test");
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "Bash", "arguments": ""}}]}}]
+        }));
+        for (id, arguments) in [(json!(null), "{\"command\":"), (json!(""), " \"ls\""), (json!("call_1"), "}")] {
+            events.extend(transformer.process_chunk(&json!({
+                "choices": [{"delta": {"tool_calls": [{"index": 0, "id": id, "function": {"name": null, "arguments": arguments}}]}}]
+            })));
+        }
+        events.extend(transformer.finish());
+
+        let starts: Vec<&Value> = events
+            .iter()
+            .filter(|(name, _)| name == "content_block_start")
+            .map(|(_, data)| data)
+            .collect();
+        assert_eq!(starts.len(), 1);
+        assert_eq!(starts[0]["content_block"]["name"], "Bash");
+        let arguments: String = events
+            .iter()
+            .filter_map(|(_, data)| data["delta"]["partial_json"].as_str())
+            .collect();
+        assert_eq!(arguments, "{\"command\": \"ls\"}");
+    }
+
+    #[test]
+    fn test_deltas_for_closed_tool_blocks_are_dropped() {
+        let mut transformer = StreamTransformer::new("test");
+        let mut events = transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_a", "function": {"name": "a", "arguments": "{}"}}]}}]
+        }));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [{"delta": {"content": "between"}}]
+        })));
+        events.extend(transformer.process_chunk(&json!({
+            "choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": "{\"late\": 1}"}}]}}]
+        })));
+        events.extend(transformer.finish());
+
+        let tool_deltas = events
+            .iter()
+            .filter(|(_, data)| data["delta"]["type"] == "input_json_delta")
+            .count();
+        assert_eq!(tool_deltas, 1);
+    }
+
+    #[test]
+    fn test_malformed_streamed_arguments_are_completed_or_reported() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"command": {"type": "string"}, "timeout": {"type": "number"}},
+            "required": ["command"]
+        });
+        let run = |fragments: &[&str]| {
+            let mut transformer = StreamTransformer::new("test")
+                .with_tool_schemas(HashMap::from([("Bash".to_string(), schema.clone())]));
+            let mut events = transformer.process_chunk(&json!({
+                "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "Bash", "arguments": ""}}]}}]
+            }));
+            for fragment in fragments {
+                events.extend(transformer.process_chunk(&json!({
+                    "choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": fragment}}]}}]
+                })));
+            }
+            events.extend(transformer.process_chunk(&json!({
+                "choices": [{"delta": {"tool_calls": [{"index": 1, "id": "call_2", "function": {"name": "Bash", "arguments": "{}"}}]}}]
+            })));
+            events.extend(transformer.finish());
+            events
+        };
+        let partial_json = |events: &[(String, Value)]| -> Vec<String> {
+            events
+                .iter()
+                .filter(|(_, data)| data["delta"]["type"] == "input_json_delta")
+                .map(|(_, data)| data["delta"]["partial_json"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        // Fragments are forwarded as they arrive; output cut off mid-string is closed with one more delta
+        let events = run(&["{\"command\": \"ls -la", "\", \"timeout\": 5"]);
+        let deltas = partial_json(&events);
+        assert_eq!(deltas, vec!["{\"command\": \"ls -la", "\", \"timeout\": 5", "}", "{}"]);
+        let input: Value = serde_json::from_str(&deltas[..3].concat()).unwrap();
+        assert_eq!(input, json!({"command": "ls -la", "timeout": 5}));
+        assert_eq!(events.last().unwrap().0, "message_stop");
+
+        // Anything else ends the stream with an error, like the 502 of a non-streaming response
+        for (fragments, expected) in [
+            (&["{'command': ", "'ls -la'}"][..], "tool call `Bash` (call_1): arguments are not valid JSON"),
+            (&["{\"timeout\": 5"][..], "tool call `Bash` (call_1): repaired arguments do not match the input schema"),
+        ] {
+            let events = run(fragments);
+            assert_eq!(partial_json(&events), fragments);
+            let (name, error) = events.last().unwrap();
+            assert_eq!(name, "error");
+            assert_eq!(error["error"]["type"], "api_error");
+            let message = error["error"]["message"].as_str().unwrap();
+            assert!(message.contains(expected), "{}", message);
+            assert!(events.iter().all(|(name, _)| name != "message_delta"));
+        }
+    }
+
+    #[test]
+    fn test_empty_stream_still_produces_valid_message() {
+        let mut transformer = StreamTransformer::new("test");
+        let events = transformer.finish();
+        assert_eq!(event_names(&events), vec!["message_start", "ping", "message_delta", "message_stop"]);
+        assert!(transformer.finish().is_empty());
+    }
+
+    #[test]
+    fn test_format_sse_event() {
+        let frame = format_sse_event("message_stop", &json!({"type": "message_stop"}));
+        assert_eq!(frame, "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n");
+    }
+}
@@ -0,0 +1,455 @@
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+use crate::config::{Provider, TextToolCalls};
+use crate::server::ClaudeRequest;
+use crate::tool_args;
+use crate::tool_names::sanitize_tool_name;
+
+/// Hermes / Qwen style: `<tool_call>{"name": ..., "arguments": ...}</tool_call>`
+const TAG_OPEN: &str = "<tool_call>";
+const TAG_CLOSE: &str = "</tool_call>";
+/// Markdown-fenced JSON call objects
+const FENCE: &str = "```";
+/// DeepSeek style: `<｜tool▁call▁begin｜>function<｜tool▁sep｜>name\n```json\n{...}\n```<｜tool▁call▁end｜>`
+const DEEPSEEK_OPEN: &str = "<｜tool▁call▁begin｜>";
+const DEEPSEEK_CLOSE: &str = "<｜tool▁call▁end｜>";
+const DEEPSEEK_SEP: &str = "<｜tool▁sep｜>";
+const DEEPSEEK_WRAPPERS: [&str; 2] = ["<｜tool▁calls▁begin｜>", "<｜tool▁calls▁end｜>"];
+
+/// Where a text tool call may start; streamed text is held back from here on
+const MARKERS: [&str; 4] = [TAG_OPEN, FENCE, DEEPSEEK_OPEN, DEEPSEEK_WRAPPERS[0]];
+
+/// Parser for the request when the provider opted in with `text_tool_calls: "extract"` and
+/// the request has tools
+pub fn parser_for(provider: &Provider, claude_req: &ClaudeRequest) -> Option<TextToolCallParser> {
+    if provider.text_tool_calls.unwrap_or_default() != TextToolCalls::Extract {
+        return None;
+    }
+    Some(TextToolCallParser::for_request(claude_req)).filter(|parser| !parser.tool_names.is_empty())
+}
+
+/// Finds tool calls that models without native function calling write into their text.
+/// Only calls of tools offered in the request are accepted, so example JSON in an answer
+/// stays text.
+#[derive(Debug, Clone, Default)]
+pub struct TextToolCallParser {
+    /// Request tool names, both as sent by Claude Code and as sanitized for the provider
+    tool_names: HashSet<String>,
+}
+
+impl TextToolCallParser {
+    pub fn for_request(claude_req: &ClaudeRequest) -> Self {
+        let tool_names = claude_req
+            .tools
+            .iter()
+            .flatten()
+            .filter_map(|tool| tool.get("name").and_then(|n| n.as_str()))
+            .flat_map(|name| [name.to_string(), sanitize_tool_name(name)])
+            .collect();
+        Self { tool_names }
+    }
+
+    /// Split `text` into the text around the tool calls and OpenAI `tool_calls` entries with
+    /// generated ids. None when the text holds no call of a known tool.
+    pub fn extract(&self, text: &str) -> Option<(String, Vec<Value>)> {
+        let mut calls = Vec::new();
+        let mut rest = String::new();
+        let mut remaining = text;
+
+        while let Some((start, marker)) = find_marker(remaining, &[TAG_OPEN, FENCE, DEEPSEEK_OPEN]) {
+            rest.push_str(&remaining[..start]);
+            let after = &remaining[start + marker.len()..];
+            let (body, close_len) = match marker {
+                TAG_OPEN => split_at_close(after, TAG_CLOSE),
+                DEEPSEEK_OPEN => split_at_close(after, DEEPSEEK_CLOSE),
+                _ => split_at_close(after, FENCE),
+            };
+            let segment_len = marker.len() + body.len() + close_len;
+
+            let found = match marker {
+                DEEPSEEK_OPEN => self.deepseek_call(body).map(|call| vec![call]),
+                FENCE => {
+                    // Skip the language tag line (```json)
+                    let json = match body.split_once('\n') {
+                        Some((lang, json)) if !lang.contains(['{', '[']) => json,
+                        _ => body,
+                    };
+                    self.json_calls(json)
+                }
+                _ => self.json_calls(body),
+            };
+            match found {
+                Some(found) => calls.extend(found),
+                None => rest.push_str(&remaining[start..start + segment_len]),
+            }
+            remaining = &remaining[start + segment_len..];
+        }
+        rest.push_str(remaining);
+
+        if calls.is_empty() {
+            return None;
+        }
+        for wrapper in DEEPSEEK_WRAPPERS {
+            rest = rest.replace(wrapper, "");
+        }
+        Some((rest.trim().to_string(), calls))
+    }
+
+    /// Move tool calls found in the text content of each choice of a `chat.completion` into
+    /// `tool_calls`, with `finish_reason: "tool_calls"`
+    pub fn extract_from_response(&self, response: &mut Value) {
+        let Some(choices) = response.get_mut("choices").and_then(|c| c.as_array_mut()) else {
+            return;
+        };
+        for choice in choices {
+            let Some(message) = choice.get_mut("message") else {
+                continue;
+            };
+            if message.get("tool_calls").and_then(|t| t.as_array()).is_some_and(|t| !t.is_empty()) {
+                continue;
+            }
+            let Some((text, calls)) = message.get("content").and_then(|c| c.as_str()).and_then(|c| self.extract(c)) else {
+                continue;
+            };
+            log::debug!("Extracted {} tool call(s) from text content", calls.len());
+            message["content"] = if text.is_empty() { Value::Null } else { json!(text) };
+            message["tool_calls"] = json!(calls);
+            choice["finish_reason"] = json!("tool_calls");
+        }
+    }
+
+    /// Calls from a JSON call object or an array of them
+    fn json_calls(&self, body: &str) -> Option<Vec<Value>> {
+        let value = tool_args::repair_json(body)?;
+        let objects = match value {
+            Value::Array(items) => items,
+            object => vec![object],
+        };
+        let calls: Vec<Value> = objects.iter().filter_map(|object| self.call_from_object(object)).collect();
+        Some(calls).filter(|calls| !calls.is_empty() && calls.len() == objects.len())
+    }
+
+    /// `{"name", "arguments" | "parameters" | "input"}`, optionally nested in `"function"`
+    fn call_from_object(&self, object: &Value) -> Option<Value> {
+        let object = object.get("function").filter(|f| f.is_object()).unwrap_or(object);
+        let name = object.get("name").and_then(|n| n.as_str())?;
+        let arguments = ["arguments", "parameters", "input"]
+            .iter()
+            .find_map(|key| object.get(*key))
+            .cloned()
+            .unwrap_or_else(|| json!({}));
+        self.tool_call(name, arguments)
+    }
+
+    /// `function<｜tool▁sep｜>name` followed by (usually fenced) JSON arguments
+    fn deepseek_call(&self, body: &str) -> Option<Value> {
+        let (_, after_sep) = body.split_once(DEEPSEEK_SEP)?;
+        let (name, arguments) = after_sep.split_once('\n').unwrap_or((after_sep, ""));
+        let arguments = match arguments.trim() {
+            "" => json!({}),
+            text => tool_args::repair_json(text).unwrap_or_else(|| json!(text)),
+        };
+        self.tool_call(name.trim(), arguments)
+    }
+
+    fn tool_call(&self, name: &str, arguments: Value) -> Option<Value> {
+        if !self.tool_names.contains(name) {
+            return None;
+        }
+        // Malformed arguments stay a string and are repaired like native ones
+        let arguments = match arguments {
+            Value::String(text) => text,
+            value => value.to_string(),
+        };
+        Some(json!({
+            "id": format!("toolu_{}", uuid::Uuid::new_v4().simple()),
+            "type": "function",
+            "function": {"name": name, "arguments": arguments}
+        }))
+    }
+}
+
+/// Applies a TextToolCallParser to OpenAI `chat.completion.chunk`s. Text is streamed as usual
+/// until a possible tool call starts and held back while it is open; a closed segment that is
+/// not a tool call (e.g. a code block) is passed on. From the first tool call on, text is held
+/// and parsed when the choice finishes, then sent as `tool_calls`.
+pub struct TextToolCallStream {
+    parser: TextToolCallParser,
+    /// Text not yet passed on
+    held: String,
+    /// Whether a tool call was seen, so everything up to the finish is held
+    holding: bool,
+}
+
+impl TextToolCallStream {
+    pub fn new(parser: TextToolCallParser) -> Self {
+        Self { parser, held: String::new(), holding: false }
+    }
+
+    /// Rewrite the text content (and finish reason) of one chunk's first choice
+    pub fn process_chunk(&mut self, mut chunk: Value) -> Value {
+        let Some(choice) = chunk.get_mut("choices").and_then(|c| c.as_array_mut()).and_then(|c| c.first_mut()) else {
+            return chunk;
+        };
+        if let Some(text) = choice.pointer("/delta/content").and_then(|c| c.as_str()) {
+            self.held.push_str(text);
+            choice["delta"]["content"] = json!(self.release());
+        }
+        if choice.get("finish_reason").is_some_and(|r| r.is_string()) && self.flush_into(choice) {
+            choice["finish_reason"] = json!("tool_calls");
+        }
+        chunk
+    }
+
+    /// Chunk with text still held when the stream ends without a finish reason
+    pub fn finish(&mut self) -> Option<Value> {
+        if self.held.is_empty() {
+            return None;
+        }
+        let mut choice = json!({"index": 0, "delta": {}});
+        self.flush_into(&mut choice);
+        Some(json!({"object": "chat.completion.chunk", "choices": [choice]}))
+    }
+
+    /// Text that can be passed on now: everything before the first tool call or still open
+    /// marker, minus a trailing part that may be the start of one
+    fn release(&mut self) -> String {
+        if self.holding {
+            return String::new();
+        }
+        let mut released = 0;
+        loop {
+            let Some((offset, marker)) = find_marker(&self.held[released..], &MARKERS) else {
+                let keep = (1..=self.held.len() - released)
+                    .filter(|&len| self.held.is_char_boundary(self.held.len() - len))
+                    .filter(|&len| MARKERS.iter().any(|m| m.starts_with(&self.held[self.held.len() - len..])))
+                    .max()
+                    .unwrap_or(0);
+                released = self.held.len() - keep;
+                break;
+            };
+            let start = released + offset;
+            let close = match marker {
+                TAG_OPEN => TAG_CLOSE,
+                FENCE => FENCE,
+                DEEPSEEK_OPEN => DEEPSEEK_CLOSE,
+                // The DeepSeek tool call section only ever holds tool calls
+                _ => {
+                    self.holding = true;
+                    released = start;
+                    break;
+                }
+            };
+            let body_start = start + marker.len();
+            let Some(end) = self.held[body_start..].find(close) else {
+                // Still open: may become a tool call
+                released = start;
+                break;
+            };
+            let segment_end = body_start + end + close.len();
+            if self.parser.extract(&self.held[start..segment_end]).is_some() {
+                self.holding = true;
+                released = start;
+                break;
+            }
+            released = segment_end;
+        }
+        self.held.drain(..released).collect()
+    }
+
+    /// Put the held text into `choice.delta` as content and/or tool_calls; returns whether
+    /// there were tool calls
+    fn flush_into(&mut self, choice: &mut Value) -> bool {
+        let held = std::mem::take(&mut self.held);
+        self.holding = false;
+        let released = choice.pointer("/delta/content").and_then(|c| c.as_str()).unwrap_or_default().to_string();
+        let (text, calls) = match self.parser.extract(&held) {
+            Some((text, calls)) => (text, calls),
+            None => (held, Vec::new()),
+        };
+        let text = released + &text;
+        if !text.is_empty() {
+            choice["delta"]["content"] = json!(text);
+        }
+        if calls.is_empty() {
+            return false;
+        }
+        log::debug!("Extracted {} tool call(s) from streamed text", calls.len());
+        let calls: Vec<Value> = calls
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut call)| {
+                call["index"] = json!(index);
+                call
+            })
+            .collect();
+        choice["delta"]["tool_calls"] = json!(calls);
+        true
+    }
+}
+
+/// Earliest occurrence of any of `markers` in `text`
+fn find_marker<'a>(text: &str, markers: &[&'a str]) -> Option<(usize, &'a str)> {
+    markers
+        .iter()
+        .filter_map(|marker| text.find(marker).map(|start| (start, *marker)))
+        .min_by_key(|(start, _)| *start)
+}
+
+/// Text before `close` and the length of `close`; everything (length 0) when it is missing
+fn split_at_close<'a>(text: &'a str, close: &str) -> (&'a str, usize) {
+    match text.find(close) {
+        Some(end) => (&text[..end], close.len()),
+        None => (text, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parser() -> TextToolCallParser {
+        let claude_req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "m",
+            "messages": [],
+            "tools": [{"name": "Bash", "input_schema": {}}, {"name": "Read", "input_schema": {}}]
+        }))
+        .unwrap();
+        TextToolCallParser::for_request(&claude_req)
+    }
+
+    fn arguments(call: &Value) -> Value {
+        serde_json::from_str(call["function"]["arguments"].as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_extract_formats() {
+        let parser = parser();
+
+        let (text, calls) = parser
+            .extract("Let me look.\n<tool_call>\n{\"name\": \"Bash\", \"arguments\": {\"command\": \"ls\"}}\n</tool_call>\n<tool_call>{\"name\": \"Read\", \"arguments\": {\"file_path\": \"a.rs\"}}</tool_call>")
+            .unwrap();
+        assert_eq!(text, "Let me look.");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0]["function"]["name"], "Bash");
+        assert_eq!(arguments(&calls[0]), json!({"command": "ls"}));
+        assert!(calls[0]["id"].as_str().unwrap().starts_with("toolu_"));
+        assert_ne!(calls[0]["id"], calls[1]["id"]);
+
+        let (text, calls) = parser.extract("```json\n{\"name\": \"Read\", \"parameters\": {\"file_path\": \"b.rs\"}}\n```").unwrap();
+        assert_eq!(text, "");
+        assert_eq!(arguments(&calls[0]), json!({"file_path": "b.rs"}));
+
+        let deepseek = "<｜tool▁calls▁begin｜><｜tool▁call▁begin｜>function<｜tool▁sep｜>Bash\n```json\n{\"command\": \"pwd\"}\n```<｜tool▁call▁end｜><｜tool▁calls▁end｜>";
+        let (text, calls) = parser.extract(deepseek).unwrap();
+        assert_eq!(text, "");
+        assert_eq!(calls[0]["function"]["name"], "Bash");
+        assert_eq!(arguments(&calls[0]), json!({"command": "pwd"}));
+
+        // Unclosed tag from a truncated answer
+        let (_, calls) = parser.extract("<tool_call>{\"name\": \"Bash\", \"arguments\": {\"command\": \"ls\"}}").unwrap();
+        assert_eq!(arguments(&calls[0]), json!({"command": "ls"}));
+    }
+
+    #[test]
+    fn test_unknown_tools_and_code_stay_text() {
+        let parser = parser();
+        assert!(parser.extract("Plain answer").is_none());
+        assert!(parser.extract("```json\n{\"name\": \"example\", \"arguments\": {}}\n```").is_none());
+        assert!(parser.extract("```rust\nfn main() {}\n```").is_none());
+
+        let (text, calls) = parser
+            .extract("Example:\n```json\n{\"a\": 1}\n```\n<tool_call>{\"name\": \"Bash\", \"arguments\": {}}</tool_call>")
+            .unwrap();
+        assert_eq!(text, "Example:\n```json\n{\"a\": 1}\n```");
+        assert_eq!(calls.len(), 1);
+    }
+
+    #[test]
+    fn test_extract_from_response() {
+        let mut response = json!({
+            "choices": [{"message": {"role": "assistant", "content": "<tool_call>{\"name\": \"Bash\", \"arguments\": {\"command\": \"ls\"}}</tool_call>"}, "finish_reason": "stop"}]
+        });
+        parser().extract_from_response(&mut response);
+        let choice = &response["choices"][0];
+        assert_eq!(choice["message"]["content"], Value::Null);
+        assert_eq!(choice["message"]["tool_calls"][0]["function"]["name"], "Bash");
+        assert_eq!(choice["finish_reason"], "tool_calls");
+    }
+
+    #[test]
+    fn test_stream_holds_back_tool_call_text() {
+        let mut stream = TextToolCallStream::new(parser());
+        let chunk = |content: &str| json!({"choices": [{"index": 0, "delta": {"content": content}}]});
+
+        let out = stream.process_chunk(chunk("Listing files <tool"));
+        assert_eq!(out["choices"][0]["delta"]["content"], "Listing files ");
+        let out = stream.process_chunk(chunk("_call>{\"name\": \"Bash\", "));
+        assert_eq!(out["choices"][0]["delta"]["content"], "");
+        let out = stream.process_chunk(chunk("\"arguments\": {\"command\": \"ls\"}}</tool_call>"));
+        assert_eq!(out["choices"][0]["delta"]["content"], "");
+
+        let out = stream.process_chunk(json!({"choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}]}));
+        let choice = &out["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["delta"]["tool_calls"][0]["index"], 0);
+        assert_eq!(choice["delta"]["tool_calls"][0]["function"]["name"], "Bash");
+        assert!(choice["delta"].get("content").is_none());
+        assert!(stream.finish().is_none());
+
+        // Text that only looked like a marker is passed on at the finish
+        let mut stream = TextToolCallStream::new(parser());
+        assert_eq!(stream.process_chunk(chunk("a <"))["choices"][0]["delta"]["content"], "a ");
+        assert_eq!(stream.process_chunk(chunk("b"))["choices"][0]["delta"]["content"], "<b");
+        stream.process_chunk(chunk("```\ncode"));
+        let out = stream.finish().unwrap();
+        assert_eq!(out["choices"][0]["delta"]["content"], "```\ncode");
+    }
+
+    #[test]
+    fn test_stream_holds_marker_arriving_as_its_own_chunk() {
+        let mut stream = TextToolCallStream::new(parser());
+        let chunk = |content: &str| json!({"choices": [{"index": 0, "delta": {"content": content}}]});
+
+        assert_eq!(stream.process_chunk(chunk("Sure."))["choices"][0]["delta"]["content"], "Sure.");
+        assert_eq!(stream.process_chunk(chunk("<"))["choices"][0]["delta"]["content"], "");
+        let out = stream.process_chunk(chunk("tool_call>{\"name\": \"Bash\", \"arguments\": {}}</tool_call>"));
+        assert_eq!(out["choices"][0]["delta"]["content"], "");
+
+        let out = stream.process_chunk(json!({"choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}]}));
+        let choice = &out["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["delta"]["tool_calls"][0]["function"]["name"], "Bash");
+
+        let mut stream = TextToolCallStream::new(parser());
+        assert_eq!(stream.process_chunk(chunk("`"))["choices"][0]["delta"]["content"], "");
+        assert_eq!(stream.process_chunk(chunk("x"))["choices"][0]["delta"]["content"], "`x");
+    }
+
+    #[test]
+    fn test_stream_releases_closed_fences_without_tool_calls() {
+        let mut stream = TextToolCallStream::new(parser());
+        let chunk = |content: &str| json!({"choices": [{"index": 0, "delta": {"content": content}}]});
+
+        assert_eq!(stream.process_chunk(chunk("Try:\n```rust\nfn main() {}"))["choices"][0]["delta"]["content"], "Try:\n");
+        assert_eq!(stream.process_chunk(chunk("\n``"))["choices"][0]["delta"]["content"], "");
+        assert_eq!(
+            stream.process_chunk(chunk("`\nThen run it"))["choices"][0]["delta"]["content"],
+            "```rust\nfn main() {}\n```\nThen run it"
+        );
+        assert_eq!(stream.process_chunk(chunk(" with cargo."))["choices"][0]["delta"]["content"], " with cargo.");
+
+        // A tool call after the code block is still held and extracted at the finish
+        assert_eq!(
+            stream.process_chunk(chunk("\n```json\n{\"name\": \"Bash\", \"arguments\": {\"command\": \"cargo run\"}}\n```"))["choices"][0]["delta"]["content"],
+            "\n"
+        );
+        assert_eq!(stream.process_chunk(chunk(" Done."))["choices"][0]["delta"]["content"], "");
+        let out = stream.process_chunk(json!({"choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}]}));
+        let choice = &out["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["delta"]["tool_calls"][0]["function"]["name"], "Bash");
+        assert_eq!(choice["delta"]["content"], "Done.");
+    }
+}
@@ -0,0 +1,110 @@
+use serde_json::{json, Value};
+
+use crate::hash::fnv1a;
+
+/// Budgets up to this many tokens map to `reasoning_effort: "low"`
+const LOW_EFFORT_MAX: u32 = 4096;
+/// Budgets up to this many tokens map to `reasoning_effort: "medium"`
+const MEDIUM_EFFORT_MAX: u32 = 16384;
+/// Prefix of the signatures the router puts on thinking blocks of other providers
+const SIGNATURE_PREFIX: &str = "ccr_";
+
+/// Extended thinking requested by a Claude client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThinkingConfig {
+    /// `budget_tokens` of the request, if given
+    pub budget_tokens: Option<u32>,
+}
+
+impl ThinkingConfig {
+    /// Parse the Messages API `thinking` field: `{"type": "enabled", "budget_tokens": N}`
+    /// enables thinking, `{"type": "disabled"}` does not. A bare `true` is accepted too.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(true) => Some(Self { budget_tokens: None }),
+            Value::Object(object) => {
+                let enabled = match object.get("type").and_then(|t| t.as_str()) {
+                    Some(kind) => kind == "enabled",
+                    None => object.contains_key("budget_tokens"),
+                };
+                enabled.then(|| Self {
+                    budget_tokens: object
+                        .get("budget_tokens")
+                        .and_then(|b| b.as_u64())
+                        .map(|b| b.min(u32::MAX as u64) as u32),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// OpenAI `reasoning_effort` matching the budget; "medium" when no budget was given
+    pub fn reasoning_effort(&self) -> &'static str {
+        match self.budget_tokens {
+            Some(budget) if budget <= LOW_EFFORT_MAX => "low",
+            Some(budget) if budget <= MEDIUM_EFFORT_MAX => "medium",
+            Some(_) => "high",
+            None => "medium",
+        }
+    }
+
+    /// OpenRouter-style `reasoning` object: the token budget when known, else the effort
+    pub fn reasoning_object(&self) -> Value {
+        match self.budget_tokens {
+            Some(budget) => json!({"max_tokens": budget}),
+            None => json!({"effort": self.reasoning_effort()}),
+        }
+    }
+}
+
+/// Reasoning text of an OpenAI message or stream delta: `reasoning_content` (DeepSeek,
+/// Gemini and Ollama mappers) or `reasoning` (OpenRouter, Groq)
+pub fn reasoning_text(message: &Value) -> Option<&str> {
+    ["reasoning_content", "reasoning"]
+        .iter()
+        .filter_map(|field| message.get(*field).and_then(|r| r.as_str()))
+        .find(|text| !text.is_empty())
+}
+
+/// Opaque signature for a thinking block produced by a non-Anthropic provider. Claude
+/// clients require one on every thinking block; it only has to be stable for the text.
+pub fn signature(thinking: &str) -> String {
+    format!("{}{:016x}", SIGNATURE_PREFIX, fnv1a(thinking))
+}
+
+/// Whether a thinking block signature was made by `signature()` rather than by Anthropic
+pub fn is_router_signature(signature: &str) -> bool {
+    signature.starts_with(SIGNATURE_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_value() {
+        let enabled = ThinkingConfig::from_value(&json!({"type": "enabled", "budget_tokens": 10000})).unwrap();
+        assert_eq!(enabled.budget_tokens, Some(10000));
+        assert_eq!(enabled.reasoning_effort(), "medium");
+        assert_eq!(enabled.reasoning_object(), json!({"max_tokens": 10000}));
+
+        assert_eq!(ThinkingConfig::from_value(&json!({"type": "disabled"})), None);
+        assert_eq!(ThinkingConfig::from_value(&json!(false)), None);
+        assert_eq!(ThinkingConfig::from_value(&json!(true)), Some(ThinkingConfig { budget_tokens: None }));
+        assert_eq!(ThinkingConfig { budget_tokens: Some(1024) }.reasoning_effort(), "low");
+        assert_eq!(ThinkingConfig { budget_tokens: Some(31999) }.reasoning_effort(), "high");
+    }
+
+    #[test]
+    fn test_reasoning_text_and_signature() {
+        assert_eq!(reasoning_text(&json!({"reasoning_content": "a"})), Some("a"));
+        assert_eq!(reasoning_text(&json!({"reasoning_content": "", "reasoning": "b"})), Some("b"));
+        assert_eq!(reasoning_text(&json!({"content": "c"})), None);
+        assert_eq!(signature("x"), signature("x"));
+        assert!(signature("x").starts_with("ccr_"));
+        assert_eq!(signature(""), "ccr_cbf29ce484222325");
+        assert_eq!(signature("a"), "ccr_af63dc4c8601ec8c");
+        assert!(is_router_signature(&signature("x")));
+        assert!(!is_router_signature("EqQBCkYIARgCKkA"));
+    }
+}
@@ -0,0 +1,463 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::server::ClaudeRequest;
+
+/// Claude tool input for the `function.arguments` string of an OpenAI tool call.
+///
+/// Well-formed JSON objects are used as-is and an empty string means no arguments. Anything
+/// else goes through `repair_json`, and a repaired value has to satisfy the tool's
+/// `input_schema`, so a wrong guess is reported instead of running the tool with it.
+pub fn parse_tool_input(arguments: &str, input_schema: Option<&Value>) -> Result<Value, String> {
+    if arguments.trim().is_empty() {
+        return Ok(Value::Object(Default::default()));
+    }
+    match serde_json::from_str::<Value>(arguments) {
+        Ok(value @ Value::Object(_)) => return Ok(value),
+        // Some models encode the arguments twice
+        Ok(Value::String(inner)) => {
+            if let Ok(value @ Value::Object(_)) = serde_json::from_str::<Value>(&inner) {
+                return Ok(value);
+            }
+        }
+        _ => {}
+    }
+
+    let repaired = repair_json(arguments).ok_or_else(|| format!("arguments are not valid JSON: {}", excerpt(arguments)))?;
+    if !repaired.is_object() {
+        return Err(format!("arguments are not a JSON object: {}", excerpt(arguments)));
+    }
+    if let Some(schema) = input_schema {
+        validate(&repaired, schema).map_err(|e| format!("repaired arguments do not match the input schema: {}", e))?;
+    }
+    log::warn!("Repaired malformed tool call arguments: {}", excerpt(arguments));
+    Ok(repaired)
+}
+
+/// Text to append to streamed `arguments` whose fragments were already forwarded: empty for a
+/// JSON object (or no arguments), else the characters that close output cut off mid-value.
+/// A completed object has to satisfy `input_schema`; anything that appending cannot fix is an error.
+pub fn completion_suffix(arguments: &str, input_schema: Option<&Value>) -> Result<String, String> {
+    if arguments.trim().is_empty() {
+        return Ok(String::new());
+    }
+    match serde_json::from_str::<Value>(arguments) {
+        Ok(Value::Object(_)) => return Ok(String::new()),
+        Ok(_) => return Err(format!("arguments are not a JSON object: {}", excerpt(arguments))),
+        Err(_) => {}
+    }
+
+    let (in_string, closers) = open_containers(arguments);
+    let quote = if in_string { "\"" } else { "" };
+    // A value or a `:` may be missing after the last key
+    for filler in ["", "null", ":null"] {
+        let suffix = format!("{}{}{}", quote, filler, closers);
+        if let Ok(completed @ Value::Object(_)) = serde_json::from_str::<Value>(&format!("{}{}", arguments, suffix)) {
+            if let Some(schema) = input_schema {
+                validate(&completed, schema).map_err(|e| format!("repaired arguments do not match the input schema: {}", e))?;
+            }
+            log::warn!("Completed truncated tool call arguments: {}", excerpt(arguments));
+            return Ok(suffix);
+        }
+    }
+    Err(format!("arguments are not valid JSON: {}", excerpt(arguments)))
+}
+
+/// Whether `text` ends inside a string, and the brackets that close its open containers
+fn open_containers(text: &str) -> (bool, String) {
+    let mut closers = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop();
+            }
+            _ => {}
+        }
+    }
+    (in_string, closers.into_iter().rev().collect())
+}
+
+/// `input_schema` of the request tool called `name`
+pub fn input_schema<'a>(claude_req: &'a ClaudeRequest, name: &str) -> Option<&'a Value> {
+    claude_req
+        .tools
+        .iter()
+        .flatten()
+        .find(|tool| tool.get("name").and_then(|n| n.as_str()) == Some(name))
+        .and_then(|tool| tool.get("input_schema"))
+}
+
+/// `input_schema` of every request tool by name, for streams that outlive the request
+pub fn input_schemas(claude_req: &ClaudeRequest) -> HashMap<String, Value> {
+    claude_req
+        .tools
+        .iter()
+        .flatten()
+        .filter_map(|tool| Some((tool.get("name")?.as_str()?.to_string(), tool.get("input_schema")?.clone())))
+        .collect()
+}
+
+/// Best-effort parse of almost-JSON as written by weaker models: markdown fences and text
+/// around the value, trailing commas, unquoted keys, single-quoted strings, Python literals
+/// and output cut off mid-value (open strings, containers and dangling keys are closed)
+pub fn repair_json(text: &str) -> Option<Value> {
+    let text = strip_fence(text);
+    let start = text.find(['{', '['])?;
+    let repaired = JsonRepairer::default().run(&text[start..]);
+    // Text after the value is ignored
+    serde_json::Deserializer::from_str(&repaired).into_iter::<Value>().next()?.ok()
+}
+
+/// Light check of `value` against a JSON schema: types, required properties and enums,
+/// recursing into properties and array items. Other keywords are not checked.
+pub fn validate(value: &Value, schema: &Value) -> Result<(), String> {
+    validate_at(value, schema, "input")
+}
+
+fn validate_at(value: &Value, schema: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            return Err(format!("{} should be {} but is {}", path, types.join(" or "), type_name(value)));
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !allowed.contains(value) {
+            return Err(format!("{} is not one of {}", path, Value::Array(allowed.clone())));
+        }
+    }
+    match value {
+        Value::Object(map) => {
+            for name in schema.get("required").and_then(|r| r.as_array()).into_iter().flatten() {
+                if let Some(name) = name.as_str().filter(|n| !map.contains_key(*n)) {
+                    return Err(format!("{} is missing required property `{}`", path, name));
+                }
+            }
+            if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+                for (name, property) in map {
+                    if let Some(property_schema) = properties.get(name) {
+                        validate_at(property, property_schema, &format!("{}.{}", path, name))?;
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(item, item_schema, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Start of a raw value, for error messages and logs
+fn excerpt(text: &str) -> String {
+    const MAX_CHARS: usize = 200;
+    let mut excerpt: String = text.chars().take(MAX_CHARS).collect();
+    if text.chars().count() > MAX_CHARS {
+        excerpt.push_str("...");
+    }
+    excerpt
+}
+
+/// Contents of the first ```-fenced block, or the text itself
+fn strip_fence(text: &str) -> &str {
+    let Some(open) = text.find("```") else {
+        return text;
+    };
+    let after = &text[open + 3..];
+    // Skip the language tag line (```json)
+    let body = after.find('\n').map(|i| &after[i + 1..]).unwrap_or(after);
+    body.find("```").map(|close| &body[..close]).unwrap_or(body)
+}
+
+enum Frame {
+    Object { expecting_key: bool },
+    Array,
+}
+
+/// Single pass over almost-JSON that writes strict JSON
+#[derive(Default)]
+struct JsonRepairer {
+    out: String,
+    stack: Vec<Frame>,
+    /// Where the last object key started while its `:` has not been seen yet
+    pending_key: Option<usize>,
+}
+
+impl JsonRepairer {
+    fn run(mut self, text: &str) -> String {
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' | '\'' => {
+                    let start = self.out.len();
+                    self.read_string(c, &mut chars);
+                    if let Some(Frame::Object { expecting_key }) = self.stack.last_mut() {
+                        if *expecting_key {
+                            *expecting_key = false;
+                            self.pending_key = Some(start);
+                        }
+                    }
+                }
+                '{' => {
+                    self.out.push(c);
+                    self.stack.push(Frame::Object { expecting_key: true });
+                }
+                '[' => {
+                    self.out.push(c);
+                    self.stack.push(Frame::Array);
+                }
+                '}' | ']' => {
+                    self.trim_trailing_comma();
+                    self.pending_key = None;
+                    let closing = match self.stack.pop() {
+                        Some(Frame::Object { .. }) => '}',
+                        Some(Frame::Array) => ']',
+                        None => c,
+                    };
+                    self.out.push(closing);
+                    if self.stack.is_empty() {
+                        break;
+                    }
+                }
+                ',' => {
+                    self.out.push(c);
+                    if let Some(Frame::Object { expecting_key }) = self.stack.last_mut() {
+                        *expecting_key = true;
+                    }
+                }
+                ':' => {
+                    self.out.push(c);
+                    self.pending_key = None;
+                }
+                c if c.is_ascii_digit() => {
+                    // Keep exponents such as `2.5E-3` inside the number
+                    self.out.push(c);
+                    while let Some(&next) = chars.peek() {
+                        if next.is_ascii_digit() || matches!(next, '.' | 'e' | 'E' | '+' | '-') {
+                            self.out.push(next);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                c if c.is_alphabetic() || c == '_' || c == '$' => {
+                    let mut word = String::from(c);
+                    while let Some(&next) = chars.peek() {
+                        if next.is_alphanumeric() || matches!(next, '_' | '$' | '-' | '.') {
+                            word.push(next);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    self.write_word(&word);
+                }
+                _ => self.out.push(c),
+            }
+        }
+        self.close();
+        self.out
+    }
+
+    /// Copy a string with `quote` as its delimiter as a double-quoted JSON string
+    fn read_string(&mut self, quote: char, chars: &mut impl Iterator<Item = char>) {
+        self.out.push('"');
+        let mut escaped = false;
+        for c in chars.by_ref() {
+            if escaped {
+                escaped = false;
+                if c == '\'' {
+                    // \' is not a JSON escape
+                    self.out.pop();
+                }
+                self.out.push(c);
+            } else if c == '\\' {
+                escaped = true;
+                self.out.push(c);
+            } else if c == quote {
+                self.out.push('"');
+                return;
+            } else if c == '"' {
+                self.out.push_str("\\\"");
+            } else if c == '\n' {
+                self.out.push_str("\\n");
+            } else {
+                self.out.push(c);
+            }
+        }
+        // Cut off inside the string
+        if escaped {
+            self.out.pop();
+        }
+        self.out.push('"');
+    }
+
+    /// Bare word: an unquoted key, a JSON or Python literal, or an unquoted string
+    fn write_word(&mut self, word: &str) {
+        let expecting_key = matches!(self.stack.last(), Some(Frame::Object { expecting_key: true }));
+        if expecting_key {
+            self.pending_key = Some(self.out.len());
+            if let Some(Frame::Object { expecting_key }) = self.stack.last_mut() {
+                *expecting_key = false;
+            }
+            self.out.push_str(&Value::String(word.to_string()).to_string());
+            return;
+        }
+        match word {
+            "true" | "True" => self.out.push_str("true"),
+            "false" | "False" => self.out.push_str("false"),
+            "null" | "None" => self.out.push_str("null"),
+            _ => self.out.push_str(&Value::String(word.to_string()).to_string()),
+        }
+    }
+
+    fn trim_trailing_comma(&mut self) {
+        let trimmed = self.out.trim_end().len();
+        self.out.truncate(trimmed);
+        if self.out.ends_with(',') {
+            self.out.pop();
+        }
+    }
+
+    /// Finish output that was cut off: drop a dangling key, complete a value and close
+    /// every open container
+    fn close(&mut self) {
+        if self.stack.is_empty() {
+            return;
+        }
+        if let Some(start) = self.pending_key.take() {
+            self.out.truncate(start);
+        }
+        self.trim_trailing_comma();
+        while self.out.ends_with(['.', '-', '+', 'e', 'E']) && !self.out.ends_with("true") && !self.out.ends_with("false") {
+            self.out.pop();
+        }
+        if self.out.ends_with(':') {
+            self.out.push_str("null");
+        }
+        while let Some(frame) = self.stack.pop() {
+            self.out.push(match frame {
+                Frame::Object { .. } => '}',
+                Frame::Array => ']',
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_repair_json() {
+        assert_eq!(repair_json(r#"{"a": 1, "b": [1, 2,],}"#), Some(json!({"a": 1, "b": [1, 2]})));
+        assert_eq!(repair_json("{path: 'src/main.rs', recursive: True}"), Some(json!({"path": "src/main.rs", "recursive": true})));
+        assert_eq!(repair_json(r#"{'text': 'it\'s "quoted"'}"#), Some(json!({"text": "it's \"quoted\""})));
+        assert_eq!(
+            repair_json("Arguments:\n```json\n{\"command\": \"ls -la\"}\n```"),
+            Some(json!({"command": "ls -la"}))
+        );
+        assert_eq!(repair_json(r#"{"command": "cargo test", "timeout": 12"#), Some(json!({"command": "cargo test", "timeout": 12})));
+        assert_eq!(repair_json(r#"{"todos": [{"content": "write te"#), Some(json!({"todos": [{"content": "write te"}]})));
+        assert_eq!(repair_json(r#"{"a": "x", "b""#), Some(json!({"a": "x"})));
+        assert_eq!(repair_json(r#"{"a": "x", "b":"#), Some(json!({"a": "x", "b": null})));
+        assert_eq!(repair_json(r#"{"a": 1} trailing text"#), Some(json!({"a": 1})));
+        assert_eq!(repair_json("{\"a\": 2.5E-3,}"), Some(json!({"a": 2.5E-3})));
+        assert_eq!(repair_json("{a: 1e5, b: [-1E+2,]}"), Some(json!({"a": 1e5, "b": [-1E+2]})));
+        assert_eq!(repair_json("no json here"), None);
+    }
+
+    #[test]
+    fn test_parse_tool_input() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "command": {"type": "string"},
+                "timeout": {"type": "integer"},
+                "mode": {"type": "string", "enum": ["fast", "slow"]}
+            },
+            "required": ["command"]
+        });
+        assert_eq!(parse_tool_input("", Some(&schema)), Ok(json!({})));
+        assert_eq!(parse_tool_input(r#"{"command": "ls"}"#, Some(&schema)), Ok(json!({"command": "ls"})));
+        assert_eq!(parse_tool_input(r#""{\"command\": \"ls\"}""#, Some(&schema)), Ok(json!({"command": "ls"})));
+        assert_eq!(parse_tool_input("{command: 'ls', timeout: 5,}", Some(&schema)), Ok(json!({"command": "ls", "timeout": 5})));
+        // Well-formed input is left to the client to validate
+        assert_eq!(parse_tool_input(r#"{"timeout": 5}"#, Some(&schema)), Ok(json!({"timeout": 5})));
+
+        let missing = parse_tool_input("{timeout: 5,}", Some(&schema)).unwrap_err();
+        assert!(missing.contains("missing required property `command`"), "{}", missing);
+        let wrong_type = parse_tool_input("{command: 'ls', timeout: 'soon'}", Some(&schema)).unwrap_err();
+        assert!(wrong_type.contains("input.timeout should be integer but is string"), "{}", wrong_type);
+        let not_enum = parse_tool_input("{command: 'ls', mode: 'medium'}", Some(&schema)).unwrap_err();
+        assert!(not_enum.contains("input.mode is not one of"), "{}", not_enum);
+        let unparseable = parse_tool_input("run ls please", Some(&schema)).unwrap_err();
+        assert!(unparseable.starts_with("arguments are not valid JSON"), "{}", unparseable);
+        assert!(parse_tool_input("[1, 2]", None).unwrap_err().contains("not a JSON object"));
+    }
+
+    #[test]
+    fn test_completion_suffix() {
+        let schema = json!({"type": "object", "properties": {"command": {"type": "string"}}, "required": ["command"]});
+        assert_eq!(completion_suffix("", Some(&schema)), Ok(String::new()));
+        assert_eq!(completion_suffix(r#"{"command": "ls"}"#, Some(&schema)), Ok(String::new()));
+        assert_eq!(completion_suffix(r#"{"command": "echo \"hi"#, Some(&schema)), Ok("\"}".to_string()));
+        assert_eq!(completion_suffix(r#"{"command": "ls", "args": ["-l""#, Some(&schema)), Ok("]}".to_string()));
+        assert_eq!(completion_suffix(r#"{"command": "ls", "cwd":"#, Some(&schema)), Ok("null}".to_string()));
+        assert_eq!(completion_suffix(r#"{"command": "ls", "cwd""#, Some(&schema)), Ok(":null}".to_string()));
+
+        let missing = completion_suffix(r#"{"comm"#, Some(&schema)).unwrap_err();
+        assert!(missing.contains("missing required property `command`"), "{}", missing);
+        // Fixing these would change text that was already forwarded
+        let single_quoted = completion_suffix("{'command': 'ls'}", Some(&schema)).unwrap_err();
+        assert!(single_quoted.starts_with("arguments are not valid JSON"), "{}", single_quoted);
+        assert!(completion_suffix(r#""{\"command\": \"ls\"}""#, None).unwrap_err().contains("not a JSON object"));
+    }
+}
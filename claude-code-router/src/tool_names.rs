@@ -0,0 +1,212 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::server::ClaudeRequest;
+use crate::hash::fnv1a;
+
+/// Longest function name OpenAI-compatible providers accept
+pub const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// Whether providers accept `name` as a function name: 1 to 64 of `a-z A-Z 0-9 _ -`
+pub fn is_valid_tool_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_TOOL_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Provider-safe form of a tool name. Valid names are kept; others get their invalid
+/// characters replaced by `_`, are cut to fit and end in an FNV-1a hash of the original name,
+/// so different names stay different and the same name always maps the same way, also
+/// across restarts.
+pub fn sanitize_tool_name(name: &str) -> String {
+    if is_valid_tool_name(name) {
+        return name.to_string();
+    }
+    let suffix = format!("_{:08x}", fnv1a(name) as u32);
+
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(MAX_TOOL_NAME_LEN - suffix.len())
+        .collect();
+    cleaned + &suffix
+}
+
+/// Sanitize a tool name field in place
+fn sanitize_name_field(name: Option<&mut Value>) {
+    if let Some(Value::String(name)) = name {
+        *name = sanitize_tool_name(name);
+    }
+}
+
+/// Per-request mapping between the tool names Claude Code sent and the sanitized names
+/// the provider sees, so names can be restored in responses
+#[derive(Debug, Clone, Default)]
+pub struct ToolNameMap {
+    /// sanitized name -> original name, only for names that changed
+    originals: HashMap<String, String>,
+}
+
+impl ToolNameMap {
+    /// Mapping for the tools of a request and the tool_use blocks in its history
+    pub fn from_request(claude_req: &ClaudeRequest) -> Self {
+        let tool_names = claude_req
+            .tools
+            .iter()
+            .flatten()
+            .filter_map(|tool| tool.get("name").and_then(|n| n.as_str()));
+        let history_names = claude_req
+            .messages
+            .iter()
+            .filter_map(|message| message.content.as_array())
+            .flatten()
+            .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_use"))
+            .filter_map(|block| block.get("name").and_then(|n| n.as_str()));
+        Self::from_names(tool_names.chain(history_names))
+    }
+
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let originals = names
+            .into_iter()
+            .filter(|name| !is_valid_tool_name(name))
+            .map(|name| (sanitize_tool_name(name), name.to_string()))
+            .collect();
+        Self { originals }
+    }
+
+    /// True when every name was already valid
+    pub fn is_empty(&self) -> bool {
+        self.originals.is_empty()
+    }
+
+    /// The original name for a sanitized one; other names are returned unchanged
+    pub fn restore<'a>(&'a self, name: &'a str) -> &'a str {
+        self.originals.get(name).map(String::as_str).unwrap_or(name)
+    }
+
+    /// Sanitize every tool name in an OpenAI chat completions body: tool definitions,
+    /// historical assistant `tool_calls`, `tool` message names and a named `tool_choice`
+    pub fn apply_to_openai_body(&self, body: &mut Value) {
+        if self.is_empty() {
+            return;
+        }
+        if let Some(tools) = body.get_mut("tools").and_then(|t| t.as_array_mut()) {
+            for tool in tools {
+                sanitize_name_field(tool.get_mut("function").and_then(|f| f.get_mut("name")));
+            }
+        }
+        if let Some(messages) = body.get_mut("messages").and_then(|m| m.as_array_mut()) {
+            for message in messages {
+                if let Some(tool_calls) = message.get_mut("tool_calls").and_then(|t| t.as_array_mut()) {
+                    for tool_call in tool_calls {
+                        sanitize_name_field(tool_call.get_mut("function").and_then(|f| f.get_mut("name")));
+                    }
+                }
+                if message.get("role").and_then(|r| r.as_str()) == Some("tool") {
+                    sanitize_name_field(message.get_mut("name"));
+                }
+            }
+        }
+        if let Some(tool_choice) = body.get_mut("tool_choice") {
+            sanitize_name_field(tool_choice.get_mut("function").and_then(|f| f.get_mut("name")));
+        }
+    }
+
+    /// Sanitize every tool name in a Gemini `GenerateContentRequest`: function declarations,
+    /// `functionCall` / `functionResponse` parts and `allowedFunctionNames`
+    pub fn apply_to_gemini_body(&self, body: &mut Value) {
+        if self.is_empty() {
+            return;
+        }
+        if let Some(tools) = body.get_mut("tools").and_then(|t| t.as_array_mut()) {
+            for declaration in tools
+                .iter_mut()
+                .filter_map(|tool| tool.get_mut("functionDeclarations").and_then(|d| d.as_array_mut()))
+                .flatten()
+            {
+                sanitize_name_field(declaration.get_mut("name"));
+            }
+        }
+        if let Some(contents) = body.get_mut("contents").and_then(|c| c.as_array_mut()) {
+            for part in contents
+                .iter_mut()
+                .filter_map(|content| content.get_mut("parts").and_then(|p| p.as_array_mut()))
+                .flatten()
+            {
+                sanitize_name_field(part.get_mut("functionCall").and_then(|f| f.get_mut("name")));
+                sanitize_name_field(part.get_mut("functionResponse").and_then(|f| f.get_mut("name")));
+            }
+        }
+        if let Some(allowed) = body
+            .pointer_mut("/toolConfig/functionCallingConfig/allowedFunctionNames")
+            .and_then(|a| a.as_array_mut())
+        {
+            for name in allowed {
+                sanitize_name_field(Some(name));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sanitize_tool_name() {
+        assert_eq!(sanitize_tool_name("Bash"), "Bash");
+        assert_eq!(sanitize_tool_name("mcp__github__create_issue"), "mcp__github__create_issue");
+
+        let dotted = sanitize_tool_name("files.read");
+        assert_eq!(dotted, "files_read_d0376a62");
+        assert!(is_valid_tool_name(&dotted));
+        assert_ne!(dotted, sanitize_tool_name("files_read"));
+
+        let long = format!("mcp__{}__{}", "server".repeat(10), "tool_name");
+        let sanitized = sanitize_tool_name(&long);
+        assert_eq!(sanitized.len(), MAX_TOOL_NAME_LEN);
+        assert!(is_valid_tool_name(&sanitized));
+        assert_eq!(sanitized, sanitize_tool_name(&long));
+        assert_ne!(sanitized, sanitize_tool_name(&format!("{}2", long)));
+    }
+
+    #[test]
+    fn test_map_sanitizes_body_and_restores() {
+        let long = format!("mcp__{}__search", "x".repeat(70));
+        let claude_req: ClaudeRequest = serde_json::from_value(json!({
+            "model": "m",
+            "messages": [
+                {"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "files.read", "input": {}}]}
+            ],
+            "tools": [{"name": long, "input_schema": {}}, {"name": "Bash", "input_schema": {}}]
+        }))
+        .unwrap();
+        let map = ToolNameMap::from_request(&claude_req);
+        let short = sanitize_tool_name(&long);
+        let dotted = sanitize_tool_name("files.read");
+
+        let mut body = json!({
+            "tools": [
+                {"type": "function", "function": {"name": long}},
+                {"type": "function", "function": {"name": "Bash"}}
+            ],
+            "messages": [
+                {"role": "assistant", "content": null, "tool_calls": [{"id": "t1", "type": "function", "function": {"name": "files.read", "arguments": "{}"}}]},
+                {"role": "tool", "tool_call_id": "t1", "name": "files.read", "content": "ok"}
+            ],
+            "tool_choice": {"type": "function", "function": {"name": long}}
+        });
+        map.apply_to_openai_body(&mut body);
+        assert_eq!(body["tools"][0]["function"]["name"], short);
+        assert_eq!(body["tools"][1]["function"]["name"], "Bash");
+        assert_eq!(body["messages"][0]["tool_calls"][0]["function"]["name"], dotted);
+        assert_eq!(body["messages"][1]["name"], dotted);
+        assert_eq!(body["tool_choice"]["function"]["name"], short);
+
+        assert_eq!(map.restore(&short), long);
+        assert_eq!(map.restore(&dotted), "files.read");
+        assert_eq!(map.restore("Bash"), "Bash");
+        assert!(ToolNameMap::from_names(["Bash", "Read"]).is_empty());
+    }
+}
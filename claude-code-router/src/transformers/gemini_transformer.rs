@@ -4,7 +4,9 @@ use crate::transformers::ProviderTransformer;
 use std::error::Error;
 
 /// Gemini transformer: Converts to Gemini API format
-/// Similar to OpenRouter but includes system field support
+/// The system prompt already travels as a `system` message (see `Provider.system_prompt`),
+/// which Gemini's OpenAI endpoint accepts, so only tools need converting
+#[derive(Default)]
 pub struct GeminiTransformer;
 
 impl GeminiTransformer {
@@ -14,12 +16,7 @@ impl GeminiTransformer {
 }
 
 impl ProviderTransformer for GeminiTransformer {
-    fn transform(&self, body: &mut Value, claude_req: &ClaudeRequest) -> Result<(), Box<dyn Error>> {
-        // Add system field if present (Gemini supports this unlike Groq)
-        if let Some(system) = &claude_req.system {
-            body["system"] = system.clone();
-        }
-        
+    fn transform(&self, body: &mut Value, _claude_req: &ClaudeRequest) -> Result<(), Box<dyn Error>> {
         // Transform tools if present (same logic as OpenRouter)
         if let Some(tools) = body.get("tools") {
             let empty_vec = vec![];
@@ -60,7 +57,7 @@ mod tests {
     use serde_json::json;
     
     #[test]
-    fn test_gemini_adds_system_field() {
+    fn test_gemini_leaves_system_prompt_in_messages() {
         let transformer = GeminiTransformer::new();
         let claude_req = ClaudeRequest {
             model: "test".to_string(),
@@ -72,16 +69,18 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
             "model": "test",
-            "messages": []
+            "messages": [{"role": "system", "content": "You are a helpful assistant"}]
         });
         
         transformer.transform(&mut body, &claude_req).unwrap();
         
-        assert_eq!(body["system"], "You are a helpful assistant");
+        assert!(body.get("system").is_none());
+        assert_eq!(body["messages"][0]["content"], "You are a helpful assistant");
     }
     
     #[test]
@@ -97,6 +96,7 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
@@ -106,7 +106,7 @@ mod tests {
                 {
                     "name": "search",
                     "description": "Search the web",
-                    "input_schema": {"type": "object"}
+                    "input_schema": {"type": "object", "additionalProperties": false, "properties": {}}
                 }
             ]
         });
@@ -117,5 +117,10 @@ mod tests {
         assert_eq!(tools[0]["type"], "function");
         assert_eq!(tools[0]["function"]["name"], "search");
         assert_eq!(tools[0]["function"]["description"], "Search the web");
+        // Schema rewriting is left to ProviderClient, per `schema::profile_for()`
+        assert_eq!(
+            tools[0]["function"]["parameters"],
+            json!({"type": "object", "additionalProperties": false, "properties": {}})
+        );
     }
 }
\ No newline at end of file
@@ -56,6 +56,7 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
@@ -82,6 +83,7 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
@@ -110,6 +112,7 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
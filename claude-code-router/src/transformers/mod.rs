@@ -3,6 +3,7 @@ pub mod gemini_transformer;
 pub mod maxtoken_transformer;
 
 use serde_json::Value;
+use crate::config::SchemaProfile;
 use crate::server::ClaudeRequest;
 use std::error::Error;
 
@@ -15,6 +16,17 @@ pub trait ProviderTransformer {
     fn name(&self) -> &'static str;
 }
 
+/// Tool schema profile a transformer implies for providers without a `schema` setting:
+/// backends behind OpenRouter (Groq among them) reject many JSON Schema keywords, and
+/// Gemini's OpenAI endpoint only takes its OpenAPI subset
+pub fn schema_profile(transformer_name: &str) -> Option<SchemaProfile> {
+    match transformer_name {
+        "openrouter" => Some(SchemaProfile::Strict),
+        "gemini" => Some(SchemaProfile::Gemini),
+        _ => None,
+    }
+}
+
 /// Apply a single transformer to the request body
 pub fn apply_transformer(
     transformer_name: &str,
@@ -5,6 +5,7 @@ use std::error::Error;
 
 /// OpenRouter transformer: Ensures tools are in OpenAI format
 /// Specifically designed for Groq compatibility (no system field support)
+#[derive(Default)]
 pub struct OpenRouterTransformer;
 
 impl OpenRouterTransformer {
@@ -70,6 +71,7 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
@@ -105,6 +107,7 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
@@ -142,6 +145,7 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
@@ -169,6 +173,7 @@ mod tests {
             temperature: None,
             stream: None,
             metadata: None,
+            ..Default::default()
         };
         
         let mut body = json!({
@@ -180,4 +185,23 @@ mod tests {
         
         assert!(body.get("tools").is_none());
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_thinking_is_left_to_provider_reasoning_setting() {
+        let transformer = OpenRouterTransformer::new();
+        let claude_req = ClaudeRequest {
+            model: "test".to_string(),
+            thinking: Some(json!({"type": "enabled", "budget_tokens": 8000})),
+            ..Default::default()
+        };
+        
+        // Reasoning fields come from Provider.reasoning (apply_reasoning), not from the transformer
+        let mut body = json!({"model": "test", "messages": []});
+        transformer.transform(&mut body, &claude_req).unwrap();
+        assert!(body.get("reasoning").is_none());
+        
+        let mut body = json!({"model": "test", "messages": [], "reasoning": {"max_tokens": 8000}});
+        transformer.transform(&mut body, &claude_req).unwrap();
+        assert_eq!(body["reasoning"], json!({"max_tokens": 8000}));
+    }
+}
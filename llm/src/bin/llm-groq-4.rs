@@ -20,7 +20,7 @@ fn main() {
     eprintln!("Checking output file status with git");
     if Path::new(output_file).exists() {
         let git_status = Command::new("git")
-            .args(&["status", "--porcelain", output_file])
+            .args(["status", "--porcelain", output_file])
             .output()
             .expect("Failed to execute git status");
         
@@ -96,10 +96,10 @@ fn main() {
     fs::rename(output_file, &orig_path)
             .unwrap_or_else(|_| panic!("Failed to move orig file to temp file"));
 
-    fs::write(&output_file, &response)
+    fs::write(output_file, &response)
         .unwrap_or_else(|_| panic!("Failed to write temporary file"));
     
-    let second_compiler_errors = run_cargo_check(&output_file);
+    let second_compiler_errors = run_cargo_check(output_file);
 
     let eval_prompt = format!(
         "Please CAREFULLY evaluate the below description (enclosed into <result-description></result-description>), and two outputs corresponding to this description, first one enclosed into \"<first-result></first-result>\" and the second enclosed into \"<second-result></second-result>\", with compile errors of first result included into \"<first-compile-errors></first-compile-errors>\" and second compile errors as \"<second-compile-errors></second-compile-errors>\", and evaluate which of the two is more precise and correct in implementing the description - and also which of them compiles! Then, if the first result is better, output the phrase 'First result is better.', if the second result is better, output the phrase 'The second implementation is better.'. Output only one of the two phrases, and nothing else\n\n<result-description>\n{}\n</result-description>\n\n<first-result>\n{}</first-result>\n\n<second-result>\n{}</second-result>\n\n<first-compile-errors>\n{}</first-compile-errors>\n\n<second-compile-errors>\n{}</second-compile-errors>",
@@ -174,7 +174,7 @@ fn run_cargo_check(source_file: &str) -> Vec<String> {
     eprintln!("Running cargo check with file {}", source_file);
     // Execute cargo check with JSON output
     let output = Command::new("cargo")
-        .args(&["check", "--message-format", "json"])
+        .args(["check", "--message-format", "json"])
         .output()
         .expect("Failed to execute cargo check command");
 
@@ -196,29 +196,28 @@ fn run_cargo_check(source_file: &str) -> Vec<String> {
             .expect("Failed to parse JSON output from cargo");
         
         // Check if this is a compiler message
-        if let Some(reason) = json.get("reason") {
-            if reason == "compiler-message" {
-                if let Some(message) = json.get("message") {
-                    // Check if this message has spans (location information)
-                    if let Some(spans) = message.get("spans").and_then(|s| s.as_array()) {
-                        for span in spans {
-                            if let Some(file_name) = span.get("file_name").and_then(|f| f.as_str()) {
-                                let span_path = Path::new(file_name);
+        let message = match json.get("message") {
+            Some(message) if json["reason"] == "compiler-message" => message,
+            _ => continue,
+        };
+
+        // Check if this message has spans (location information)
+        if let Some(spans) = message.get("spans").and_then(|s| s.as_array()) {
+            for span in spans {
+                if let Some(file_name) = span.get("file_name").and_then(|f| f.as_str()) {
+                    let span_path = Path::new(file_name);
+                            
+                    // Check if this error is from our target source file
+                    if span_path == source_path || 
+                       span_path.file_name() == source_path.file_name() {
                                 
-                                // Check if this error is from our target source file
-                                if span_path == source_path || 
-                                   span_path.file_name() == source_path.file_name() {
-                                    
-                                    // Extract the error message
-                                    if let Some(rendered) = message.get("rendered").and_then(|r| r.as_str()) {
-                                        errors.push(rendered.to_string());
-                                    } else if let Some(msg_text) = message.get("message").and_then(|m| m.as_str()) {
-                                        errors.push(msg_text.to_string());
-                                    }
-                                    break; // Found matching file, no need to check other spans
-                                }
-                            }
+                        // Extract the error message
+                        if let Some(rendered) = message.get("rendered").and_then(|r| r.as_str()) {
+                            errors.push(rendered.to_string());
+                        } else if let Some(msg_text) = message.get("message").and_then(|m| m.as_str()) {
+                            errors.push(msg_text.to_string());
                         }
+                        break; // Found matching file, no need to check other spans
                     }
                 }
             }
@@ -21,7 +21,7 @@ fn main() {
     eprintln!("Checking output file status with git");
     if Path::new(output_file).exists() {
         let git_status = Command::new("git")
-            .args(&["status", "--porcelain", output_file])
+            .args(["status", "--porcelain", output_file])
             .output()
             .expect("Failed to execute git status");
         
@@ -98,7 +98,7 @@ fn main() {
             .unwrap_or_else(|_| panic!("Failed to backup original file"));
     }
 
-    fs::write(&output_file, &response)
+    fs::write(output_file, &response)
         .unwrap_or_else(|_| panic!("Failed to write temporary file"));
     
     let second_compiler_errors = run_cargo_check(output_file);
@@ -175,7 +175,7 @@ fn run_cargo_check(source_file: &str) -> Vec<String> {
     eprintln!("Running cargo check with file {}", source_file);
     // Execute cargo check with JSON output
     let output = Command::new("cargo")
-        .args(&["check", "--message-format", "json"])
+        .args(["check", "--message-format", "json"])
         .output()
         .expect("Failed to execute cargo check command");
 
@@ -197,29 +197,28 @@ fn run_cargo_check(source_file: &str) -> Vec<String> {
             .expect("Failed to parse JSON output from cargo");
         
         // Check if this is a compiler message
-        if let Some(reason) = json.get("reason") {
-            if reason == "compiler-message" {
-                if let Some(message) = json.get("message") {
-                    // Check if this message has spans (location information)
-                    if let Some(spans) = message.get("spans").and_then(|s| s.as_array()) {
-                        for span in spans {
-                            if let Some(file_name) = span.get("file_name").and_then(|f| f.as_str()) {
-                                let span_path = Path::new(file_name);
+        let message = match json.get("message") {
+            Some(message) if json["reason"] == "compiler-message" => message,
+            _ => continue,
+        };
+
+        // Check if this message has spans (location information)
+        if let Some(spans) = message.get("spans").and_then(|s| s.as_array()) {
+            for span in spans {
+                if let Some(file_name) = span.get("file_name").and_then(|f| f.as_str()) {
+                    let span_path = Path::new(file_name);
+                            
+                    // Check if this error is from our target source file
+                    if span_path == source_path || 
+                       span_path.file_name() == source_path.file_name() {
                                 
-                                // Check if this error is from our target source file
-                                if span_path == source_path || 
-                                   span_path.file_name() == source_path.file_name() {
-                                    
-                                    // Extract the error message
-                                    if let Some(rendered) = message.get("rendered").and_then(|r| r.as_str()) {
-                                        errors.push(rendered.to_string());
-                                    } else if let Some(msg_text) = message.get("message").and_then(|m| m.as_str()) {
-                                        errors.push(msg_text.to_string());
-                                    }
-                                    break; // Found matching file, no need to check other spans
-                                }
-                            }
+                        // Extract the error message
+                        if let Some(rendered) = message.get("rendered").and_then(|r| r.as_str()) {
+                            errors.push(rendered.to_string());
+                        } else if let Some(msg_text) = message.get("message").and_then(|m| m.as_str()) {
+                            errors.push(msg_text.to_string());
                         }
+                        break; // Found matching file, no need to check other spans
                     }
                 }
             }
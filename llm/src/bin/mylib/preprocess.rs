@@ -11,7 +11,7 @@ fn preprocess_with_depth(file_name: &str, depth: usize) -> String {
     }
 
     let path = Path::new(file_name);
-    let contents = fs::read_to_string(&path).expect(&format!("Unable to read file {}", &file_name));
+    let contents = fs::read_to_string(path).unwrap_or_else(|_| panic!("Unable to read file {}", &file_name));
     let mut output = String::new();
     let mut cursor = 0;
 
@@ -53,7 +53,7 @@ fn preprocess_with_depth(file_name: &str, depth: usize) -> String {
 mod tests {
     use super::*;
     use tempfile::{NamedTempFile, tempdir};
-    use std::fs::{self, File};
+    use std::fs;
     use std::io::Write;
 
     #[test]
@@ -184,7 +184,7 @@ mod tests {
         fs::write(&b_path, format!("{{!{}!}}", "a.txt")).unwrap();
 
         let result = preprocess(a_path.to_str().unwrap());
-        assert!(result.len() > 0);
+        assert!(!result.is_empty());
     }
 }
 
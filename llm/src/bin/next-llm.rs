@@ -9,7 +9,7 @@ fn main() {
         eprintln!("Usage: {} <foo>", args[0]);
         std::process::exit(1);
     }
-    let foo = &args[1];
+    let name_stem = &args[1];
 
     let instruct_dir = Path::new("instruct/bin");
     let src_dir = Path::new("src/bin");
@@ -20,12 +20,12 @@ fn main() {
     if let Ok(entries) = fs::read_dir(instruct_dir) {
         for entry in entries.flatten() {
             let name = entry.file_name().into_string().unwrap_or_default();
-            if let Some(rest) = name.strip_prefix(&format!("llm-{}-", foo)) {
-                if let Some(num_str) = rest.strip_suffix(".md") {
-                    if let Ok(num) = num_str.parse::<u32>() {
-                        max_num = Some(max_num.map_or(num, |m| m.max(num)));
-                    }
-                }
+            let num = name
+                .strip_prefix(&format!("llm-{}-", name_stem))
+                .and_then(|rest| rest.strip_suffix(".md"))
+                .and_then(|num_str| num_str.parse::<u32>().ok());
+            if let Some(num) = num {
+                max_num = Some(max_num.map_or(num, |m| m.max(num)));
             }
         }
     }
@@ -34,12 +34,12 @@ fn main() {
     if let Ok(entries) = fs::read_dir(src_dir) {
         for entry in entries.flatten() {
             let name = entry.file_name().into_string().unwrap_or_default();
-            if let Some(rest) = name.strip_prefix(&format!("llm-{}-", foo)) {
-                if let Some(num_str) = rest.strip_suffix(".rs") {
-                    if let Ok(num) = num_str.parse::<u32>() {
-                        max_num = Some(max_num.map_or(num, |m| m.max(num)));
-                    }
-                }
+            let num = name
+                .strip_prefix(&format!("llm-{}-", name_stem))
+                .and_then(|rest| rest.strip_suffix(".rs"))
+                .and_then(|num_str| num_str.parse::<u32>().ok());
+            if let Some(num) = num {
+                max_num = Some(max_num.map_or(num, |m| m.max(num)));
             }
         }
     }
@@ -47,17 +47,17 @@ fn main() {
     let current_num = match max_num {
         Some(n) => n,
         None => {
-            eprintln!("No matching files found for llm-{}-<number>.md or llm-{}-<number>.rs", foo, foo);
+            eprintln!("No matching files found for llm-{}-<number>.md or llm-{}-<number>.rs", name_stem, name_stem);
             std::process::exit(1);
         }
     };
     let next_num = current_num + 1;
 
-    let md_src = instruct_dir.join(format!("llm-{}-{}.md", foo, current_num));
-    let md_dst = instruct_dir.join(format!("llm-{}-{}.md", foo, next_num));
+    let md_src = instruct_dir.join(format!("llm-{}-{}.md", name_stem, current_num));
+    let md_dst = instruct_dir.join(format!("llm-{}-{}.md", name_stem, next_num));
 
-    let rs_src = src_dir.join(format!("llm-{}-{}.rs", foo, current_num));
-    let rs_dst = src_dir.join(format!("llm-{}-{}.rs", foo, next_num));
+    let rs_src = src_dir.join(format!("llm-{}-{}.rs", name_stem, current_num));
+    let rs_dst = src_dir.join(format!("llm-{}-{}.rs", name_stem, next_num));
 
     if !md_src.exists() {
         eprintln!("Source file not found: {}", md_src.display());
@@ -92,7 +92,7 @@ fn main() {
         std::process::exit(1);
     }
 
-    let commit_msg = format!("first commit for llm-{}-{}", foo, next_num);
+    let commit_msg = format!("first commit for llm-{}-{}", name_stem, next_num);
     let output = Command::new("git")
         .arg("commit")
         .arg("-m")
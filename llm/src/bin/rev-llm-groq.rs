@@ -26,7 +26,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut input_content = String::new();
     File::open(input_file)?.read_to_string(&mut input_content)?;
 
-    let prompt: String;
     let output_exists = Path::new(output_file).exists();
     let output_empty = if output_exists {
         let metadata = fs::metadata(output_file)?;
@@ -35,21 +34,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         true
     };
 
-    if output_exists && !output_empty {
+    let prompt = if output_exists && !output_empty {
         let mut output_content = String::new();
         File::open(output_file)?.read_to_string(&mut output_content)?;
 
-        prompt = format!(
+        format!(
             "Please verify that the implementation below (enclosed into <result-specimen></result-specimen>) is accurately described by the specification (enclosed into <result-specification></result-specification>) as much as possible. If it does - then simply output the content of the result-specification verbatim. If you find that there are imperfections in how result-specification describes the specimen, then incrementally improve it and output the full result, with your improvements. Do not delimit the result with anything, output it verbatim.\n\n<result-specimen>\n{}\n</result-specimen>\n\n<result-specification>\n{}\n</result-specification>",
             input_content.trim(),
             output_content.trim()
-        );
+        )
     } else {
-        prompt = format!(
+        format!(
             "Please produce a detailed specification which will allow to recreate the implementation below from first principles:\n\n{}",
             input_content.trim()
-        );
-    }
+        )
+    };
 
     eprintln!("Calling Groq API for initial response...");
     let response = call_groq(&client, &api_key, &prompt, 0.7, 16384)?;
@@ -95,19 +95,17 @@ where
         F: FnMut(PathBuf, PathBuf),
     {
         if let Ok(entries) = fs::read_dir(dir) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.is_dir() {
-                        walk(&path, md_root, rs_root, callback);
-                    } else if path.extension().and_then(|s| s.to_str()) == Some("md") {
-                        let relative = path.strip_prefix(md_root).unwrap();
-                        let mut rs_path = rs_root.to_path_buf();
-                        rs_path.push(relative);
-                        rs_path.set_extension("rs");
-                        if rs_path.exists() {
-                            callback(path, rs_path);
-                        }
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    walk(&path, md_root, rs_root, callback);
+                } else if path.extension().and_then(|s| s.to_str()) == Some("md") {
+                    let relative = path.strip_prefix(md_root).unwrap();
+                    let mut rs_path = rs_root.to_path_buf();
+                    rs_path.push(relative);
+                    rs_path.set_extension("rs");
+                    if rs_path.exists() {
+                        callback(path, rs_path);
                     }
                 }
             }
@@ -125,19 +123,17 @@ where
         F: FnMut(PathBuf),
     {
         if let Ok(entries) = fs::read_dir(dir) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.is_dir() {
-                        walk(&path, rs_root, md_root, callback);
-                    } else if path.extension().and_then(|s| s.to_str()) == Some("rs") {
-                        let relative = path.strip_prefix(rs_root).unwrap();
-                        let mut md_path = md_root.to_path_buf();
-                        md_path.push(relative);
-                        md_path.set_extension("md");
-                        if !md_path.exists() {
-                            callback(path);
-                        }
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    walk(&path, rs_root, md_root, callback);
+                } else if path.extension().and_then(|s| s.to_str()) == Some("rs") {
+                    let relative = path.strip_prefix(rs_root).unwrap();
+                    let mut md_path = md_root.to_path_buf();
+                    md_path.push(relative);
+                    md_path.set_extension("md");
+                    if !md_path.exists() {
+                        callback(path);
                     }
                 }
             }